@@ -13,8 +13,24 @@ pub trait AsyncStore {
 
     async fn insert_batch(&self, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()>;
 
+    /// See [`crate::Store::compare_and_swap`].
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> io::Result<bool>;
+
+    /// See [`crate::Store::insert_if_absent`].
+    async fn insert_if_absent(&self, key: &str, value: &[u8]) -> io::Result<bool> {
+        self.compare_and_swap(key, None, Some(value)).await
+    }
+
     async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
 
+    /// See [`crate::Store::multi_get`].
+    async fn multi_get(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>>;
+
     fn get_range<R>(
         &self,
         range: R,
@@ -24,6 +24,13 @@ enum Message {
         resp: oneshot::Sender<io::Result<()>>,
     },
 
+    CompareAndSwap {
+        key: String,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+        resp: oneshot::Sender<io::Result<bool>>,
+    },
+
     Flush {
         resp: oneshot::Sender<io::Result<()>>,
     },
@@ -80,6 +87,18 @@ where S: store::Store + Sync + Send + 'static {
             }).await.unwrap();
         },
 
+        Message::CompareAndSwap {
+            key,
+            expected,
+            new,
+            resp,
+        } => {
+            tokio::task::spawn_blocking(move || {
+                let result = store.compare_and_swap(&key, expected.as_deref(), new.as_deref());
+                let _ = resp.send(result);
+            }).await.unwrap();
+        },
+
         Message::Flush {
             resp
         } => {
@@ -137,6 +156,24 @@ impl<S: store::Store + Send + Sync + 'static> AsyncStore for AsyncStoreImpl<S> {
         rx.await.unwrap()
     }
 
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> io::Result<bool> {
+        let (tx, rx) = oneshot::channel();
+
+        self.channel.send(Message::CompareAndSwap {
+            key: key.to_owned(),
+            expected: expected.map(<[u8]>::to_vec),
+            new: new.map(<[u8]>::to_vec),
+            resp: tx,
+        }).await.unwrap();
+
+        rx.await.unwrap()
+    }
+
     async fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
         let store = self.store.clone();
         let key = key.to_owned();
@@ -146,6 +183,16 @@ impl<S: store::Store + Send + Sync + 'static> AsyncStore for AsyncStoreImpl<S> {
         }).await.unwrap()
     }
 
+    async fn multi_get(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let store = self.store.clone();
+        let keys: Vec<String> = keys.iter().map(|it| it.to_string()).collect();
+
+        tokio::task::spawn_blocking(move || {
+            let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+            store.multi_get(&keys)
+        }).await.unwrap()
+    }
+
     fn get_range<R>(
         &self,
         range: R,
@@ -165,7 +212,7 @@ impl<S: store::Store + Send + Sync + 'static> AsyncStore for AsyncStoreImpl<S> {
             let cursor = match store.get_range(range) {
                 Ok(cursor) => cursor,
                 Err(e) => {
-                    let _ = tx.send(Err(e));
+                    let _ = tx.blocking_send(Err(e));
                     return;
                 }
             };
@@ -189,7 +236,7 @@ impl<S: store::Store + Send + Sync + 'static> AsyncStore for AsyncStoreImpl<S> {
     }
 
     async fn shutdown(self) {
-        if let Err(_) = self.channel.send(Message::Shutdown).await {
+        if self.channel.send(Message::Shutdown).await.is_err() {
             self.join_handle.abort();
         }
 
@@ -217,6 +264,9 @@ mod test {
 
         let value = store.get("hi").await.unwrap();
         assert_eq!(value, Some(b"hello".to_vec()));
+
+        let values = store.multi_get(&["missing", "hi"]).await.unwrap();
+        assert_eq!(values, vec![None, Some(b"hello".to_vec())]);
     }
 
     #[tokio::test]
@@ -253,5 +303,21 @@ mod test {
 
         assert_eq!(actual, expected);
     }
-}
 
+    #[tokio::test]
+    async fn test_compare_and_swap_async() {
+        let path: path::PathBuf = "test_compare_and_swap_async".into();
+        if path.exists() {
+            std::fs::remove_dir_all(path.clone()).unwrap();
+        }
+
+        let store = make_store(path).unwrap().to_async();
+
+        assert!(store.insert_if_absent("lease", b"owner1").await.unwrap());
+        assert!(!store.insert_if_absent("lease", b"owner2").await.unwrap());
+        assert!(!store.compare_and_swap("lease", Some(b"owner2"), None).await.unwrap());
+        assert!(store.compare_and_swap("lease", Some(b"owner1"), None).await.unwrap());
+
+        assert_eq!(store.get("lease").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,764 @@
+//! Incremental backups of stores, built from checkpoints.
+//!
+//! Backup directory layout and metadata file format are specified in
+//! [docs/backup-spec.md](docs/backup-spec.md).
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use fs2::FileExt;
+
+use crate::blob::parse_blob_filename;
+use crate::crc::{Crc32c, crc32c};
+use crate::io_ext::{ReadExt, WriteExt};
+use crate::sstable::parse_sst_filename;
+use crate::store::Store;
+use crate::wal::{self, Wal, WalRecord, read_archive};
+
+const MAGIC: u32 = 0xBAC0FFEE;
+const VERSION: u8 = 1;
+
+const LOCK_FILENAME: &str = "LOCK";
+
+/// Holds SST and blob files, shared by every backup that contains them.
+const SHARED_DIRECTORY: &str = "shared";
+
+/// Holds a directory per backup with its manifest and WAL.
+const PRIVATE_DIRECTORY: &str = "private";
+
+/// Holds the metadata file of every backup.
+const META_DIRECTORY: &str = "meta";
+
+/// Checkpoint of the store being backed up.
+const TMP_DIRECTORY: &str = "tmp";
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Summary of a backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: u64,
+
+    /// Seconds since the UNIX epoch.
+    pub created_at: u64,
+
+    /// Total size of the files of the backup, including the ones shared with other backups.
+    pub size: u64,
+    pub file_count: usize,
+}
+
+/// Point up to which [`BackupEngine::restore_backup_until`] replays archived WAL records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Replay records up to and including the one with this sequence number.
+    Sequence(u64),
+
+    /// Replay records logged at or before this time.
+    Time(SystemTime),
+}
+
+impl RecoveryTarget {
+    fn includes(&self, record: &WalRecord) -> bool {
+        match self {
+            Self::Sequence(sequence) => record.sequence <= *sequence,
+            Self::Time(time) => {
+                let millis = time.duration_since(UNIX_EPOCH).map_or(0, |it| it.as_millis());
+                u128::from(record.timestamp) <= millis
+            }
+        }
+    }
+}
+
+/// A file of a backup, as recorded in its metadata file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BackupFile {
+    /// Path relative to the backup directory.
+    path: String,
+    size: u64,
+    crc: u32,
+}
+
+struct BackupMeta {
+    created_at: u64,
+    files: Vec<BackupFile>,
+}
+
+impl BackupMeta {
+    fn info(&self, id: u64) -> BackupInfo {
+        BackupInfo {
+            id,
+            created_at: self.created_at,
+            size: self.files.iter().map(|it| it.size).sum(),
+            file_count: self.files.len(),
+        }
+    }
+}
+
+/// Keeps backups of stores in a directory.
+///
+/// SSTs and blob files are never modified once written and are identified by their ID, so each
+/// backup only copies the ones that no earlier backup in the directory holds already. Everything
+/// else a backup needs is copied for every backup.
+///
+/// The directory is locked while the engine is open. Backups of different stores can't share a
+/// directory, since their SST IDs overlap.
+pub struct BackupEngine {
+    directory: PathBuf,
+    _lock_file: File,
+}
+
+impl BackupEngine {
+    /// Opens the backup directory, creating it if it doesn't exist.
+    ///
+    /// Files left behind by a backup that was interrupted are removed.
+    pub fn open(directory: impl AsRef<Path>) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+
+        for subdirectory in [SHARED_DIRECTORY, PRIVATE_DIRECTORY, META_DIRECTORY] {
+            fs::create_dir_all(directory.join(subdirectory))?;
+        }
+
+        let _lock_file = File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(directory.join(LOCK_FILENAME))?;
+
+        _lock_file.try_lock_exclusive()?;
+
+        let engine = Self {
+            directory,
+            _lock_file,
+        };
+
+        engine.remove_unreferenced_files()?;
+
+        Ok(engine)
+    }
+
+    /// Backs up `store` through a checkpoint, see [`Store::checkpoint`], and returns the summary
+    /// of the new backup.
+    pub fn create_backup<S: Store>(&self, store: &S) -> io::Result<BackupInfo> {
+        let backups = self.read_backups()?;
+        let id = backups.keys().next_back().map_or(1, |it| it + 1);
+
+        let tmp = self.directory.join(TMP_DIRECTORY);
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)?;
+        }
+
+        store.checkpoint(&tmp)?;
+
+        // Shared files already backed up, along with their size and checksum.
+        let shared: BTreeMap<String, &BackupFile> = backups
+            .values()
+            .flat_map(|it| it.files.iter())
+            .map(|it| (it.path.clone(), it))
+            .collect();
+
+        let private = Path::new(PRIVATE_DIRECTORY).join(backup_name(id));
+        fs::create_dir_all(self.directory.join(&private))?;
+
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(&tmp)? {
+            let entry = entry?;
+
+            let filename = entry.file_name();
+            let filename = filename.to_str().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected file {filename:?}"))
+            })?;
+
+            let is_shared = parse_sst_filename(filename).is_some()
+                || parse_blob_filename(filename).is_some();
+
+            let path = match is_shared {
+                true => Path::new(SHARED_DIRECTORY).join(filename),
+                false => private.join(filename),
+            };
+
+            // SAFETY: the path is built from valid UTF-8
+            let path = path.to_str().unwrap().to_owned();
+
+            if let Some(existing) = shared.get(&path) {
+                if checksum_file(&entry.path())? != (existing.size, existing.crc) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{path} of an earlier backup differs, the backups are of another store"),
+                    ));
+                }
+
+                // A damaged copy would otherwise be passed on to this backup too. Copying it again
+                // repairs it for the earlier backups as well.
+                let backed_up = checksum_file(&self.directory.join(&path)).ok();
+                if backed_up != Some((existing.size, existing.crc)) {
+                    copy_file(&entry.path(), &self.directory.join(&path))?;
+                }
+
+                files.push((*existing).clone());
+                continue;
+            }
+
+            let (size, crc) = copy_file(&entry.path(), &self.directory.join(&path))?;
+            files.push(BackupFile { path, size, crc });
+        }
+
+        fs::remove_dir_all(&tmp)?;
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let meta = BackupMeta {
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            files,
+        };
+
+        // The backup exists once its metadata file does.
+        self.write_meta(id, &meta)?;
+
+        Ok(meta.info(id))
+    }
+
+    /// Returns the summaries of all backups, oldest first.
+    pub fn list_backups(&self) -> io::Result<Vec<BackupInfo>> {
+        Ok(self
+            .read_backups()?
+            .iter()
+            .map(|(id, meta)| meta.info(*id))
+            .collect())
+    }
+
+    /// Checks that every file of the backup is present with the size and checksum it was backed
+    /// up with.
+    pub fn verify_backup(&self, id: u64) -> io::Result<()> {
+        let meta = self.read_meta(id)?;
+
+        for file in meta.files.iter() {
+            let (size, crc) = checksum_file(&self.directory.join(&file.path))?;
+
+            if size != file.size || crc != file.crc {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} of backup {id} is corrupted", file.path),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes all but the `keep` newest backups, along with the files no remaining backup needs.
+    /// The WAL archive of the store is purged separately, see [`BackupEngine::purge_wal_archive`].
+    pub fn purge_old_backups(&self, keep: usize) -> io::Result<()> {
+        let backups = self.read_backups()?;
+        let purge_count = backups.len().saturating_sub(keep);
+
+        for id in backups.keys().take(purge_count) {
+            fs::remove_file(self.meta_path(*id))?;
+        }
+
+        self.remove_unreferenced_files()
+    }
+
+    /// Removes the files of the WAL archive `wal_archive` that only hold records the oldest backup
+    /// holds already, which no restore replays. Since the store keeps adding to its archive, call
+    /// this after [`BackupEngine::purge_old_backups`] to keep the archive from growing without
+    /// bound. Does nothing if there are no backups.
+    pub fn purge_wal_archive(&self, wal_archive: impl AsRef<Path>) -> io::Result<()> {
+        let backups = self.read_backups()?;
+
+        let Some((id, oldest)) = backups.first_key_value() else {
+            return Ok(());
+        };
+
+        let Some(file) = oldest.files.iter().find(|it| it.path.ends_with(wal::FILENAME)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("backup {id} has no WAL"),
+            ));
+        };
+
+        let (truncated_sequence, records) = wal::read_valid_records(&self.directory.join(&file.path))?;
+        let sequence = records.last().map_or(truncated_sequence, |it| it.sequence);
+
+        wal::purge_archive(wal_archive.as_ref(), sequence)?;
+
+        Ok(())
+    }
+
+    /// Restores the backup into `dest`, which must be empty or not exist. The restored store can
+    /// be opened like any other store. Files are checked against their checksums as they are
+    /// copied, and `dest` is removed again if the backup can't be restored.
+    pub fn restore_backup(&self, id: u64, dest: impl AsRef<Path>) -> io::Result<()> {
+        let dest = dest.as_ref();
+        let meta = self.read_meta(id)?;
+
+        if dest.exists() && fs::read_dir(dest)?.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("restore directory {dest:?} is not empty"),
+            ));
+        }
+
+        fs::create_dir_all(dest)?;
+
+        let result = meta.files.iter().try_for_each(|file| {
+            // SAFETY: every backup file is in a subdirectory of the backup directory
+            let filename = Path::new(&file.path).file_name().unwrap();
+            let (size, crc) = copy_file(&self.directory.join(&file.path), &dest.join(filename))?;
+
+            if size != file.size || crc != file.crc {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} of backup {id} is corrupted", file.path),
+                ));
+            }
+
+            Ok(())
+        });
+
+        if result.is_err() && let Err(e) = fs::remove_dir_all(dest) {
+            eprintln!("Error removing incomplete restore: {e}");
+        }
+
+        result
+    }
+
+    /// Restores the backup into `dest` like [`BackupEngine::restore_backup`], then replays the
+    /// WAL records archived in `wal_archive` that follow the backup, up to `target`. See
+    /// [`crate::Options::wal_archive_dir`]. Records the store hasn't archived yet can be replayed
+    /// as well by copying its `wal.log` into the archive directory.
+    ///
+    /// Fails if the backup is past the target already, or if records between the backup and the
+    /// target are missing from the archive.
+    pub fn restore_backup_until(
+        &self,
+        id: u64,
+        dest: impl AsRef<Path>,
+        wal_archive: impl AsRef<Path>,
+        target: RecoveryTarget,
+    ) -> io::Result<()> {
+        let dest = dest.as_ref();
+
+        if let RecoveryTarget::Time(time) = target {
+            let created_at = self.read_meta(id)?.created_at;
+            let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |it| it.as_secs());
+
+            if seconds < created_at {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("backup {id} was created at {created_at}, past the target time {seconds}"),
+                ));
+            }
+        }
+
+        let records = read_archive(wal_archive.as_ref())?;
+
+        self.restore_backup(id, dest)?;
+
+        let result = replay_records(dest, records, target);
+
+        if result.is_err() && let Err(e) = fs::remove_dir_all(dest) {
+            eprintln!("Error removing incomplete restore: {e}");
+        }
+
+        result
+    }
+
+    /// Removes shared files and private directories that no backup refers to, and the checkpoint
+    /// of an interrupted backup.
+    fn remove_unreferenced_files(&self) -> io::Result<()> {
+        let backups = self.read_backups()?;
+
+        let referenced: HashSet<&str> = backups
+            .values()
+            .flat_map(|it| it.files.iter())
+            .map(|it| it.path.as_str())
+            .collect();
+
+        for entry in fs::read_dir(self.directory.join(SHARED_DIRECTORY))? {
+            let entry = entry?;
+            let path = Path::new(SHARED_DIRECTORY).join(entry.file_name());
+
+            if !path.to_str().is_some_and(|it| referenced.contains(it))
+                && let Err(e) = fs::remove_file(entry.path()) {
+                eprintln!("Error removing unreferenced backup file: {e}");
+            }
+        }
+
+        for entry in fs::read_dir(self.directory.join(PRIVATE_DIRECTORY))? {
+            let entry = entry?;
+
+            let is_backup = entry
+                .file_name()
+                .to_str()
+                .and_then(|it| it.parse::<u64>().ok())
+                .is_some_and(|it| backups.contains_key(&it));
+
+            if !is_backup && let Err(e) = fs::remove_dir_all(entry.path()) {
+                eprintln!("Error removing unreferenced backup directory: {e}");
+            }
+        }
+
+        let tmp = self.directory.join(TMP_DIRECTORY);
+        if tmp.exists() && let Err(e) = fs::remove_dir_all(&tmp) {
+            eprintln!("Error removing interrupted backup: {e}");
+        }
+
+        Ok(())
+    }
+
+    fn meta_path(&self, id: u64) -> PathBuf {
+        self.directory.join(META_DIRECTORY).join(backup_name(id))
+    }
+
+    fn read_backups(&self) -> io::Result<BTreeMap<u64, BackupMeta>> {
+        let mut backups = BTreeMap::new();
+
+        for entry in fs::read_dir(self.directory.join(META_DIRECTORY))? {
+            let entry = entry?;
+
+            let Some(id) = entry.file_name().to_str().and_then(|it| it.parse().ok()) else {
+                continue;
+            };
+
+            backups.insert(id, self.read_meta(id)?);
+        }
+
+        Ok(backups)
+    }
+
+    fn read_meta(&self, id: u64) -> io::Result<BackupMeta> {
+        let mut file = match File::open(self.meta_path(id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("backup {id} doesn't exist")));
+            }
+            Err(e) => return Err(e),
+        };
+
+        if file.read_u32()? != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid backup metadata magic number"));
+        }
+
+        let version = file.read_u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported backup metadata version: {version}"),
+            ));
+        }
+
+        let crc = file.read_u32()?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        if crc != crc32c(&buf) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Metadata of backup {id} is corrupted"),
+            ));
+        }
+
+        let mut reader = Cursor::new(buf);
+        let created_at = reader.read_u64()?;
+
+        let files = (0..reader.read_u64()?)
+            .map(|_| {
+                Ok(BackupFile {
+                    path: reader.read_string()?,
+                    size: reader.read_u64()?,
+                    crc: reader.read_u32()?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(BackupMeta { created_at, files })
+    }
+
+    fn write_meta(&self, id: u64, meta: &BackupMeta) -> io::Result<()> {
+        let mut buf = Vec::new();
+        buf.write_u64(meta.created_at)?;
+        buf.write_u64(meta.files.len() as u64)?;
+
+        for file in meta.files.iter() {
+            buf.write_string(&file.path)?;
+            buf.write_u64(file.size)?;
+            buf.write_u32(file.crc)?;
+        }
+
+        let path = self.meta_path(id);
+        let tmp_path = path.with_extension("tmp");
+
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_u32(MAGIC)?;
+        tmp.write_u8(VERSION)?;
+        tmp.write_u32(crc32c(&buf))?;
+        tmp.write_all(&buf)?;
+        tmp.sync_all()?;
+        drop(tmp);
+
+        fs::rename(&tmp_path, &path)?;
+
+        #[cfg(unix)]
+        File::open(self.directory.join(META_DIRECTORY))?.sync_all()?;
+
+        Ok(())
+    }
+}
+
+/// Appends the records following the last one in the WAL of the store in `directory` to it, up
+/// to `target`. They are applied when the store is opened.
+fn replay_records(directory: &Path, records: Vec<WalRecord>, target: RecoveryTarget) -> io::Result<()> {
+    let mut wal = Wal::new(directory, None)?;
+    wal.records()?.for_each(drop);
+
+    let backup_sequence = wal.last_sequence();
+    let mut last_sequence = backup_sequence;
+
+    if let RecoveryTarget::Sequence(sequence) = target && sequence < backup_sequence {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("backup already holds records up to {backup_sequence}, past the target {sequence}"),
+        ));
+    }
+
+    for record in records {
+        if record.sequence <= backup_sequence {
+            continue;
+        }
+
+        if !target.includes(&record) {
+            break;
+        }
+
+        if record.sequence != last_sequence + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("WAL archive is missing records following record {last_sequence}"),
+            ));
+        }
+
+        wal.log_record(&record)?;
+        last_sequence = record.sequence;
+    }
+
+    if let RecoveryTarget::Sequence(sequence) = target && last_sequence < sequence {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("WAL archive ends at record {last_sequence}, before the target {sequence}"),
+        ));
+    }
+
+    wal.sync()
+}
+
+fn backup_name(id: u64) -> String {
+    format!("{id:016}")
+}
+
+/// Returns the size and the CRC32C of the file.
+fn checksum_file(path: &Path) -> io::Result<(u64, u32)> {
+    copy_into(path, io::sink())
+}
+
+/// Copies the file and syncs the copy, returning the size and the CRC32C of the file.
+fn copy_file(from: &Path, to: &Path) -> io::Result<(u64, u32)> {
+    let mut file = File::create(to)?;
+    let result = copy_into(from, &mut file)?;
+    file.sync_all()?;
+
+    Ok(result)
+}
+
+fn copy_into<W: Write>(from: &Path, mut to: W) -> io::Result<(u64, u32)> {
+    let mut file = File::open(from)?;
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+
+    let mut size = 0;
+    let mut crc = Crc32c::default();
+
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        crc.update(&buf[..read]);
+        to.write_all(&buf[..read])?;
+        size += read as u64;
+    }
+
+    Ok((size, crc.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::options::Options;
+    use crate::store_impl::{make_store, make_store_with_options};
+
+    #[test]
+    fn test_backups_share_sstables_and_can_be_restored() {
+        let dir = PathBuf::from("test_backups_share_sstables_and_can_be_restored");
+        let backup_dir = PathBuf::from("test_backups_share_sstables_and_can_be_restored_backups");
+        let dest = PathBuf::from("test_backups_share_sstables_and_can_be_restored_restore");
+        for path in [&dir, &backup_dir, &dest] {
+            let _ = fs::remove_dir_all(path);
+        }
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        let engine = BackupEngine::open(&backup_dir).unwrap();
+
+        store.insert("first", b"1").unwrap();
+        store.flush().unwrap();
+        let first = engine.create_backup(&store).unwrap();
+
+        store.insert("second", b"2").unwrap();
+        store.flush().unwrap();
+        store.insert("unflushed", b"3").unwrap();
+        let second = engine.create_backup(&store).unwrap();
+
+        assert_eq!(engine.list_backups().unwrap(), vec![first.clone(), second.clone()]);
+
+        // The SST of the first backup is not copied again.
+        let shared = fs::read_dir(backup_dir.join(SHARED_DIRECTORY)).unwrap().count();
+        assert_eq!(shared, 2);
+
+        engine.verify_backup(first.id).unwrap();
+        engine.verify_backup(second.id).unwrap();
+
+        engine.purge_old_backups(1).unwrap();
+        assert_eq!(engine.list_backups().unwrap(), vec![second.clone()]);
+        assert!(!backup_dir.join(PRIVATE_DIRECTORY).join(backup_name(first.id)).exists());
+
+        let err = engine.verify_backup(first.id).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        engine.restore_backup(second.id, &dest).unwrap();
+
+        let err = engine.restore_backup(second.id, &dest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        let restored = make_store(dest.clone()).unwrap();
+        assert_eq!(restored.get("first").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(restored.get("second").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(restored.get("unflushed").unwrap(), Some(b"3".to_vec()));
+        drop(restored);
+
+        // Corrupt a shared file, which verification and restoring catch.
+        let meta = engine.read_meta(second.id).unwrap();
+        let sst = meta.files.iter().find(|it| it.path.starts_with(SHARED_DIRECTORY)).unwrap();
+        let mut data = fs::read(backup_dir.join(&sst.path)).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        fs::write(backup_dir.join(&sst.path), data).unwrap();
+
+        let err = engine.verify_backup(second.id).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&dest).unwrap();
+        assert!(engine.restore_backup(second.id, &dest).is_err());
+        assert!(!dest.exists());
+
+        // The next backup copies the damaged file again rather than reusing it.
+        let third = engine.create_backup(&store).unwrap();
+        engine.verify_backup(third.id).unwrap();
+        engine.verify_backup(second.id).unwrap();
+
+        drop(store);
+        drop(engine);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&backup_dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_replays_archived_wal_records_up_to_target() {
+        let dir = PathBuf::from("test_restore_replays_archived_wal_records_up_to_target");
+        let archive = PathBuf::from("test_restore_replays_archived_wal_records_up_to_target_archive");
+        let backup_dir = PathBuf::from("test_restore_replays_archived_wal_records_up_to_target_backups");
+        let dest = PathBuf::from("test_restore_replays_archived_wal_records_up_to_target_restore");
+        for path in [&dir, &archive, &backup_dir, &dest] {
+            let _ = fs::remove_dir_all(path);
+        }
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            wal_archive_dir: Some(archive.clone()),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+        let engine = BackupEngine::open(&backup_dir).unwrap();
+
+        // Records 1 and 2, the second of which is only in the memtable when backed up.
+        store.insert("first", b"1").unwrap();
+        store.flush().unwrap();
+        store.insert("second", b"2").unwrap();
+        let backup = engine.create_backup(&store).unwrap();
+
+        // Records 3 and 4, of which only the first gets archived by a flush.
+        store.insert("third", b"3").unwrap();
+        store.flush().unwrap();
+        store.insert("fourth", b"4").unwrap();
+
+        engine
+            .restore_backup_until(backup.id, &dest, &archive, RecoveryTarget::Sequence(2))
+            .unwrap();
+
+        let restored = make_store(dest.clone()).unwrap();
+        assert_eq!(restored.get("second").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(restored.get("third").unwrap(), None);
+        drop(restored);
+        fs::remove_dir_all(&dest).unwrap();
+
+        // The backup is past a time before it was created.
+        let before = RecoveryTarget::Time(SystemTime::now() - std::time::Duration::from_secs(60));
+        let err = engine.restore_backup_until(backup.id, &dest, &archive, before).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!dest.exists());
+
+        // The last record is not archived yet.
+        let err = engine
+            .restore_backup_until(backup.id, &dest, &archive, RecoveryTarget::Sequence(4))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!dest.exists());
+
+        fs::copy(dir.join("wal.log"), archive.join("current.log")).unwrap();
+
+        // Only the file holding nothing but record 1, which precedes the backup, is purged.
+        assert_eq!(fs::read_dir(&archive).unwrap().count(), 3);
+        engine.purge_wal_archive(&archive).unwrap();
+        assert_eq!(fs::read_dir(&archive).unwrap().count(), 2);
+
+        let target = RecoveryTarget::Time(SystemTime::now());
+        engine.restore_backup_until(backup.id, &dest, &archive, target).unwrap();
+
+        let restored = make_store(dest.clone()).unwrap();
+        assert_eq!(restored.get("first").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(restored.get("third").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(restored.get("fourth").unwrap(), Some(b"4".to_vec()));
+
+        drop(restored);
+        drop(store);
+        drop(engine);
+        for path in [&dir, &archive, &backup_dir, &dest] {
+            fs::remove_dir_all(path).unwrap();
+        }
+    }
+}
@@ -1,21 +1,84 @@
-use std::io::{self, Write, stdin};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write, stdin};
 use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::ops::Bound;
 
-use sand_db::{Store, make_store};
+use sand_db::{
+    MAX_LEVEL, Options, Store, export_dump, import_dump, make_store, read_dump_header, verify,
+};
+
+type KeyRange<'a> = (Bound<&'a str>, Bound<&'a str>);
+
+/// Narrows `range` with a `-gt`, `-lt`, `-lte` or `-gte` filter.
+///
+/// Returns false if `op` is not one of those filters.
+fn apply_range_filter<'a>(range: &mut KeyRange<'a>, op: &str, operand: &'a str) -> bool {
+    fn w_conflict() {
+        eprintln!(
+            "warning: conflicting filters; using values specified last."
+        );
+    }
+
+    match op {
+        "-lt" => {
+            if range.1 != Bound::Unbounded {
+                w_conflict();
+            }
+            range.1 = Bound::Excluded(operand);
+        },
+        "-lte" => {
+            if range.1 != Bound::Unbounded {
+                w_conflict();
+            }
+            range.1 = Bound::Included(operand);
+        },
+        "-gt" => {
+            if range.0 != Bound::Unbounded {
+                w_conflict();
+            }
+            range.0 = Bound::Excluded(operand);
+        },
+        "-gte" => {
+            if range.0 != Bound::Unbounded {
+                w_conflict();
+            }
+            range.0 = Bound::Included(operand);
+        },
+
+        _ => return false,
+    }
+
+    true
+}
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
+    // Checked without opening the store, which would replay the WAL and remove orphaned files.
+    if args.len() == 3 && args[1] == "verify" {
+        match verify(&args[2]) {
+            Ok(report) => {
+                println!("{report}");
+                std::process::exit(if report.is_ok() { 0 } else { 2 });
+            }
+
+            Err(e) => {
+                eprintln!("Failed to verify store: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
     if args.len() != 2 {
         eprintln!("Usage: {} <directory>", args[0]);
+        eprintln!("       {} verify <directory>", args[0]);
         std::process::exit(1);
     }
 
     let directory = PathBuf::from(&args[1]);
 
-    let store = match make_store(directory) {
+    let store = match make_store(directory.clone()) {
         Ok(store) => store,
 
         Err(e) => {
@@ -72,8 +135,7 @@ fn main() -> io::Result<()> {
 
                 if let Some(value) = value {
                     let escaped: Vec<_> = value.iter()
-                        .map(|it| std::ascii::escape_default(*it))
-                        .flatten()
+                        .flat_map(|it| std::ascii::escape_default(*it))
                         .collect();
                     eprintln!("{}", String::from_utf8_lossy(&escaped));
                 } else {
@@ -97,12 +159,6 @@ fn main() -> io::Result<()> {
             }
 
             "list" => {
-                fn w_conflict() {
-                    eprintln!(
-                        "warning: conflicting filters; using values specified last."
-                    );
-                }
-
                 fn usage() {
                     eprintln!("usage: list [(-gt|-lt|-lte|-gte) <value>]...", );
                 }
@@ -119,36 +175,9 @@ fn main() -> io::Result<()> {
                         }
                     };
 
-                    match op {
-                        "-lt" => {
-                            if range.1 != Bound::Unbounded {
-                                w_conflict();
-                            }
-                            range.1 = Bound::Excluded(operand);
-                        },
-                        "-lte" => {
-                            if range.1 != Bound::Unbounded {
-                                w_conflict();
-                            }
-                            range.1 = Bound::Included(operand);
-                        },
-                        "-gt" => {
-                            if range.0 != Bound::Unbounded {
-                                w_conflict();
-                            }
-                            range.0 = Bound::Excluded(operand);
-                        },
-                        "-gte" => {
-                            if range.0 != Bound::Unbounded {
-                                w_conflict();
-                            }
-                            range.0 = Bound::Included(operand);
-                        },
-
-                        _ => {
-                            usage();
-                            continue 'outer;
-                        }
+                    if !apply_range_filter(&mut range, op, operand) {
+                        usage();
+                        continue 'outer;
                     }
                 }
 
@@ -157,8 +186,7 @@ fn main() -> io::Result<()> {
                         for item in iter {
                             let (key, value) = item?;
                             let escaped: Vec<u8> = value.iter()
-                                .map(|it| std::ascii::escape_default(*it))
-                                .flatten()
+                                .flat_map(|it| std::ascii::escape_default(*it))
                                 .collect();
                             let value = String::from_utf8_lossy(&escaped);
 
@@ -170,6 +198,115 @@ fn main() -> io::Result<()> {
                 }
             }
 
+            "compact" => {
+                fn usage() {
+                    eprintln!("usage: compact [(-gt|-lt|-lte|-gte) <value>]... [-level <level>]");
+                }
+
+                let mut args = parts.into_iter().skip(1);
+                let mut range = (Bound::Unbounded, Bound::Unbounded);
+                let mut level = MAX_LEVEL;
+
+                while let Some(op) = args.next() {
+                    let operand = match args.next() {
+                        Some(x) => x,
+                        None => {
+                            usage();
+                            continue 'outer;
+                        }
+                    };
+
+                    if op == "-level" {
+                        level = match operand.parse() {
+                            Ok(level) if level <= MAX_LEVEL => level,
+                            _ => {
+                                eprintln!("Level must be a number between 0 and {MAX_LEVEL}");
+                                continue 'outer;
+                            }
+                        };
+                    } else if !apply_range_filter(&mut range, op, operand) {
+                        usage();
+                        continue 'outer;
+                    }
+                }
+
+                match store.compact_range(range, level) {
+                    Ok(_) => eprintln!("Compacted"),
+                    Err(e) => eprintln!("Failed to compact: {e}"),
+                }
+            }
+
+            "export" => {
+                fn usage() {
+                    eprintln!("usage: export <file> [(-gt|-lt|-lte|-gte) <value>]...");
+                }
+
+                if parts.len() < 2 {
+                    usage();
+                    continue;
+                }
+
+                let path = parts[1];
+                let mut args = parts.into_iter().skip(2);
+                let mut range = (Bound::Unbounded, Bound::Unbounded);
+
+                while let Some(op) = args.next() {
+                    let operand = match args.next() {
+                        Some(x) => x,
+                        None => {
+                            usage();
+                            continue 'outer;
+                        }
+                    };
+
+                    if !apply_range_filter(&mut range, op, operand) {
+                        usage();
+                        continue 'outer;
+                    }
+                }
+
+                let result = File::create(path).and_then(|file| {
+                    export_dump(&store, &Options::default(), range, BufWriter::new(file), |count| {
+                        eprint!("\rExported {count} entries");
+                    })
+                });
+
+                match result {
+                    Ok(count) => eprintln!("\rExported {count} entries to {path}"),
+                    Err(e) => eprintln!("\nFailed to export: {e}"),
+                }
+            }
+
+            "import" => {
+                if parts.len() != 2 {
+                    eprintln!("Usage: import <file>");
+                    continue;
+                }
+
+                let path = parts[1];
+
+                let header = File::open(path).and_then(|file| read_dump_header(&mut BufReader::new(file)));
+                match header {
+                    Ok(header) => eprintln!("Importing dump sorted by {}", header.comparator),
+                    Err(e) => {
+                        eprintln!("Failed to read dump: {e}");
+                        continue;
+                    }
+                }
+
+                let scratch = directory.join("import.tmp");
+                let result = File::open(path).and_then(|file| {
+                    import_dump(&store, &Options::default(), BufReader::new(file), &scratch, |count| {
+                        eprint!("\rRead {count} entries");
+                    })
+                });
+
+                match result {
+                    Ok(count) => eprintln!("\rImported {count} entries"),
+                    Err(e) => eprintln!("\nFailed to import: {e}"),
+                }
+            }
+
             cmd => {
                 eprintln!("Unknown command: {cmd}");
             }
@@ -0,0 +1,45 @@
+use std::io;
+
+use sand_db::{Inspection, inspect_manifest, inspect_sst, inspect_wal};
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {program} [--json] wal <file>");
+    eprintln!("       {program} [--json] manifest <file|directory>");
+    eprintln!("       {program} [--json] sst <file>");
+    std::process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let program = args.remove(0);
+
+    let json = args.first().is_some_and(|it| it == "--json");
+    if json {
+        args.remove(0);
+    }
+
+    if args.len() != 2 {
+        usage(&program);
+    }
+
+    let path = &args[1];
+
+    let inspection: io::Result<Inspection> = match args[0].as_str() {
+        "wal" => inspect_wal(path),
+        "manifest" => inspect_manifest(path),
+        "sst" => inspect_sst(path),
+        _ => usage(&program),
+    };
+
+    match inspection {
+        Ok(inspection) if json => println!("{}", inspection.to_json()),
+        Ok(inspection) => print!("{inspection}"),
+
+        Err(e) => {
+            eprintln!("Failed to read {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+//! Blob files holding values that are too large to be kept in SSTs.
+//!
+//! SSTs store a [`BlobRef`] in place of such values, so compactions move the reference around
+//! instead of rewriting the value. Blob file format is specified in
+//! [docs/blob-file-spec.md](docs/blob-file-spec.md).
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::crc::crc32c;
+use crate::entry::Entry;
+use crate::io_ext::{ReadExt, WriteExt};
+
+const MAGIC: u32 = 0xB10BF11E;
+const VERSION: u8 = 1;
+
+const HEADER_SIZE: u64 = 5;
+
+const BLOB_FILENAME_PREFIX: &str = "blob_";
+const BLOB_FILENAME_EXTENSION: &str = ".blob";
+
+pub(crate) fn blob_file_path(directory: &Path, id: u64) -> PathBuf {
+    directory.join(format!("{BLOB_FILENAME_PREFIX}{id:016}{BLOB_FILENAME_EXTENSION}"))
+}
+
+/// Returns the ID of the blob file if the given file name is that of a blob file.
+pub(crate) fn parse_blob_filename(filename: &str) -> Option<u64> {
+    filename
+        .strip_prefix(BLOB_FILENAME_PREFIX)?
+        .strip_suffix(BLOB_FILENAME_EXTENSION)?
+        .parse()
+        .ok()
+}
+
+/// Summarizes the blob file at `path` from its records, up to the first torn or corrupted one.
+/// Garbage is not known from the file alone and left at 0.
+pub(crate) fn read_blob_file_desc(path: &Path, id: u64) -> io::Result<BlobFileDesc> {
+    let mut file = io::BufReader::new(File::open(path)?);
+
+    if file.read_u32()? != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid blob file magic number"));
+    }
+
+    let version = file.read_u8()?;
+    if !(1..=VERSION).contains(&version) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported blob file version: {version}"),
+        ));
+    }
+
+    let mut desc = BlobFileDesc {
+        id,
+        ..Default::default()
+    };
+
+    let mut remaining = file.get_ref().metadata()?.len().saturating_sub(HEADER_SIZE);
+
+    while remaining >= 12 {
+        let crc = file.read_u32()?;
+        let length = file.read_u64()?;
+
+        if length > remaining - 12 {
+            break;
+        }
+
+        let buf = file.read_bytes_with_len(length as usize)?;
+        if crc != crc32c(&buf) {
+            break;
+        }
+
+        let mut record = Cursor::new(buf);
+        let _key = record.read_string()?;
+        let value = record.read_bytes()?;
+
+        desc.value_count += 1;
+        desc.value_bytes += value.len() as u64;
+        remaining -= 12 + length;
+    }
+
+    Ok(desc)
+}
+
+/// Location of a value in a blob file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobRef {
+    pub file_id: u64,
+
+    /// Offset of the record holding the value.
+    pub offset: u64,
+
+    /// Length of the value in bytes.
+    pub size: u64,
+}
+
+/// Summary of a blob file, recorded in the manifest along with the SSTs referring to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlobFileDesc {
+    pub id: u64,
+    pub value_count: u64,
+    pub value_bytes: u64,
+
+    /// Values no longer referred to by any SST, as counted by compactions dropping references.
+    pub garbage_count: u64,
+    pub garbage_bytes: u64,
+}
+
+impl BlobFileDesc {
+    /// Share of the bytes of the file taken up by values that are no longer referred to.
+    pub fn garbage_ratio(&self) -> f64 {
+        if self.value_bytes == 0 {
+            return 1.0;
+        }
+
+        self.garbage_bytes as f64 / self.value_bytes as f64
+    }
+
+    /// Returns true if none of the values of the file are referred to anymore.
+    pub fn is_drained(&self) -> bool {
+        self.garbage_count >= self.value_count
+    }
+}
+
+/// Writer for blob files. Values are appended as they are added and the file is synced by
+/// [`BlobWriter::finalize`].
+pub struct BlobWriter {
+    file: BufWriter<File>,
+    id: u64,
+    offset: u64,
+    value_count: u64,
+    value_bytes: u64,
+}
+
+impl BlobWriter {
+    pub fn open(directory: &Path, id: u64) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(blob_file_path(directory, id))?);
+
+        file.write_u32(MAGIC)?;
+        file.write_u8(VERSION)?;
+
+        Ok(Self {
+            file,
+            id,
+            offset: HEADER_SIZE,
+            value_count: 0,
+            value_bytes: 0,
+        })
+    }
+
+    /// Appends the value of `key` to the file and returns where it was written.
+    pub fn write(&mut self, key: &str, value: &[u8]) -> io::Result<BlobRef> {
+        let mut buf = Vec::with_capacity(16 + key.len() + value.len());
+        buf.write_string(key)?;
+        buf.write_bytes(value)?;
+
+        self.file.write_u32(crc32c(&buf))?;
+        self.file.write_u64(buf.len() as u64)?;
+        self.file.write_all(&buf)?;
+
+        let blob = BlobRef {
+            file_id: self.id,
+            offset: self.offset,
+            size: value.len() as u64,
+        };
+
+        self.offset += 12 + buf.len() as u64;
+        self.value_count += 1;
+        self.value_bytes += value.len() as u64;
+
+        Ok(blob)
+    }
+
+    /// Flushes the file to disk and returns its summary.
+    pub fn finalize(self) -> io::Result<BlobFileDesc> {
+        let file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+
+        Ok(BlobFileDesc {
+            id: self.id,
+            value_count: self.value_count,
+            value_bytes: self.value_bytes,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reads values referred to by [`BlobRef`]s from the blob files of a store.
+pub struct BlobReader {
+    directory: PathBuf,
+}
+
+impl BlobReader {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    pub fn read(&self, blob: &BlobRef) -> io::Result<Vec<u8>> {
+        let mut file = File::open(blob_file_path(&self.directory, blob.file_id))?;
+
+        if file.read_u32()? != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid blob file magic number"));
+        }
+
+        let version = file.read_u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported blob file version: {version}"),
+            ));
+        }
+
+        let file_len = file.metadata()?.len();
+        let corrupted = || io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Corrupted blob at offset {} of blob file {}", blob.offset, blob.file_id),
+        );
+
+        file.seek(SeekFrom::Start(blob.offset))?;
+
+        let crc = file.read_u32()?;
+        let length = file.read_u64()?;
+
+        // The length is checked before anything is allocated for it, the reference may be bogus.
+        if length > file_len.saturating_sub(blob.offset.saturating_add(12)) {
+            return Err(corrupted());
+        }
+
+        let buf = file.read_bytes_with_len(length as usize)?;
+        if crc != crc32c(&buf) {
+            return Err(corrupted());
+        }
+
+        let mut record = Cursor::new(buf);
+        let _key = record.read_string()?;
+        let value = record.read_bytes()?;
+
+        if value.len() as u64 != blob.size {
+            return Err(corrupted());
+        }
+
+        Ok(value)
+    }
+
+    /// Reads the value a blob reference points to. Other entries are returned as they are.
+    pub fn resolve(&self, entry: Entry) -> io::Result<Entry> {
+        match entry {
+            Entry::Blob { blob, expires_at } => Ok(Entry::Value {
+                value: self.read(&blob)?,
+                expires_at,
+            }),
+            entry => Ok(entry),
+        }
+    }
+
+    /// Resolves the versions of a key, newest first, that collapsing them reads: the merge
+    /// operands and the version below them, which is the only one that may need reading from a
+    /// blob file. Older versions are dropped.
+    pub fn resolve_versions(&self, mut versions: Vec<Entry>) -> io::Result<Vec<Entry>> {
+        if let Some(base) = versions.iter().position(|it| !matches!(it, Entry::Merge(_))) {
+            versions.truncate(base + 1);
+
+            // SAFETY: the version at `base` was just kept
+            let entry = versions.pop().unwrap();
+            versions.push(self.resolve(entry)?);
+        }
+
+        Ok(versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn test_blob_file_can_be_written_and_read() {
+        let path = PathBuf::from("test_blob_file_can_be_written_and_read");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir(&path).unwrap();
+
+        let mut writer = BlobWriter::open(&path, 7).unwrap();
+        let first = writer.write("key1", &[1; 100]).unwrap();
+        let second = writer.write("key2", &[2; 200]).unwrap();
+        let desc = writer.finalize().unwrap();
+
+        assert_eq!(desc.value_count, 2);
+        assert_eq!(desc.value_bytes, 300);
+        assert_eq!(parse_blob_filename("blob_0000000000000007.blob"), Some(7));
+
+        let reader = BlobReader::new(path.clone());
+        assert_eq!(reader.read(&second).unwrap(), vec![2; 200]);
+        assert_eq!(reader.read(&first).unwrap(), vec![1; 100]);
+
+        // A reference that doesn't point at the start of a record is caught by the checksum.
+        let shifted = BlobRef { offset: first.offset + 1, ..first };
+        assert!(reader.read(&shifted).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Consistent copies of a store that can be opened like any other store.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::blob::blob_file_path;
+use crate::entry::Entry;
+use crate::manifest::ManifestSnapshot;
+use crate::sstable::sst_file_path;
+use crate::wal::Wal;
+
+/// What a checkpoint holds: the SSTs and blob files of `snapshot` and `unflushed`, the entries of
+/// the memtables at the time the snapshot was taken, by column family ID. `last_sequence` is the
+/// sequence number of the last WAL record the store had logged by then.
+pub(crate) struct CheckpointState {
+    pub snapshot: ManifestSnapshot,
+    pub unflushed: Vec<(u32, String, Entry)>,
+    pub last_sequence: u64,
+}
+
+/// Writes a store to `dest` made of `state`.
+///
+/// The files of the snapshot are hard-linked from `directory` where possible. `dest` must not
+/// exist, and is removed again if the checkpoint can't be completed.
+pub(crate) fn write_checkpoint(directory: &Path, state: &CheckpointState, dest: &Path) -> io::Result<()> {
+    if dest.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("checkpoint directory {dest:?} already exists"),
+        ));
+    }
+
+    fs::create_dir_all(dest)?;
+
+    let result = write_files(directory, state, dest);
+
+    if result.is_err() && let Err(e) = fs::remove_dir_all(dest) {
+        eprintln!("Error removing incomplete checkpoint: {e}");
+    }
+
+    result
+}
+
+fn write_files(directory: &Path, state: &CheckpointState, dest: &Path) -> io::Result<()> {
+    let CheckpointState {
+        snapshot,
+        unflushed,
+        last_sequence,
+    } = state;
+    let version = &snapshot.version;
+
+    for sstable in version.get_sstables() {
+        link_or_copy(&sst_file_path(directory, sstable.id), &sst_file_path(dest, sstable.id))?;
+    }
+
+    for blob_file in version.get_blob_files() {
+        link_or_copy(&blob_file_path(directory, blob_file.id), &blob_file_path(dest, blob_file.id))?;
+    }
+
+    snapshot.write(dest)?;
+
+    // Unflushed entries are restored from the WAL and flushed when the checkpoint is opened.
+    let mut wal = Wal::new(dest, None)?;
+
+    // The unflushed entries are logged as a single record taking the sequence number of the last
+    // record of the store, so archived records that follow it can be replayed onto the checkpoint.
+    match unflushed.is_empty() {
+        true => wal.set_last_sequence(*last_sequence),
+        false => wal.set_last_sequence(last_sequence.saturating_sub(1)),
+    }
+
+    wal.truncate()?;
+
+    if !unflushed.is_empty() {
+        wal.log_many(unflushed.iter().map(|(family, key, entry)| (*family, key.as_str(), entry)))?;
+    }
+
+    wal.sync()?;
+
+    Ok(())
+}
+
+/// Hard-links `from` to `to`, copying it instead if it can't be linked, such as when `to` is on
+/// another filesystem. SST and blob files are never modified once written, so both are fine.
+pub(crate) fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to)?;
+    fs::File::open(to)?.sync_all()?;
+
+    Ok(())
+}
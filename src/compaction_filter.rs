@@ -0,0 +1,39 @@
+//! User-defined filtering of entries during compaction.
+
+/// Information about the compaction an entry is being filtered in.
+#[derive(Debug, Clone)]
+pub struct CompactionContext {
+    /// Level the merged SSTable is written to.
+    pub target_level: u8,
+
+    /// Whether the compaction was requested through [`crate::Store::compact_range`].
+    pub is_manual: bool,
+
+    /// Whether no older version of the keys being compacted exists outside this compaction.
+    ///
+    /// Removing an entry when this is false may make an older version of the key visible again.
+    pub is_bottommost: bool,
+}
+
+/// What to do with an entry passed to a [`CompactionFilter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Keep the entry as is.
+    Keep,
+
+    /// Drop the entry from the merged SSTable.
+    Remove,
+
+    /// Keep the entry, replacing its value.
+    ChangeValue(Vec<u8>),
+}
+
+/// Filter invoked for every entry that survives a compaction.
+///
+/// Only the latest version of each key is passed to the filter, older versions are dropped by the
+/// compaction before the filter sees them, as are expired entries. Merge operands that could not
+/// be combined with a value yet are not filtered either. Entries that have not been compacted yet are not
+/// filtered and remain visible to reads.
+pub trait CompactionFilter: Send + Sync {
+    fn filter(&self, context: &CompactionContext, key: &str, value: &[u8]) -> Decision;
+}
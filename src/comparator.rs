@@ -0,0 +1,254 @@
+//! Orderings of keys.
+
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::ops::Bound::*;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+/// Defines the order keys are sorted in, in the memtable, in SSTs and when they are read.
+///
+/// The SSTs of a store are sorted by the comparator it was created with. Its name is recorded in
+/// the manifest and opening the store with a comparator of another name fails.
+pub trait Comparator: Send + Sync {
+    /// Identifies the ordering. Comparators ordering keys differently must have different names.
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &str, b: &str) -> Ordering;
+}
+
+/// Orders keys by their bytes. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "sand.bytewise"
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Orders keys by their bytes, largest first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "sand.reverse_bytewise"
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Orders keys by their bytes, except for a trailing run of ASCII digits which is compared by its
+/// numeric value, so `item9` comes before `item10`. Keys with equal values, like `item01` and
+/// `item1`, are ordered by their bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NumericSuffixComparator;
+
+impl NumericSuffixComparator {
+    /// Splits `key` into the part before its numeric suffix and the suffix with leading zeros
+    /// removed.
+    fn split(key: &str) -> (&str, &str) {
+        let prefix = key.trim_end_matches(|it: char| it.is_ascii_digit());
+        let number = key[prefix.len()..].trim_start_matches('0');
+
+        (prefix, number)
+    }
+}
+
+impl Comparator for NumericSuffixComparator {
+    fn name(&self) -> &str {
+        "sand.numeric_suffix"
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        let (a_prefix, a_number) = Self::split(a);
+        let (b_prefix, b_number) = Self::split(b);
+
+        a_prefix
+            .cmp(b_prefix)
+            // Without leading zeros, a longer number is a larger one.
+            .then_with(|| a_number.len().cmp(&b_number.len()))
+            .then_with(|| a_number.cmp(b_number))
+            .then_with(|| a.cmp(b))
+    }
+}
+
+/// Returns the built-in comparator of the given name, if there is one.
+pub(crate) fn builtin_comparator(name: &str) -> Option<Arc<dyn Comparator>> {
+    builtin_comparators().into_iter().find(|it| it.name() == name)
+}
+
+/// Returns the comparators that come with the crate, bytewise first.
+pub(crate) fn builtin_comparators() -> [Arc<dyn Comparator>; 3] {
+    [
+        Arc::new(BytewiseComparator),
+        Arc::new(ReverseBytewiseComparator),
+        Arc::new(NumericSuffixComparator),
+    ]
+}
+
+/// Returns true if no key can fall in `range` when ordered by `comparator`.
+pub(crate) fn range_is_empty<R: RangeBounds<str> + ?Sized>(
+    comparator: &dyn Comparator,
+    range: &R,
+) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Included(a), Included(b)) => comparator.compare(a, b).is_gt(),
+        (Included(a), Excluded(b)) | (Excluded(a), Included(b)) | (Excluded(a), Excluded(b)) => {
+            comparator.compare(a, b).is_ge()
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn range_contains<R: RangeBounds<str> + ?Sized>(
+    comparator: &dyn Comparator,
+    range: &R,
+    key: &str,
+) -> bool {
+    let after_start = match range.start_bound() {
+        Included(start) => comparator.compare(start, key).is_le(),
+        Excluded(start) => comparator.compare(start, key).is_lt(),
+        Unbounded => true,
+    };
+
+    let before_end = match range.end_bound() {
+        Included(end) => comparator.compare(key, end).is_le(),
+        Excluded(end) => comparator.compare(key, end).is_lt(),
+        Unbounded => true,
+    };
+
+    after_start && before_end
+}
+
+/// Returns true if `range` overlaps the keys from `min` to `max`, both inclusive.
+pub(crate) fn range_overlaps<R: RangeBounds<str> + ?Sized>(
+    comparator: &dyn Comparator,
+    range: &R,
+    min: &str,
+    max: &str,
+) -> bool {
+    let min_matches = match range.start_bound() {
+        Included(start) => comparator.compare(start, max).is_le(),
+        Excluded(start) => comparator.compare(start, max).is_lt(),
+        Unbounded => true,
+    };
+
+    let max_matches = match range.end_bound() {
+        Included(end) => comparator.compare(end, min).is_ge(),
+        Excluded(end) => comparator.compare(end, min).is_gt(),
+        Unbounded => true,
+    };
+
+    min_matches && max_matches
+}
+
+/// A range of keys that owns its bounds.
+#[derive(Debug, Clone)]
+pub(crate) struct KeyRange {
+    pub start: Bound<String>,
+    pub end: Bound<String>,
+}
+
+impl RangeBounds<str> for KeyRange {
+    fn start_bound(&self) -> Bound<&str> {
+        self.start.as_ref().map(String::as_str)
+    }
+
+    fn end_bound(&self) -> Bound<&str> {
+        self.end.as_ref().map(String::as_str)
+    }
+}
+
+/// Returns the smallest key, in bytewise order, that is larger than every key starting with
+/// `prefix`, or None if there is no such key.
+fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut successor = prefix.to_owned();
+
+    while let Some(last) = successor.pop() {
+        // Skip over the surrogates, which are not valid chars
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            _ => char::from_u32(last as u32 + 1),
+        };
+
+        if let Some(next) = next {
+            successor.push(next);
+            return Some(successor);
+        }
+    }
+
+    None
+}
+
+/// Returns a range holding every key starting with `prefix`, ordered by `comparator`.
+///
+/// Keys sharing a prefix are next to each other only under the bytewise comparators. Under any
+/// other comparator the range is unbounded and the keys have to be filtered.
+pub(crate) fn prefix_range(comparator: &dyn Comparator, prefix: &str) -> KeyRange {
+    let successor = prefix_successor(prefix);
+
+    let (start, end) = if comparator.name() == BytewiseComparator.name() {
+        (Included(prefix.to_owned()), successor.map_or(Unbounded, Excluded))
+    } else if comparator.name() == ReverseBytewiseComparator.name() {
+        (successor.map_or(Unbounded, Excluded), Included(prefix.to_owned()))
+    } else {
+        (Unbounded, Unbounded)
+    };
+
+    KeyRange { start, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric_suffix_comparator_orders_by_value() {
+        let mut keys = vec!["item10", "item9", "item", "item010", "item01", "item1", "other2", "item100"];
+        keys.sort_by(|a, b| NumericSuffixComparator.compare(a, b));
+
+        assert_eq!(
+            keys,
+            vec!["item", "item01", "item1", "item9", "item010", "item10", "item100", "other2"]
+        );
+    }
+
+    #[test]
+    fn test_ranges_follow_the_comparator() {
+        let comparator = ReverseBytewiseComparator;
+
+        assert!(!range_is_empty(&comparator, &(Included("b"), Included("a"))));
+        assert!(range_is_empty(&comparator, &(Included("a"), Included("b"))));
+
+        assert!(range_contains(&comparator, &(Included("c"), Excluded("a")), "b"));
+        assert!(!range_contains(&comparator, &(Included("c"), Excluded("a")), "a"));
+
+        assert!(range_overlaps(&comparator, &(Included("c"), Included("b")), "b", "a"));
+        assert!(!range_overlaps(&comparator, &(Included("c"), Excluded("b")), "b", "a"));
+    }
+
+    #[test]
+    fn test_prefix_range_holds_keys_with_prefix() {
+        let range = prefix_range(&BytewiseComparator, "user:1");
+        assert!(range_contains(&BytewiseComparator, &range, "user:1"));
+        assert!(range_contains(&BytewiseComparator, &range, "user:12"));
+        assert!(!range_contains(&BytewiseComparator, &range, "user:2"));
+        assert!(!range_contains(&BytewiseComparator, &range, "user:"));
+
+        let range = prefix_range(&ReverseBytewiseComparator, "user:1");
+        assert!(range_contains(&ReverseBytewiseComparator, &range, "user:12"));
+        assert!(!range_contains(&ReverseBytewiseComparator, &range, "user:2"));
+
+        assert_eq!(prefix_successor("a\u{D7FF}"), Some("a\u{E000}".to_owned()));
+        assert_eq!(prefix_successor("a\u{10FFFF}"), Some("b".to_owned()));
+        assert_eq!(prefix_successor(""), None);
+    }
+}
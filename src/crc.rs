@@ -37,6 +37,29 @@ pub fn crc32c(data: &[u8]) -> u32 {
     crc32c_iter(data.iter().cloned())
 }
 
+/// CRC32C of data that is fed in pieces, such as a file read through a buffer.
+#[derive(Debug, Clone)]
+pub struct Crc32c(u32);
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Self(0xFFFF_FFFF)
+    }
+}
+
+impl Crc32c {
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            let index = (self.0 ^ (*byte as u32)) & 0xFF;
+            self.0 = CRC32C_TABLE[index as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.0 ^ 0xFFFF_FFFF
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,4 +78,12 @@ mod tests {
     fn test_hello_world() {
         assert_eq!(crc32c(b"hello world"), 0xc99465aa);
     }
+
+    #[test]
+    fn test_incremental() {
+        let mut crc = Crc32c::default();
+        crc.update(b"hello ");
+        crc.update(b"world");
+        assert_eq!(crc.finish(), 0xc99465aa);
+    }
 }
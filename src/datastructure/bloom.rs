@@ -0,0 +1,106 @@
+//! Bloom filters for testing whether a set may contain a key without storing the keys.
+
+/// Bits of the filter per key added to it, giving a false positive rate of about 1%.
+const BITS_PER_KEY: usize = 10;
+
+/// Smallest size of a filter, in bits.
+const MIN_BITS: usize = 64;
+
+/// Hashes a key for adding it to or looking it up in a [`BloomFilter`].
+///
+/// This is FNV-1a followed by the finalizer of SplitMix64 for a better spread of bits. The hash is
+/// stored in files, so it must never change.
+pub fn hash(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    /// Builds a filter holding the keys with the given hashes, see [`hash`].
+    pub fn from_hashes(hashes: &[u64]) -> Self {
+        let bit_count = (hashes.len() * BITS_PER_KEY).max(MIN_BITS);
+
+        // k = ln(2) * bits per key minimizes the false positive rate
+        let hash_count = ((BITS_PER_KEY as f64) * std::f64::consts::LN_2).round() as u32;
+
+        let mut filter = Self {
+            bits: vec![0; bit_count.div_ceil(8)],
+            hash_count,
+        };
+
+        for hash in hashes {
+            for bit in filter.probes(*hash) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+
+        filter
+    }
+
+    /// Recreates a filter from the parts it was stored as.
+    pub fn from_parts(bits: Vec<u8>, hash_count: u32) -> Self {
+        Self { bits, hash_count }
+    }
+
+    pub fn bits(&self) -> &[u8] {
+        &self.bits
+    }
+
+    pub fn hash_count(&self) -> u32 {
+        self.hash_count
+    }
+
+    /// Returns false if the key with the given hash is certainly not in the filter.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        if self.bits.is_empty() {
+            return false;
+        }
+
+        self.probes(hash).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Bits a key is stored in, derived from its hash by double hashing.
+    fn probes(&self, hash: u64) -> impl Iterator<Item = usize> + use<> {
+        let bit_count = (self.bits.len() * 8) as u64;
+        let delta = hash.rotate_right(32);
+
+        (0..self.hash_count as u64)
+            .map(move |i| (hash.wrapping_add(i.wrapping_mul(delta)) % bit_count) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter_has_no_false_negatives() {
+        let keys: Vec<_> = (0..1000).map(|it| format!("key_{it}")).collect();
+        let hashes: Vec<_> = keys.iter().map(|it| hash(it.as_bytes())).collect();
+
+        let filter = BloomFilter::from_hashes(&hashes);
+        assert!(hashes.iter().all(|it| filter.may_contain(*it)));
+
+        let false_positives = (0..1000)
+            .filter(|it| filter.may_contain(hash(format!("other_{it}").as_bytes())))
+            .count();
+        assert!(false_positives < 50, "{false_positives} false positives");
+
+        let filter = BloomFilter::from_parts(filter.bits().to_vec(), filter.hash_count());
+        assert!(hashes.iter().all(|it| filter.may_contain(*it)));
+    }
+}
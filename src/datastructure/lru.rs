@@ -91,6 +91,8 @@ where
     }
 }
 
+type EntryMap<K, V> = HashMap<KeyRef<K>, (V, NodeHandle<K>)>;
+
 /// A simple LRU cache implementation.
 ///
 /// # Example
@@ -119,7 +121,7 @@ where
     // User should not have to take a mutable reference to self read from the cache using
     // Self::get. But we do need to mutably borrow these without to move the read values
     // to the front of the list.
-    map: UnsafeCell<HashMap<KeyRef<K>, (V, NodeHandle<K>)>>,
+    map: UnsafeCell<EntryMap<K, V>>,
     list: UnsafeCell<SlotMap<K>>,
 
     capacity: usize,
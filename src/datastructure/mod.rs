@@ -1,2 +1,3 @@
+pub mod bloom;
 pub mod lru;
 pub mod slotmap;
@@ -64,11 +64,7 @@ pub struct NodeHandle<T> {
 
 impl<T> Clone for NodeHandle<T> {
     fn clone(&self) -> Self {
-        Self {
-            index: self.index,
-            generation: self.generation,
-            phantom: PhantomData,
-        }
+        *self
     }
 }
 
@@ -103,6 +99,7 @@ pub struct SlotMap<T> {
 
 impl<T> SlotMap<T> {
     /// Creates a new empty `SlotMap`.
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             slots: Vec::new(),
@@ -221,6 +218,7 @@ impl<T> SlotMap<T> {
     ///
     /// assert_eq!(map.get(map.tail().unwrap()), Some(&1));
     /// ```
+    #[allow(dead_code)]
     pub fn tail(&self) -> Option<NodeHandle<T>> {
         self.tail.map(|index| NodeHandle {
             index,
@@ -264,6 +262,7 @@ impl<T> SlotMap<T> {
     ///
     /// assert_eq!(map.iter().collect::<Vec<_>>(), vec![&3, &1]);
     /// ```
+    #[allow(dead_code)]
     pub fn remove(&mut self, node: NodeHandle<T>) {
         let slot = &mut self.slots[node.index];
 
@@ -307,9 +306,7 @@ impl<T> SlotMap<T> {
             return None;
         }
 
-        if slot.value.is_none() {
-            return None;
-        }
+        slot.value.as_ref()?;
 
         slot.generation += 1;
         let generation = slot.generation;
@@ -388,6 +385,7 @@ impl<T> SlotMap<T> {
     ///
     /// assert_eq!(map.iter().collect::<Vec<_>>(), vec![&2, &1]);
     /// ```
+    #[allow(dead_code)]
     fn iter(&self) -> impl Iterator<Item = &T> {
         std::iter::from_fn({
             let mut current = self.head;
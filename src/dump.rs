@@ -0,0 +1,530 @@
+//! Logical dumps of the entries of a store, portable across store and file format versions.
+//!
+//! Dump format is specified in [docs/dump-file-spec.md](docs/dump-file-spec.md).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::crc::crc32c;
+use crate::entry::now_millis;
+use crate::io_ext::{ReadExt, WriteExt};
+use crate::options::Options;
+use crate::sst_file_writer::SstFileWriter;
+use crate::store::Store;
+
+const MAGIC: u32 = 0xD0DE5A4D;
+const VERSION: u8 = 2;
+
+/// Size of the keys and values a block holds before the next one is started.
+const BLOCK_SIZE_TARGET: usize = 64 * 1024;
+
+/// Size of the keys and values an SST built by an import holds before the next one is started.
+const IMPORT_SST_SIZE_TARGET: usize = 64 * 1024 * 1024;
+
+/// Keys, values and expiry times of a block.
+type Block = Vec<(String, Vec<u8>, Option<u64>)>;
+
+/// Options of the store a dump was exported from, as recorded in its header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DumpHeader {
+    /// Version of the dump format. Entries of version 1 dumps have no expiry time.
+    pub version: u8,
+
+    /// Name of the comparator the entries are sorted by.
+    pub comparator: String,
+
+    /// Name of the prefix extractor, if any.
+    pub prefix_extractor: Option<String>,
+
+    /// Default TTL in milliseconds, if any.
+    pub default_ttl: Option<u64>,
+
+    pub blob_threshold: Option<u64>,
+}
+
+impl DumpHeader {
+    fn from_options(options: &Options) -> Self {
+        Self {
+            version: VERSION,
+            comparator: options.comparator().name().to_owned(),
+            prefix_extractor: options.prefix_extractor.as_ref().map(|it| it.name().to_owned()),
+            default_ttl: options.default_ttl.map(|it| it.as_millis() as u64),
+            blob_threshold: options.blob_threshold.map(|it| it as u64),
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32(MAGIC)?;
+        writer.write_u8(self.version)?;
+        writer.write_string(&self.comparator)?;
+        writer.write_string(self.prefix_extractor.as_deref().unwrap_or(""))?;
+        writer.write_u64(self.default_ttl.unwrap_or(0))?;
+        writer.write_u64(self.blob_threshold.unwrap_or(0))?;
+
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        if reader.read_u32()? != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid dump magic number"));
+        }
+
+        let version = reader.read_u8()?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported dump version: {version}"),
+            ));
+        }
+
+        let comparator = reader.read_string()?;
+        let prefix_extractor = reader.read_string()?;
+        let default_ttl = reader.read_u64()?;
+        let blob_threshold = reader.read_u64()?;
+
+        Ok(Self {
+            version,
+            comparator,
+            prefix_extractor: Some(prefix_extractor).filter(|it| !it.is_empty()),
+            default_ttl: Some(default_ttl).filter(|it| *it != 0),
+            blob_threshold: Some(blob_threshold).filter(|it| *it != 0),
+        })
+    }
+}
+
+/// Reads the header of a dump, leaving `reader` at its first block.
+pub fn read_dump_header<R: Read>(reader: &mut R) -> io::Result<DumpHeader> {
+    DumpHeader::read(reader)
+}
+
+/// Writes the live entries of `range` to `writer`, recording `options`, the options the store
+/// was opened with, in the header. `progress` is called with the number of entries written so
+/// far after every block. Returns the number of entries written.
+///
+/// Keys are exported along with their values and expiry times, unmerged operands are not.
+pub fn export_dump<S, R, W>(
+    store: &S,
+    options: &Options,
+    range: R,
+    mut writer: W,
+    mut progress: impl FnMut(u64),
+) -> io::Result<u64>
+where
+    S: Store,
+    R: RangeBounds<str> + Clone,
+    W: Write,
+{
+    DumpHeader::from_options(options).write(&mut writer)?;
+
+    let mut count = 0;
+    let mut block = Vec::with_capacity(BLOCK_SIZE_TARGET);
+    let mut block_count = 0;
+
+    for item in store.get_range_with_expiry(range)? {
+        let (key, value, expires_at) = item?;
+
+        block.write_string(&key)?;
+        block.write_bytes(&value)?;
+        // 0 means the value never expires
+        block.write_u64(expires_at.unwrap_or(0))?;
+        block_count += 1;
+
+        if block.len() >= BLOCK_SIZE_TARGET {
+            write_block(&mut writer, block_count, &block)?;
+            count += block_count as u64;
+            progress(count);
+
+            block.clear();
+            block_count = 0;
+        }
+    }
+
+    if block_count > 0 {
+        write_block(&mut writer, block_count, &block)?;
+        count += block_count as u64;
+        progress(count);
+    }
+
+    // An empty block marks the end, so a truncated dump is told apart from a complete one.
+    write_block(&mut writer, 0, &[])?;
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Writes the entries of a dump read from `reader` to `store`, the options of which are
+/// `options`. `progress` is called with the number of entries written so far after every block.
+/// Returns the number of entries written, which leaves out entries that expired in the meantime.
+///
+/// If the dump is sorted by the comparator of the store and the store has no blob threshold, its
+/// entries are written to SSTs in `scratch`, which is created and removed again, and ingested
+/// through [`Store::ingest_external_files`]. They are only visible once the whole dump is read.
+/// Otherwise they are written a block at a time through [`Store::insert_batch`], or
+/// [`Store::insert_with_ttl`] for entries that expire, so that large values are moved to blob
+/// files as they are flushed.
+pub fn import_dump<S, R>(
+    store: &S,
+    options: &Options,
+    mut reader: R,
+    scratch: &Path,
+    progress: impl FnMut(u64),
+) -> io::Result<u64>
+where
+    S: Store,
+    R: Read,
+{
+    let header = DumpHeader::read(&mut reader)?;
+    let blocks = std::iter::from_fn(move || read_block(&mut reader, header.version).transpose());
+
+    // SSTs built for ingestion hold every value inline.
+    if header.comparator != options.comparator().name() || options.blob_threshold.is_some() {
+        return import_batches(store, blocks, progress);
+    }
+
+    fs::create_dir_all(scratch)?;
+
+    let result = import_sstables(store, options, blocks, scratch, progress);
+
+    if let Err(e) = fs::remove_dir_all(scratch) {
+        eprintln!("Error removing import scratch directory: {e}");
+    }
+
+    result
+}
+
+fn import_sstables<S: Store>(
+    store: &S,
+    options: &Options,
+    blocks: impl Iterator<Item = io::Result<Block>>,
+    scratch: &Path,
+    mut progress: impl FnMut(u64),
+) -> io::Result<u64> {
+    let mut count = 0;
+    let mut paths = Vec::new();
+    let mut writer: Option<SstFileWriter> = None;
+    let mut written = 0;
+
+    for block in blocks {
+        let now = now_millis();
+
+        for (key, value, expires_at) in block? {
+            if expires_at.is_some_and(|it| it <= now) {
+                continue;
+            }
+
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let path = scratch.join(format!("{:016}.sst", paths.len()));
+                    paths.push(path.clone());
+                    writer.insert(SstFileWriter::create(path, options)?)
+                }
+            };
+
+            match expires_at {
+                Some(expires_at) => writer.put_with_expiry(&key, &value, expires_at)?,
+                None => writer.put(&key, &value)?,
+            }
+
+            written += key.len() + value.len();
+            count += 1;
+        }
+
+        if written >= IMPORT_SST_SIZE_TARGET && let Some(writer) = writer.take() {
+            writer.finish()?;
+            written = 0;
+        }
+
+        progress(count);
+    }
+
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+
+    if !paths.is_empty() {
+        store.ingest_external_files(&paths)?;
+    }
+
+    Ok(count)
+}
+
+fn import_batches<S: Store>(
+    store: &S,
+    blocks: impl Iterator<Item = io::Result<Block>>,
+    mut progress: impl FnMut(u64),
+) -> io::Result<u64> {
+    let mut count = 0;
+
+    for block in blocks {
+        let mut entries = BTreeMap::new();
+        let mut expiring = Vec::new();
+
+        for (key, value, expires_at) in block? {
+            match expires_at {
+                Some(expires_at) => expiring.push((key, value, expires_at)),
+                None => {
+                    entries.insert(key, value);
+                }
+            }
+        }
+
+        if !entries.is_empty() {
+            store.insert_batch(&entries)?;
+            count += entries.len() as u64;
+        }
+
+        // Only TTLs can be written, taken from the expiry times just before each insert so the
+        // entries expire on the same schedule, give or take a millisecond.
+        for (key, value, expires_at) in expiring {
+            if let Some(ttl) = expires_at.checked_sub(now_millis()).filter(|it| *it > 0) {
+                store.insert_with_ttl(&key, &value, Duration::from_millis(ttl))?;
+                count += 1;
+            }
+        }
+
+        progress(count);
+    }
+
+    Ok(count)
+}
+
+fn write_block<W: Write>(writer: &mut W, count: u32, data: &[u8]) -> io::Result<()> {
+    writer.write_u32(count)?;
+    writer.write_u64(data.len() as u64)?;
+    writer.write_u32(crc32c(data))?;
+    writer.write_all(data)
+}
+
+/// Reads the entries of the next block of a dump of the given format version, or None once the
+/// end of the dump is reached.
+fn read_block<R: Read>(reader: &mut R, version: u8) -> io::Result<Option<Block>> {
+    let count = reader.read_u32()?;
+    let len = reader.read_u64()?;
+    let crc = reader.read_u32()?;
+
+    // Read without allocating for the length up front, it may be corrupted.
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+
+    if data.len() as u64 != len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Dump is truncated"));
+    }
+
+    if crc != crc32c(&data) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Dump block is corrupted"));
+    }
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let mut data = Cursor::new(data);
+    let entries = (0..count)
+        .map(|_| {
+            let key = data.read_string()?;
+            let value = data.read_bytes()?;
+            let expires_at = match version {
+                1 => 0,
+                _ => data.read_u64()?,
+            };
+
+            Ok((key, value, Some(expires_at).filter(|it| *it != 0)))
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(Some(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Bound::*;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::blob::parse_blob_filename;
+    use crate::comparator::ReverseBytewiseComparator;
+    use crate::store_impl::{make_store, make_store_with_options};
+
+    #[test]
+    fn test_exported_range_can_be_imported() {
+        let dirs = [
+            "test_exported_range_can_be_imported",
+            "test_exported_range_can_be_imported_sorted",
+            "test_exported_range_can_be_imported_reverse",
+        ]
+        .map(PathBuf::from);
+        for dir in dirs.iter() {
+            let _ = fs::remove_dir_all(dir);
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        let store = make_store(dirs[0].clone()).unwrap();
+        for i in 0..5000 {
+            store.insert(&format!("key{i:05}"), format!("value{i}").as_bytes()).unwrap();
+        }
+        store.flush().unwrap();
+        store.insert("outside", b"range").unwrap();
+
+        let mut dump = Vec::new();
+        let mut reported = 0;
+        let count = export_dump(&store, &Options::default(), (Included("key"), Excluded("key9")), &mut dump, |it| {
+            reported = it
+        })
+        .unwrap();
+
+        assert_eq!(count, 5000);
+        assert_eq!(reported, 5000);
+
+        // Sorted like the store, so written to SSTs.
+        let sorted = make_store(dirs[1].clone()).unwrap();
+        let scratch = dirs[1].join("import");
+        let count = import_dump(&sorted, &Options::default(), &dump[..], &scratch, |_| {}).unwrap();
+
+        assert_eq!(count, 5000);
+        assert!(!scratch.exists());
+        assert_eq!(sorted.get("key01234").unwrap(), Some(b"value1234".to_vec()));
+        assert_eq!(sorted.get("outside").unwrap(), None);
+
+        let options = Options {
+            comparator: Some(Arc::new(ReverseBytewiseComparator)),
+            ..Options::default()
+        };
+        let reverse = make_store_with_options(dirs[2].clone(), options.clone()).unwrap();
+        let count = import_dump(&reverse, &options, &dump[..], &dirs[2].join("import"), |_| {}).unwrap();
+
+        assert_eq!(count, 5000);
+        assert_eq!(reverse.get("key04999").unwrap(), Some(b"value4999".to_vec()));
+
+        // Corruption and truncation are both caught.
+        let mut corrupted = dump.clone();
+        corrupted[100] ^= 0xFF;
+        let err = import_dump(&sorted, &Options::default(), &corrupted[..], &scratch, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let truncated = &dump[..dump.len() - 20];
+        let err = import_dump(&sorted, &Options::default(), truncated, &scratch, |_| {}).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        drop(store);
+        drop(sorted);
+        drop(reverse);
+        for dir in dirs.iter() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_expiry_times_are_kept_across_export_and_import() {
+        let dirs = [
+            "test_expiry_times_are_kept_across_export_and_import",
+            "test_expiry_times_are_kept_across_export_and_import_sorted",
+            "test_expiry_times_are_kept_across_export_and_import_reverse",
+        ]
+        .map(PathBuf::from);
+        for dir in dirs.iter() {
+            let _ = fs::remove_dir_all(dir);
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        let store = make_store(dirs[0].clone()).unwrap();
+        store.insert("forever", b"value").unwrap();
+        store.insert_with_ttl("expiring", b"value", Duration::from_secs(3600)).unwrap();
+        store.insert_with_ttl("short", b"value", Duration::from_millis(50)).unwrap();
+
+        let expected: Vec<_> = store.get_range_with_expiry(..).unwrap().map(|it| it.unwrap()).collect();
+
+        let mut dump = Vec::new();
+        let count = export_dump(&store, &Options::default(), .., &mut dump, |_| {}).unwrap();
+        assert_eq!(count, 3);
+
+        // Entries that expire before they are imported are left out.
+        std::thread::sleep(Duration::from_millis(100));
+        let expected: Vec<_> = expected.into_iter().filter(|(key, _, _)| key != "short").collect();
+
+        // Written to SSTs.
+        let sorted = make_store(dirs[1].clone()).unwrap();
+        let count = import_dump(&sorted, &Options::default(), &dump[..], &dirs[1].join("import"), |_| {}).unwrap();
+
+        assert_eq!(count, 2);
+        let imported: Vec<_> = sorted.get_range_with_expiry(..).unwrap().map(|it| it.unwrap()).collect();
+        assert_eq!(imported, expected);
+
+        // Written through the WAL as TTLs, which may push expiry times back slightly.
+        let options = Options {
+            comparator: Some(Arc::new(ReverseBytewiseComparator)),
+            ..Options::default()
+        };
+        let reverse = make_store_with_options(dirs[2].clone(), options.clone()).unwrap();
+        let count = import_dump(&reverse, &options, &dump[..], &dirs[2].join("import"), |_| {}).unwrap();
+
+        assert_eq!(count, 2);
+        let mut imported: Vec<_> = reverse.get_range_with_expiry(..).unwrap().map(|it| it.unwrap()).collect();
+        imported.reverse();
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[1], expected[1]);
+        assert_eq!((&imported[0].0, &imported[0].1), (&expected[0].0, &expected[0].1));
+        let delay = imported[0].2.unwrap() - expected[0].2.unwrap();
+        assert!(delay < 1000, "expiry time pushed back by {delay}ms");
+
+        drop(store);
+        drop(sorted);
+        drop(reverse);
+        for dir in dirs.iter() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_import_moves_large_values_to_blob_files() {
+        let dirs = [
+            "test_import_moves_large_values_to_blob_files",
+            "test_import_moves_large_values_to_blob_files_blobs",
+        ]
+        .map(PathBuf::from);
+        for dir in dirs.iter() {
+            let _ = fs::remove_dir_all(dir);
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        let store = make_store(dirs[0].clone()).unwrap();
+        store.insert("large", &[b'a'; 4096]).unwrap();
+        store.insert("small", b"value").unwrap();
+
+        let mut dump = Vec::new();
+        export_dump(&store, &Options::default(), .., &mut dump, |_| {}).unwrap();
+
+        let options = Options {
+            blob_threshold: Some(1024),
+            ..Options::default()
+        };
+        let blobs = make_store_with_options(dirs[1].clone(), options.clone()).unwrap();
+        let count = import_dump(&blobs, &options, &dump[..], &dirs[1].join("import"), |_| {}).unwrap();
+        blobs.flush().unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(blobs.get("large").unwrap(), Some(vec![b'a'; 4096]));
+        assert_eq!(blobs.get("small").unwrap(), Some(b"value".to_vec()));
+
+        let blob_files = fs::read_dir(&dirs[1])
+            .unwrap()
+            .filter(|it| parse_blob_filename(&it.as_ref().unwrap().file_name().to_string_lossy()).is_some())
+            .count();
+        assert_eq!(blob_files, 1);
+
+        drop(store);
+        drop(blobs);
+        for dir in dirs.iter() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
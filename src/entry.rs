@@ -0,0 +1,258 @@
+//! Records along with the metadata stored next to them in the WAL, memtable and SSTables.
+
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::blob::BlobRef;
+use crate::io_ext::{ReadExt, WriteExt};
+
+const KIND_VALUE: u8 = 0;
+const KIND_MERGE: u8 = 1;
+const KIND_TOMBSTONE: u8 = 2;
+const KIND_BLOB: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Value {
+        value: Vec<u8>,
+
+        /// Time after which the value is treated as absent, in milliseconds since the UNIX epoch.
+        expires_at: Option<u64>,
+    },
+
+    /// Operands for the merge operator that are yet to be combined with an older value of the
+    /// key, oldest first.
+    Merge(Vec<Vec<u8>>),
+
+    /// Marks the key as deleted, shadowing older versions of it.
+    Tombstone,
+
+    /// A value stored in a blob file. Only found in SSTs, the LSM tree resolves these to values
+    /// before handing entries out.
+    Blob {
+        blob: BlobRef,
+        expires_at: Option<u64>,
+    },
+}
+
+impl Entry {
+    pub fn new(value: Vec<u8>) -> Self {
+        Entry::Value {
+            value,
+            expires_at: None,
+        }
+    }
+
+    /// Creates a value that expires `ttl` from now, or one that never expires if `ttl` is None.
+    pub fn with_ttl(value: Vec<u8>, ttl: Option<Duration>) -> Self {
+        let expires_at = ttl.map(|ttl| now_millis().saturating_add(ttl.as_millis() as u64));
+
+        Entry::Value { value, expires_at }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self {
+            Entry::Value { expires_at, .. } | Entry::Blob { expires_at, .. } => {
+                expires_at.is_some_and(|it| it <= now)
+            }
+            Entry::Merge(_) | Entry::Tombstone => false,
+        }
+    }
+
+    /// Returns the value if this is a value record. Expiry is not taken into account.
+    pub fn into_value(self) -> Option<Vec<u8>> {
+        match self {
+            Entry::Value { value, .. } => Some(value),
+            Entry::Merge(_) | Entry::Tombstone | Entry::Blob { .. } => None,
+        }
+    }
+
+    /// Approximate size of the entry in memory, used for deciding when to flush the memtable.
+    pub fn size(&self) -> usize {
+        match self {
+            Entry::Value { value, .. } => value.len(),
+            Entry::Merge(operands) => operands.iter().map(Vec::len).sum(),
+            Entry::Tombstone => 0,
+            Entry::Blob { blob, .. } => blob.size as usize,
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        // kind (1) + expiry (8) or operand count (8)
+        let header = 9;
+
+        match self {
+            Entry::Value { value, .. } => header + 8 + value.len(),
+            Entry::Merge(operands) => header + operands.iter().map(|it| 8 + it.len()).sum::<usize>(),
+            Entry::Tombstone => 1,
+            Entry::Blob { .. } => header + 24,
+        }
+    }
+
+    pub fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Entry::Value { value, expires_at } => {
+                writer.write_u8(KIND_VALUE)?;
+                // 0 means the value never expires
+                writer.write_u64(expires_at.unwrap_or(0))?;
+                writer.write_bytes(value)?;
+            }
+
+            Entry::Merge(operands) => {
+                writer.write_u8(KIND_MERGE)?;
+                writer.write_u64(operands.len() as u64)?;
+                for operand in operands {
+                    writer.write_bytes(operand)?;
+                }
+            }
+
+            Entry::Tombstone => writer.write_u8(KIND_TOMBSTONE)?,
+
+            Entry::Blob { blob, expires_at } => {
+                writer.write_u8(KIND_BLOB)?;
+                writer.write_u64(expires_at.unwrap_or(0))?;
+                writer.write_u64(blob.file_id)?;
+                writer.write_u64(blob.offset)?;
+                writer.write_u64(blob.size)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match reader.read_u8()? {
+            KIND_VALUE => Ok(Entry::Value {
+                expires_at: Self::decode_expiry(reader.read_u64()?),
+                value: reader.read_bytes()?,
+            }),
+
+            KIND_MERGE => {
+                let count = reader.read_u64()?;
+                let operands = (0..count)
+                    .map(|_| reader.read_bytes())
+                    .collect::<io::Result<_>>()?;
+
+                Ok(Entry::Merge(operands))
+            }
+
+            KIND_TOMBSTONE => Ok(Entry::Tombstone),
+
+            KIND_BLOB => Ok(Entry::Blob {
+                expires_at: Self::decode_expiry(reader.read_u64()?),
+                blob: BlobRef {
+                    file_id: reader.read_u64()?,
+                    offset: reader.read_u64()?,
+                    size: reader.read_u64()?,
+                },
+            }),
+
+            kind => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown record kind: {kind}"),
+            )),
+        }
+    }
+
+    /// Decodes a record like [`Entry::decode`], skipping over the bytes of its value or operands,
+    /// which are left empty. This is for reads that only need to know which keys are live.
+    pub fn decode_without_values<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+        match reader.read_u8()? {
+            KIND_VALUE => {
+                let expires_at = Self::decode_expiry(reader.read_u64()?);
+                skip_bytes(reader)?;
+
+                Ok(Entry::Value {
+                    value: Vec::new(),
+                    expires_at,
+                })
+            }
+
+            KIND_MERGE => {
+                for _ in 0..reader.read_u64()? {
+                    skip_bytes(reader)?;
+                }
+
+                Ok(Entry::Merge(Vec::new()))
+            }
+
+            KIND_TOMBSTONE => Ok(Entry::Tombstone),
+
+            // Blob references are read as empty values, the blob isn't read anyway.
+            KIND_BLOB => {
+                let expires_at = Self::decode_expiry(reader.read_u64()?);
+                reader.seek(SeekFrom::Current(24))?;
+
+                Ok(Entry::Value {
+                    value: Vec::new(),
+                    expires_at,
+                })
+            }
+
+            kind => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown record kind: {kind}"),
+            )),
+        }
+    }
+
+    /// Returns the entry with its value or operands left empty, see
+    /// [`Entry::decode_without_values`].
+    pub fn without_values(&self) -> Self {
+        match self {
+            Entry::Value { expires_at, .. } | Entry::Blob { expires_at, .. } => Entry::Value {
+                value: Vec::new(),
+                expires_at: *expires_at,
+            },
+            Entry::Merge(_) => Entry::Merge(Vec::new()),
+            Entry::Tombstone => Entry::Tombstone,
+        }
+    }
+
+    /// Returns whether the key of the entry has a value, given that this is its latest version.
+    /// Merge operands always produce one.
+    pub fn is_live(&self, now: u64) -> bool {
+        match self {
+            Entry::Value { .. } | Entry::Blob { .. } => !self.is_expired(now),
+            Entry::Merge(_) => true,
+            Entry::Tombstone => false,
+        }
+    }
+
+    /// Decodes a value written before records carried a kind, which is how values were stored by
+    /// version 2 of the WAL and SST formats.
+    pub fn decode_legacy_value<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok(Entry::Value {
+            expires_at: Self::decode_expiry(reader.read_u64()?),
+            value: reader.read_bytes()?,
+        })
+    }
+
+    fn decode_expiry(expires_at: u64) -> Option<u64> {
+        (expires_at != 0).then_some(expires_at)
+    }
+}
+
+/// Skips over a length-prefixed byte string.
+pub(crate) fn skip_bytes<R: Read + Seek>(reader: &mut R) -> io::Result<()> {
+    let len = reader.read_u64()?;
+    let len = i64::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Byte string is too long"))?;
+
+    reader.seek(SeekFrom::Current(len))?;
+    Ok(())
+}
+
+pub trait EntryCursor: Iterator<Item = io::Result<(String, Entry)>> {}
+impl<I: Iterator<Item = io::Result<(String, Entry)>>> EntryCursor for I {}
+
+/// Current time in milliseconds since the UNIX epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
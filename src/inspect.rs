@@ -0,0 +1,500 @@
+//! Dumps of the WAL, manifest and SST files of a store, as text or JSON.
+//!
+//! Files are parsed by the same readers the store uses, so a dump shows what the store itself
+//! reads. Torn or corrupted data at the end of a WAL or manifest is reported as trailing bytes.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Seek;
+use std::path::Path;
+
+use crate::blob::BlobFileDesc;
+use crate::entry::Entry;
+use crate::manifest::reader::{ManifestEntry, ManifestReader};
+use crate::manifest::{SSTableDesc, read_active_manifest};
+use crate::sstable::parse_sst_filename;
+use crate::sstable::reader::RawSSTableReader;
+use crate::wal;
+
+/// Contents of a file read by [`inspect_wal`], [`inspect_manifest`] or [`inspect_sst`].
+///
+/// Displayed as indented text, or written as JSON by [`Inspection::to_json`]. Values are shown
+/// with non-printable bytes escaped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inspection(Node);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Null,
+    Int(u64),
+    Str(String),
+    List(Vec<Node>),
+    Map(Vec<(&'static str, Node)>),
+}
+
+impl Inspection {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write_json(&mut out, &self.0);
+        out
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_text(f, &self.0, 0)
+    }
+}
+
+/// Reads the records of the WAL file at `path`, up to the first torn or corrupted one.
+pub fn inspect_wal(path: impl AsRef<Path>) -> io::Result<Inspection> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let len = reader.get_ref().metadata()?.len();
+
+    let mut fields = vec![("file", Node::Str(path.display().to_string()))];
+
+    // A WAL that was never written to has no header yet.
+    if len == 0 {
+        fields.push(("records", Node::List(Vec::new())));
+        return Ok(Inspection(Node::Map(fields)));
+    }
+
+    let (version, previous_sequence) = wal::parse_header(&mut reader)?;
+    let mut last_sequence = previous_sequence;
+    let mut valid_len = reader.stream_position()?;
+    let mut records = Vec::new();
+
+    while let Ok(Some(record)) = wal::read_record(&mut reader, version, last_sequence) {
+        valid_len = reader.stream_position()?;
+        last_sequence = record.sequence;
+
+        let entries = record
+            .entries
+            .iter()
+            .map(|(family, key, entry)| {
+                let mut fields = vec![("family", Node::Int(*family as u64)), ("key", Node::Str(key.clone()))];
+                fields.extend(entry_fields(entry));
+                Node::Map(fields)
+            })
+            .collect();
+
+        records.push(Node::Map(vec![
+            ("sequence", Node::Int(record.sequence)),
+            ("timestamp", Node::Int(record.timestamp)),
+            ("entries", Node::List(entries)),
+        ]));
+    }
+
+    fields.extend([
+        ("version", Node::Int(version as u64)),
+        ("previous_sequence", Node::Int(previous_sequence)),
+        ("records", Node::List(records)),
+        ("trailing_bytes", Node::Int(len - valid_len)),
+    ]);
+
+    Ok(Inspection(Node::Map(fields)))
+}
+
+/// Reads every entry of a manifest file, along with the live set they result in. Given a store
+/// directory, its active manifest is read.
+pub fn inspect_manifest(path: impl AsRef<Path>) -> io::Result<Inspection> {
+    let mut path = path.as_ref().to_path_buf();
+    if path.is_dir() {
+        path = read_active_manifest(&path)?.0;
+    }
+
+    let file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    let history = ManifestReader::new(file).read_history()?;
+
+    // Entries that contradict the ones before them are shown, even though no live set results.
+    let live = match ManifestReader::new(File::open(&path)?).read() {
+        Ok(state) => Node::Map(vec![
+            ("next_sst_id", Node::Int(state.next_sst_id)),
+            ("comparator", state.comparator.map(Node::Str).unwrap_or(Node::Null)),
+            ("column_families", column_families(state.column_families.into_iter())),
+            ("sstables", Node::List(state.sstables.values().map(sstable_desc).collect())),
+            ("blob_files", Node::List(state.blob_files.values().map(blob_file_desc).collect())),
+            ("flushed_wal_sequences", flushed_wal_sequences(state.flushed_wal_sequences.into_iter())),
+        ]),
+
+        Err(e) => Node::Map(vec![("error", Node::Str(e.to_string()))]),
+    };
+
+    Ok(Inspection(Node::Map(vec![
+        ("file", Node::Str(path.display().to_string())),
+        ("version", Node::Int(history.version as u64)),
+        ("entries", Node::List(history.entries.iter().map(manifest_entry).collect())),
+        ("trailing_bytes", Node::Int(len - history.valid_len)),
+        ("live", live),
+    ])))
+}
+
+/// Reads the chunks and items of the SST file at `path`, along with its properties. If it is in
+/// a store directory, what the manifest records of it is included.
+pub fn inspect_sst(path: impl AsRef<Path>) -> io::Result<Inspection> {
+    let path = path.as_ref();
+    let file_size = std::fs::metadata(path)?.len();
+
+    let mut reader = RawSSTableReader::open(path.to_path_buf())?;
+    let version = reader.version()?;
+    let footer = reader.footer()?;
+    let chunk_descs = reader.list_chunks()?;
+    let prefix_filter = reader.read_prefix_filter()?;
+
+    let mut entry_count = 0;
+    let mut tombstone_count = 0;
+    let mut chunks = Vec::with_capacity(chunk_descs.len());
+
+    for chunk_desc in chunk_descs.iter() {
+        let header = reader.read_chunk_header(chunk_desc.pos)?;
+        let items = reader.read_chunk_at(chunk_desc.pos)?;
+
+        entry_count += items.len() as u64;
+        tombstone_count += items.iter().filter(|(_, it)| matches!(it, Entry::Tombstone)).count() as u64;
+
+        let items = items
+            .iter()
+            .map(|(key, entry)| {
+                let mut fields = vec![("key", Node::Str(key.clone()))];
+                fields.extend(entry_fields(entry));
+                Node::Map(fields)
+            })
+            .collect();
+
+        chunks.push(Node::Map(vec![
+            ("index", Node::Int(chunk_desc.index as u64)),
+            ("pos", Node::Int(chunk_desc.pos)),
+            ("min_key", Node::Str(chunk_desc.min_key.clone())),
+            ("max_key", Node::Str(chunk_desc.max_key.clone())),
+            ("item_count", Node::Int(header.item_count as u64)),
+            ("compressed_size", Node::Int(header.compressed_size)),
+            ("uncompressed_size", Node::Int(header.uncompressed_size)),
+            ("items", Node::List(items)),
+        ]));
+    }
+
+    let prefix_filter = match prefix_filter {
+        Some(filter) => Node::Map(vec![
+            ("extractor", Node::Str(filter.extractor)),
+            (
+                "chunks",
+                Node::List(
+                    filter
+                        .chunks
+                        .iter()
+                        .map(|it| {
+                            Node::Map(vec![
+                                ("bits", Node::Int(it.bits().len() as u64 * 8)),
+                                ("hash_count", Node::Int(it.hash_count() as u64)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ]),
+        None => Node::Null,
+    };
+
+    Ok(Inspection(Node::Map(vec![
+        ("file", Node::Str(path.display().to_string())),
+        ("version", Node::Int(version as u64)),
+        (
+            "footer",
+            Node::Map(vec![
+                ("filter_pos", footer.filter_pos.map(Node::Int).unwrap_or(Node::Null)),
+                ("chunk_dir_pos", Node::Int(footer.chunk_dir_pos)),
+                ("chunk_count", Node::Int(footer.chunk_count as u64)),
+            ]),
+        ),
+        (
+            "properties",
+            Node::Map(vec![
+                ("file_size", Node::Int(file_size)),
+                ("entry_count", Node::Int(entry_count)),
+                ("tombstone_count", Node::Int(tombstone_count)),
+            ]),
+        ),
+        ("manifest", recorded_sstable(path).map(|it| sstable_desc(&it)).unwrap_or(Node::Null)),
+        ("prefix_filter", prefix_filter),
+        ("chunks", Node::List(chunks)),
+    ])))
+}
+
+/// Returns what the manifest of the store the SST at `path` is in records of it, if anything.
+fn recorded_sstable(path: &Path) -> Option<SSTableDesc> {
+    let id = parse_sst_filename(path.file_name()?.to_str()?)?;
+
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let (_, mut state) = read_active_manifest(directory).ok()?;
+    state.sstables.remove(&id)
+}
+
+fn manifest_entry(entry: &ManifestEntry) -> Node {
+    Node::Map(vec![
+        ("next_sst_id", Node::Int(entry.next_sst_id)),
+        ("added", Node::List(entry.added.iter().map(sstable_desc).collect())),
+        ("removed", Node::List(entry.removed.iter().copied().map(Node::Int).collect())),
+        ("column_families", column_families(entry.column_families.iter().cloned())),
+        ("comparator", entry.comparator.clone().map(Node::Str).unwrap_or(Node::Null)),
+        ("blob_files_added", Node::List(entry.blob_files.add.iter().map(blob_file_desc).collect())),
+        (
+            "blob_garbage",
+            Node::List(
+                entry
+                    .blob_files
+                    .garbage
+                    .iter()
+                    .map(|it| {
+                        Node::Map(vec![
+                            ("file_id", Node::Int(it.file_id)),
+                            ("count", Node::Int(it.count)),
+                            ("bytes", Node::Int(it.bytes)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ),
+        ("blob_files_removed", Node::List(entry.blob_files.remove.iter().copied().map(Node::Int).collect())),
+        ("flushed_wal_sequences", flushed_wal_sequences(entry.flushed_wal_sequences.iter().copied())),
+    ])
+}
+
+fn flushed_wal_sequences(sequences: impl Iterator<Item = (u32, u64)>) -> Node {
+    Node::List(
+        sequences
+            .map(|(family, sequence)| {
+                Node::Map(vec![("family", Node::Int(family as u64)), ("sequence", Node::Int(sequence))])
+            })
+            .collect(),
+    )
+}
+
+fn column_families(column_families: impl Iterator<Item = (u32, String)>) -> Node {
+    Node::List(
+        column_families
+            .map(|(id, name)| Node::Map(vec![("id", Node::Int(id as u64)), ("name", Node::Str(name))]))
+            .collect(),
+    )
+}
+
+fn sstable_desc(desc: &SSTableDesc) -> Node {
+    Node::Map(vec![
+        ("id", Node::Int(desc.id)),
+        ("family", Node::Int(desc.family as u64)),
+        ("level", Node::Int(desc.level as u64)),
+        ("min_key", Node::Str(desc.min_key.clone())),
+        ("max_key", Node::Str(desc.max_key.clone())),
+        ("file_size", Node::Int(desc.props.file_size)),
+        ("entry_count", Node::Int(desc.props.entry_count)),
+        ("tombstone_count", Node::Int(desc.props.tombstone_count)),
+        ("smallest_flush_seq", Node::Int(desc.props.smallest_flush_seq)),
+        ("largest_flush_seq", Node::Int(desc.props.largest_flush_seq)),
+        ("created_at", Node::Int(desc.props.created_at)),
+        ("compression", Node::Int(desc.props.compression as u64)),
+    ])
+}
+
+fn blob_file_desc(desc: &BlobFileDesc) -> Node {
+    Node::Map(vec![
+        ("id", Node::Int(desc.id)),
+        ("value_count", Node::Int(desc.value_count)),
+        ("value_bytes", Node::Int(desc.value_bytes)),
+        ("garbage_count", Node::Int(desc.garbage_count)),
+        ("garbage_bytes", Node::Int(desc.garbage_bytes)),
+    ])
+}
+
+fn entry_fields(entry: &Entry) -> Vec<(&'static str, Node)> {
+    let expiry = |expires_at: &Option<u64>| expires_at.map(Node::Int).unwrap_or(Node::Null);
+
+    match entry {
+        Entry::Value { value, expires_at } => vec![
+            ("kind", Node::Str("value".to_owned())),
+            ("value", bytes(value)),
+            ("expires_at", expiry(expires_at)),
+        ],
+
+        Entry::Merge(operands) => vec![
+            ("kind", Node::Str("merge".to_owned())),
+            ("operands", Node::List(operands.iter().map(|it| bytes(it)).collect())),
+        ],
+
+        Entry::Tombstone => vec![("kind", Node::Str("tombstone".to_owned()))],
+
+        Entry::Blob { blob, expires_at } => vec![
+            ("kind", Node::Str("blob".to_owned())),
+            ("file_id", Node::Int(blob.file_id)),
+            ("offset", Node::Int(blob.offset)),
+            ("size", Node::Int(blob.size)),
+            ("expires_at", expiry(expires_at)),
+        ],
+    }
+}
+
+fn bytes(value: &[u8]) -> Node {
+    let escaped: Vec<u8> = value
+        .iter()
+        .flat_map(|it| std::ascii::escape_default(*it))
+        .collect();
+
+    Node::Str(String::from_utf8_lossy(&escaped).into_owned())
+}
+
+fn write_text(f: &mut fmt::Formatter<'_>, node: &Node, indent: usize) -> fmt::Result {
+    let pad = "  ".repeat(indent);
+
+    match node {
+        Node::Map(fields) => {
+            for (name, value) in fields.iter() {
+                match value {
+                    Node::Map(it) if !it.is_empty() => {
+                        writeln!(f, "{pad}{name}:")?;
+                        write_text(f, value, indent + 1)?;
+                    }
+                    Node::List(it) if !it.is_empty() => {
+                        writeln!(f, "{pad}{name}:")?;
+                        write_text(f, value, indent + 1)?;
+                    }
+                    _ => writeln!(f, "{pad}{name}: {}", scalar(value))?,
+                }
+            }
+        }
+
+        Node::List(items) => {
+            for item in items.iter() {
+                match item {
+                    Node::Map(_) | Node::List(_) => {
+                        writeln!(f, "{pad}-")?;
+                        write_text(f, item, indent + 1)?;
+                    }
+                    _ => writeln!(f, "{pad}- {}", scalar(item))?,
+                }
+            }
+        }
+
+        _ => writeln!(f, "{pad}{}", scalar(node))?,
+    }
+
+    Ok(())
+}
+
+/// Text of a node that is not nested, or of an empty nested one.
+fn scalar(node: &Node) -> String {
+    match node {
+        Node::Null => "none".to_owned(),
+        Node::Int(value) => value.to_string(),
+        Node::Str(value) => format!("{value:?}"),
+        Node::List(_) => "[]".to_owned(),
+        Node::Map(_) => "{}".to_owned(),
+    }
+}
+
+fn write_json(out: &mut String, node: &Node) {
+    match node {
+        Node::Null => out.push_str("null"),
+        Node::Int(value) => out.push_str(&value.to_string()),
+        Node::Str(value) => write_json_string(out, value),
+
+        Node::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(out, item);
+            }
+            out.push(']');
+        }
+
+        Node::Map(fields) => {
+            out.push('{');
+            for (i, (name, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(out, name);
+                out.push(':');
+                write_json(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::sstable::sst_file_path;
+    use crate::store::Store;
+    use crate::store_impl::make_store;
+
+    #[test]
+    fn test_inspect_dumps_wal_manifest_and_sst() {
+        let dir = PathBuf::from("test_inspect_dumps_wal_manifest_and_sst");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("flushed", b"a\"b").unwrap();
+        store.flush().unwrap();
+        store.insert("unflushed", b"value\n").unwrap();
+
+        let wal = inspect_wal(dir.join(wal::FILENAME)).unwrap();
+        let json = wal.to_json();
+        assert!(json.contains(r#""key":"unflushed","kind":"value","value":"value\\n""#), "{json}");
+        assert!(json.ends_with(r#""trailing_bytes":0}"#), "{json}");
+        assert!(!json.contains("\"flushed\""), "{json}");
+
+        let manifest = inspect_manifest(&dir).unwrap();
+        let json = manifest.to_json();
+        assert!(json.contains(r#""min_key":"flushed""#), "{json}");
+        assert!(json.contains(r#""live":{"next_sst_id":"#), "{json}");
+
+        let sstables: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|it| parse_sst_filename(it.unwrap().file_name().to_str()?))
+            .collect();
+
+        let sst = inspect_sst(sst_file_path(&dir, sstables[0])).unwrap();
+        let json = sst.to_json();
+        assert!(json.contains(r#""key":"flushed","kind":"value","value":"a\\\"b""#), "{json}");
+        assert!(json.contains(r#""manifest":{"id":"#), "{json}");
+
+        let text = sst.to_string();
+        assert!(text.contains("  entry_count: 1\n"), "{text}");
+        assert!(text.contains("      key: \"flushed\"\n"), "{text}");
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
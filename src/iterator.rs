@@ -0,0 +1,568 @@
+//! Iterators that can be positioned at any key and moved in both directions.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::sync::Arc;
+
+use crate::blob::BlobReader;
+use crate::comparator::Comparator;
+use crate::entry::Entry;
+use crate::manifest::Version;
+use crate::merge_operator::{MergeOperator, collapse};
+use crate::sstable::ChunkDesc;
+use crate::sstable::reader::{Chunk, SSTableReader};
+use crate::util::KeyOnlyOrd;
+
+/// A sorted run of entries that can be positioned at any key, such as a memtable or an SSTable.
+///
+/// Positioning past either end leaves the source without a current entry.
+pub(crate) trait SeekableSource {
+    fn seek_to_first(&mut self) -> io::Result<()>;
+
+    fn seek_to_last(&mut self) -> io::Result<()>;
+
+    /// Positions the source at the first key at or after `key`.
+    fn seek(&mut self, key: &str) -> io::Result<()>;
+
+    /// Positions the source at the last key at or before `key`.
+    fn seek_for_prev(&mut self, key: &str) -> io::Result<()>;
+
+    fn next(&mut self) -> io::Result<()>;
+
+    fn prev(&mut self) -> io::Result<()>;
+
+    fn current(&self) -> Option<(&str, &Entry)>;
+}
+
+/// Returns the index of the first entry at or after `key`.
+fn lower_bound(entries: &[(String, Entry)], key: &str, comparator: &dyn Comparator) -> usize {
+    entries.partition_point(|(it, _)| comparator.compare(it, key).is_lt())
+}
+
+/// Returns the index of the first entry after `key`.
+fn upper_bound(entries: &[(String, Entry)], key: &str, comparator: &dyn Comparator) -> usize {
+    entries.partition_point(|(it, _)| comparator.compare(it, key).is_le())
+}
+
+/// Entries held in memory, such as a copy of the memtable.
+pub(crate) struct VecSource<'a> {
+    entries: Vec<(String, Entry)>,
+    comparator: &'a dyn Comparator,
+    pos: Option<usize>,
+}
+
+impl<'a> VecSource<'a> {
+    /// `entries` must be sorted by `comparator`.
+    pub fn new(entries: Vec<(String, Entry)>, comparator: &'a dyn Comparator) -> Self {
+        Self {
+            entries,
+            comparator,
+            pos: None,
+        }
+    }
+
+    fn set_pos(&mut self, pos: Option<usize>) {
+        self.pos = pos.filter(|it| *it < self.entries.len());
+    }
+}
+
+impl SeekableSource for VecSource<'_> {
+    fn seek_to_first(&mut self) -> io::Result<()> {
+        self.set_pos(Some(0));
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> io::Result<()> {
+        self.set_pos(self.entries.len().checked_sub(1));
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &str) -> io::Result<()> {
+        self.set_pos(Some(lower_bound(&self.entries, key, self.comparator)));
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &str) -> io::Result<()> {
+        self.set_pos(upper_bound(&self.entries, key, self.comparator).checked_sub(1));
+        Ok(())
+    }
+
+    fn next(&mut self) -> io::Result<()> {
+        self.set_pos(self.pos.map(|it| it + 1));
+        Ok(())
+    }
+
+    fn prev(&mut self) -> io::Result<()> {
+        self.set_pos(self.pos.and_then(|it| it.checked_sub(1)));
+        Ok(())
+    }
+
+    fn current(&self) -> Option<(&str, &Entry)> {
+        self.pos.map(|it| {
+            let (key, entry) = &self.entries[it];
+            (key.as_str(), entry)
+        })
+    }
+}
+
+/// A single SSTable, read a chunk at a time.
+pub(crate) struct SSTableSource<'a, S: SSTableReader> {
+    reader: &'a S,
+    sst_id: u64,
+    chunks: Vec<ChunkDesc>,
+    comparator: &'a dyn Comparator,
+
+    // Index into `chunks` of the loaded chunk.
+    chunk_index: usize,
+    chunk: Chunk,
+    pos: Option<usize>,
+}
+
+impl<'a, S: SSTableReader> SSTableSource<'a, S> {
+    pub fn open(reader: &'a S, sst_id: u64, comparator: &'a dyn Comparator) -> io::Result<Self> {
+        Ok(Self {
+            reader,
+            sst_id,
+            chunks: reader.list_chunks(sst_id)?,
+            comparator,
+            chunk_index: 0,
+            chunk: Chunk::new(),
+            pos: None,
+        })
+    }
+
+    fn load(&mut self, chunk_index: usize) -> io::Result<()> {
+        self.pos = None;
+        self.chunk = self.reader.read_chunk(self.sst_id, self.chunks[chunk_index].index)?;
+        self.chunk_index = chunk_index;
+
+        Ok(())
+    }
+
+    /// Positions the source at `pos` in the loaded chunk, moving on to the start of the following
+    /// chunks if it is past the end.
+    fn settle_forward(&mut self, mut pos: usize) -> io::Result<()> {
+        while pos >= self.chunk.len() {
+            if self.chunk_index + 1 >= self.chunks.len() {
+                self.pos = None;
+                return Ok(());
+            }
+
+            self.load(self.chunk_index + 1)?;
+            pos = 0;
+        }
+
+        self.pos = Some(pos);
+        Ok(())
+    }
+
+    /// Positions the source at `pos` in the loaded chunk, moving on to the end of the preceding
+    /// chunks if it is before the start.
+    fn settle_backward(&mut self, mut pos: Option<usize>) -> io::Result<()> {
+        while pos.is_none() {
+            if self.chunk_index == 0 {
+                self.pos = None;
+                return Ok(());
+            }
+
+            self.load(self.chunk_index - 1)?;
+            pos = self.chunk.len().checked_sub(1);
+        }
+
+        self.pos = pos;
+        Ok(())
+    }
+}
+
+impl<S: SSTableReader> SeekableSource for SSTableSource<'_, S> {
+    fn seek_to_first(&mut self) -> io::Result<()> {
+        if self.chunks.is_empty() {
+            self.pos = None;
+            return Ok(());
+        }
+
+        self.load(0)?;
+        self.settle_forward(0)
+    }
+
+    fn seek_to_last(&mut self) -> io::Result<()> {
+        if self.chunks.is_empty() {
+            self.pos = None;
+            return Ok(());
+        }
+
+        self.load(self.chunks.len() - 1)?;
+        self.settle_backward(self.chunk.len().checked_sub(1))
+    }
+
+    fn seek(&mut self, key: &str) -> io::Result<()> {
+        // The first chunk that ends at or after the key
+        let chunk_index = self
+            .chunks
+            .partition_point(|it| self.comparator.compare(&it.max_key, key).is_lt());
+
+        if chunk_index == self.chunks.len() {
+            self.pos = None;
+            return Ok(());
+        }
+
+        self.load(chunk_index)?;
+        self.settle_forward(lower_bound(&self.chunk, key, self.comparator))
+    }
+
+    fn seek_for_prev(&mut self, key: &str) -> io::Result<()> {
+        // The last chunk that starts at or before the key
+        let Some(chunk_index) = self
+            .chunks
+            .partition_point(|it| self.comparator.compare(&it.min_key, key).is_le())
+            .checked_sub(1)
+        else {
+            self.pos = None;
+            return Ok(());
+        };
+
+        self.load(chunk_index)?;
+        self.settle_backward(upper_bound(&self.chunk, key, self.comparator).checked_sub(1))
+    }
+
+    fn next(&mut self) -> io::Result<()> {
+        match self.pos {
+            Some(pos) => self.settle_forward(pos + 1),
+            None => Ok(()),
+        }
+    }
+
+    fn prev(&mut self) -> io::Result<()> {
+        match self.pos {
+            Some(pos) => self.settle_backward(pos.checked_sub(1)),
+            None => Ok(()),
+        }
+    }
+
+    fn current(&self) -> Option<(&str, &Entry)> {
+        self.pos.map(|it| {
+            let (key, entry) = &self.chunk[it];
+            (key.as_str(), entry)
+        })
+    }
+}
+
+/// Sources ordered by their current key, smallest first when moving forward and largest first
+/// when moving backward. Ties on the key are broken by the source index, so versions of a key are
+/// popped newest first.
+enum Heap<'c> {
+    Forward(BinaryHeap<Reverse<(KeyOnlyOrd<'c, ()>, usize)>>),
+    Backward(BinaryHeap<(KeyOnlyOrd<'c, ()>, Reverse<usize>)>),
+}
+
+impl<'c> Heap<'c> {
+    fn push(&mut self, key: String, idx: usize, comparator: &'c dyn Comparator) {
+        let key = KeyOnlyOrd((key, ()), comparator);
+
+        match self {
+            Heap::Forward(heap) => heap.push(Reverse((key, idx))),
+            Heap::Backward(heap) => heap.push((key, Reverse(idx))),
+        }
+    }
+
+    fn peek_key(&self) -> Option<&str> {
+        match self {
+            Heap::Forward(heap) => heap.peek().map(|Reverse((key, _))| key.0.0.as_str()),
+            Heap::Backward(heap) => heap.peek().map(|(key, _)| key.0.0.as_str()),
+        }
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        match self {
+            Heap::Forward(heap) => heap.pop().map(|Reverse((_, idx))| idx),
+            Heap::Backward(heap) => heap.pop().map(|(_, Reverse(idx))| idx),
+        }
+    }
+}
+
+/// An iterator over the live values of a store that can be positioned at any key and moved in
+/// both directions.
+///
+/// It reads from a copy of the memtable and from the SSTables that were live when it was created,
+/// so writes made afterwards are not seen. A new iterator is not positioned at any key; call one
+/// of the seek methods first.
+///
+/// If a method fails, the iterator is left without a current entry until it is positioned again.
+pub struct StoreIterator<'a> {
+    // Newest first, so versions of a key are collapsed in the right order.
+    sources: Vec<Box<dyn SeekableSource + 'a>>,
+    heap: Heap<'a>,
+
+    comparator: &'a dyn Comparator,
+    operator: Option<&'a dyn MergeOperator>,
+    blobs: &'a BlobReader,
+    now: u64,
+
+    current: Option<(String, Vec<u8>)>,
+    _version: Arc<Version>,
+}
+
+impl<'a> StoreIterator<'a> {
+    pub(crate) fn new(
+        sources: Vec<Box<dyn SeekableSource + 'a>>,
+        comparator: &'a dyn Comparator,
+        operator: Option<&'a dyn MergeOperator>,
+        blobs: &'a BlobReader,
+        now: u64,
+        version: Arc<Version>,
+    ) -> Self {
+        Self {
+            sources,
+            heap: Heap::Forward(BinaryHeap::new()),
+            comparator,
+            operator,
+            blobs,
+            now,
+            current: None,
+            _version: version,
+        }
+    }
+
+    /// Returns true if the iterator is positioned at an entry.
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.current.as_ref().map(|(key, _)| key.as_str())
+    }
+
+    pub fn value(&self) -> Option<&[u8]> {
+        self.current.as_ref().map(|(_, value)| value.as_slice())
+    }
+
+    /// Positions the iterator at the first key of the store.
+    pub fn seek_to_first(&mut self) -> io::Result<()> {
+        self.reposition(true, |source| source.seek_to_first())
+    }
+
+    /// Positions the iterator at the last key of the store.
+    pub fn seek_to_last(&mut self) -> io::Result<()> {
+        self.reposition(false, |source| source.seek_to_last())
+    }
+
+    /// Positions the iterator at the first key at or after `key`.
+    pub fn seek(&mut self, key: &str) -> io::Result<()> {
+        self.reposition(true, |source| source.seek(key))
+    }
+
+    /// Positions the iterator at the last key at or before `key`.
+    pub fn seek_for_prev(&mut self, key: &str) -> io::Result<()> {
+        self.reposition(false, |source| source.seek_for_prev(key))
+    }
+
+    /// Moves the iterator to the following key. Does nothing if the iterator is not valid.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<()> {
+        let Some((key, _)) = self.current.take() else {
+            return Ok(());
+        };
+
+        if matches!(self.heap, Heap::Forward(_)) {
+            // The sources are already past the current key.
+            return self.advance();
+        }
+
+        let comparator = self.comparator;
+
+        self.reposition(true, |source| {
+            source.seek(&key)?;
+
+            if source.current().is_some_and(|(it, _)| comparator.compare(it, &key).is_eq()) {
+                source.next()?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Moves the iterator to the preceding key. Does nothing if the iterator is not valid.
+    pub fn prev(&mut self) -> io::Result<()> {
+        let Some((key, _)) = self.current.take() else {
+            return Ok(());
+        };
+
+        if matches!(self.heap, Heap::Backward(_)) {
+            return self.advance();
+        }
+
+        let comparator = self.comparator;
+
+        self.reposition(false, |source| {
+            source.seek_for_prev(&key)?;
+
+            if source.current().is_some_and(|(it, _)| comparator.compare(it, &key).is_eq()) {
+                source.prev()?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Positions every source with `position` and moves to the nearest live value in the given
+    /// direction.
+    fn reposition<F>(&mut self, forward: bool, mut position: F) -> io::Result<()>
+    where
+        F: FnMut(&mut dyn SeekableSource) -> io::Result<()>,
+    {
+        self.current = None;
+
+        self.heap = if forward {
+            Heap::Forward(BinaryHeap::new())
+        } else {
+            Heap::Backward(BinaryHeap::new())
+        };
+
+        for (idx, source) in self.sources.iter_mut().enumerate() {
+            if let Err(e) = position(source.as_mut()) {
+                self.heap = Heap::Forward(BinaryHeap::new());
+                return Err(e);
+            }
+
+            if let Some((key, _)) = source.current() {
+                self.heap.push(key.to_owned(), idx, self.comparator);
+            }
+        }
+
+        self.advance()
+    }
+
+    /// Collapses the versions of the key at the top of the heap, moving on to the next key in the
+    /// direction of the heap until one has a live value.
+    fn advance(&mut self) -> io::Result<()> {
+        let forward = matches!(self.heap, Heap::Forward(_));
+
+        while let Some(key) = self.heap.peek_key().map(str::to_owned) {
+            let mut versions = Vec::new();
+
+            while self.heap.peek_key().is_some_and(|it| self.comparator.compare(it, &key).is_eq()) {
+                // SAFETY: we just peeked an item
+                let idx = self.heap.pop().unwrap();
+                let source = &mut self.sources[idx];
+
+                if let Some((_, entry)) = source.current() {
+                    versions.push(entry.clone());
+                }
+
+                let moved = if forward { source.next() } else { source.prev() };
+
+                if let Err(e) = moved {
+                    self.heap = Heap::Forward(BinaryHeap::new());
+                    return Err(e);
+                }
+
+                if let Some((next, _)) = source.current() {
+                    self.heap.push(next.to_owned(), idx, self.comparator);
+                }
+            }
+
+            let versions = self.blobs.resolve_versions(versions)?;
+            let entry = collapse(self.operator, &key, versions, self.now, true)?;
+
+            // An expired entry still shadows older versions of the key.
+            if !entry.is_expired(self.now) && let Some(value) = entry.into_value() {
+                self.current = Some((key, value));
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::comparator::BytewiseComparator;
+    use crate::sstable::reader::SSTChunkIterator;
+
+    struct MockReader(Vec<Chunk>);
+
+    impl SSTableReader for MockReader {
+        type ChunkIterator = SSTChunkIterator;
+
+        fn list_chunks(&self, _: u64) -> io::Result<Vec<ChunkDesc>> {
+            Ok(self
+                .0
+                .iter()
+                .enumerate()
+                .map(|(index, chunk)| ChunkDesc {
+                    index,
+                    pos: 0,
+                    min_key: chunk.first().unwrap().0.clone(),
+                    max_key: chunk.last().unwrap().0.clone(),
+                })
+                .collect())
+        }
+
+        fn read_chunk(&self, _: u64, chunk_index: usize) -> io::Result<Chunk> {
+            Ok(self.0[chunk_index].clone())
+        }
+
+        fn chunk_iterator(&self, _: u64) -> io::Result<Self::ChunkIterator> {
+            unimplemented!()
+        }
+    }
+
+    fn chunk(keys: &[&str]) -> Chunk {
+        keys.iter()
+            .map(|key| (key.to_string(), Entry::new(b"sst".to_vec())))
+            .collect()
+    }
+
+    #[test]
+    fn test_iterator_crosses_chunks_in_both_directions() {
+        let comparator = BytewiseComparator;
+        let reader = MockReader(vec![chunk(&["a", "c"]), chunk(&["e", "g"]), chunk(&["i"])]);
+
+        let memtable = VecSource::new(
+            vec![
+                ("b".to_owned(), Entry::new(b"memtable".to_vec())),
+                ("e".to_owned(), Entry::Tombstone),
+            ],
+            &comparator,
+        );
+
+        let sources: Vec<Box<dyn SeekableSource>> = vec![
+            Box::new(memtable),
+            Box::new(SSTableSource::open(&reader, 0, &comparator).unwrap()),
+        ];
+
+        let blobs = BlobReader::new(PathBuf::new());
+        let mut iter = StoreIterator::new(sources, &comparator, None, &blobs, 0, Arc::new(Version::default()));
+
+        let mut keys = Vec::new();
+        iter.seek_to_last().unwrap();
+        while let Some(key) = iter.key() {
+            keys.push(key.to_owned());
+            iter.prev().unwrap();
+        }
+        assert_eq!(keys, vec!["i", "g", "c", "b", "a"]);
+
+        iter.seek("d").unwrap();
+        assert_eq!(iter.key(), Some("g"));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), Some("c"));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), Some("g"));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), Some("i"));
+
+        iter.seek_for_prev("h").unwrap();
+        assert_eq!(iter.key(), Some("g"));
+        iter.seek_for_prev("e").unwrap();
+        assert_eq!(iter.key(), Some("c"));
+        iter.seek_to_first().unwrap();
+        iter.next().unwrap();
+        assert_eq!(iter.value(), Some(&b"memtable"[..]));
+    }
+}
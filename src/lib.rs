@@ -1,17 +1,53 @@
 mod async_store_impl;
+mod backup;
+mod blob;
+mod checkpoint;
+mod compaction_filter;
+mod comparator;
 mod crc;
 mod datastructure;
+mod dump;
+mod entry;
+mod inspect;
 mod io_ext;
+mod iterator;
 mod lsm_tree;
 mod manifest;
+mod memtable;
+mod merge_operator;
+mod options;
+mod prefix_extractor;
+mod repair;
+mod sst_file_writer;
 mod sstable;
 mod store_impl;
 mod util;
+mod verify;
 mod wal;
+mod write_batch;
 
 mod store;
 mod async_store;
 
 pub use store::Store;
+pub use backup::{BackupEngine, BackupInfo, RecoveryTarget};
 pub use async_store::AsyncStore;
-pub use store_impl::{DefaultStore, make_store};
+pub use store_impl::{
+    ColumnFamilyHandle, DEFAULT_COLUMN_FAMILY_NAME, DefaultStore, make_store,
+    make_store_with_column_families, make_store_with_options, open_read_only,
+};
+pub use iterator::StoreIterator;
+pub use write_batch::WriteBatch;
+pub use sst_file_writer::SstFileWriter;
+pub use verify::{VerifyReport, verify};
+pub use repair::{RepairReport, repair};
+pub use inspect::{Inspection, inspect_manifest, inspect_sst, inspect_wal};
+pub use dump::{DumpHeader, export_dump, import_dump, read_dump_header};
+pub use options::{Options, RangeOptions};
+pub use compaction_filter::{CompactionContext, CompactionFilter, Decision};
+pub use comparator::{
+    BytewiseComparator, Comparator, NumericSuffixComparator, ReverseBytewiseComparator,
+};
+pub use prefix_extractor::{DelimitedPrefix, FixedPrefix, PrefixExtractor};
+pub use merge_operator::{AppendOperator, MergeOperator, U64AddOperator};
+pub use lsm_tree::MAX_LEVEL;
@@ -1,53 +1,58 @@
 use std::{
-    collections::BTreeMap,
     fs::{self, File},
     io,
-    ops::RangeBounds,
+    ops::{Bound::*, RangeBounds},
     path::{Path, PathBuf},
 };
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::atomic::AtomicU8;
 use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use fs2::FileExt;
 
-use crate::{
-    Store,
-    sstable::reader::{CachedSSTableReader, FsSSTReader, SSTableReader},
-};
+use crate::blob::{BlobReader, BlobRef, BlobWriter, parse_blob_filename};
+use crate::sstable::reader::{CachedSSTableReader, FsSSTReader, SSTableReader};
 use crate::manifest::Manifest;
+use crate::manifest::ManifestUpdate;
 use crate::manifest::SSTableDesc;
+use crate::manifest::Version;
+use crate::sstable::reader::SSTChunkIterator;
 use crate::sstable::writer::SSTableWriter;
-use crate::store::Cursor;
-use crate::util::merge_sorted_uniq_cursor;
+use crate::sstable::{Compression, SSTableProperties, is_sst_tmp_filename, parse_sst_filename, sst_file_path};
+use crate::checkpoint::link_or_copy;
+use crate::compaction_filter::{CompactionContext, Decision};
+use crate::comparator::{Comparator, range_contains};
+use crate::entry::{Entry, EntryCursor, now_millis};
+use crate::iterator::SSTableSource;
+use crate::options::Options;
+use crate::merge_operator::collapse;
+use crate::util::merge_sorted_grouped_cursor;
 
 const DB_LOCK_FILENAME: &str = ".lock";
 
 // FIXME: This is very arbitrarily chosen
 const COMPACT_EVERY_N_SSTABLES: u8 = 25;
 
-const MAX_LEVEL: u8 = 3;
+/// Deepest level of the LSM tree.
+pub const MAX_LEVEL: u8 = 3;
 
-pub struct LSMTree<S: SSTableReader> {
-    directory: PathBuf,
-    lock: Option<File>,
+/// Share of garbage bytes in a blob file at which compactions start moving its remaining values
+/// out of it, so it can eventually be deleted.
+const BLOB_GC_GARBAGE_RATIO: f64 = 0.5;
 
-    manifest: Manifest,
-    sstable_reader: S,
-
-    // Number of level-0 SSTables.
-    // This is updated everytime we read manifest and
-    // may be 0 if we haven't read it yet.
-    level_zero_count: AtomicU8,
-}
-
-fn sst_file_path(directory: &Path, id: u64) -> PathBuf {
-    directory.join(format!("sstable_{id:016}.sst"))
+/// Exclusive lock on a store directory, released when dropped.
+pub(crate) struct DirectoryLock {
+    directory: PathBuf,
+    lock: File,
 }
 
-impl LSMTree<CachedSSTableReader<FsSSTReader>> {
-    pub fn new(directory: PathBuf) -> io::Result<Self> {
+impl DirectoryLock {
+    pub fn acquire(directory: &Path) -> io::Result<Self> {
         if !directory.exists() {
-            fs::create_dir_all(&directory)?;
+            fs::create_dir_all(directory)?;
         }
 
         let lock = File::options()
@@ -59,57 +64,357 @@ impl LSMTree<CachedSSTableReader<FsSSTReader>> {
 
         lock.try_lock_exclusive()?;
 
-        let manifest = Manifest::open(&directory)?;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            lock,
+        })
+    }
+}
+
+impl Drop for DirectoryLock {
+    fn drop(&mut self) {
+        let _ = fs2::FileExt::unlock(&self.lock);
+
+        let res = fs::remove_file(self.directory.join(DB_LOCK_FILENAME));
+
+        if let Err(e) = res {
+            eprintln!("Error removing lock file: {e}");
+        }
+    }
+}
+
+/// The SSTables of a single column family.
+///
+/// Column families of a store share the manifest, but each is compacted on its own.
+pub struct LSMTree<S: SSTableReader> {
+    directory: PathBuf,
+    family: u32,
+
+    manifest: Arc<Manifest>,
+    sstable_reader: S,
+    blob_reader: BlobReader,
+
+    // Number of level-0 SSTables.
+    // This is updated everytime we read manifest and
+    // may be 0 if we haven't read it yet.
+    level_zero_count: AtomicU8,
+
+    // Held while picking and merging SSTables so concurrent compactions never pick the same ones.
+    compaction_lock: Mutex<()>,
+
+    comparator: Arc<dyn Comparator>,
+    options: Options,
+}
+
+impl LSMTree<CachedSSTableReader<FsSSTReader>> {
+    pub fn new(directory: PathBuf, manifest: Arc<Manifest>, family: u32, options: Options) -> Self {
         let sstable_reader = FsSSTReader::new(directory.clone()).cached();
+        let blob_reader = BlobReader::new(directory.clone());
 
-        Ok(Self {
+        Self {
             directory,
-            lock: Some(lock),
+            family,
             manifest,
             sstable_reader,
+            blob_reader,
             level_zero_count: AtomicU8::new(0),
-        })
+            compaction_lock: Mutex::new(()),
+            comparator: options.comparator(),
+            options,
+        }
     }
 }
 
-impl<S: SSTableReader> LSMTree<S> {
-    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
-        let candidate_ssts = self.manifest.get_candidate_sstables_for_key(key);
+/// A cursor over SSTables that keeps the version it was created from alive, so the files it reads
+/// are not deleted by a concurrent compaction.
+struct PinnedCursor<C: EntryCursor> {
+    inner: C,
+    _version: Arc<Version>,
+}
 
-        for candidate in candidate_ssts {
+impl<C: EntryCursor> Iterator for PinnedCursor<C> {
+    type Item = C::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Removes SST and blob files that are not referenced by the manifest.
+///
+/// These are left behind when the process dies between writing an SST and recording it in the
+/// manifest, or between removing a compacted SST from the manifest and deleting its file. Partially
+/// written SSTs are removed as well.
+pub(crate) fn remove_orphaned_files(directory: &Path, manifest: &Manifest) -> io::Result<()> {
+    let live = manifest.get_sstable_ids();
+    let live_blob_files = manifest.get_blob_file_ids();
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+
+        let filename = entry.file_name();
+        let Some(filename) = filename.to_str() else {
+            continue;
+        };
+
+        let is_orphan = match (parse_sst_filename(filename), parse_blob_filename(filename)) {
+            (Some(id), _) => !live.contains(&id),
+            (None, Some(id)) => !live_blob_files.contains(&id),
+            (None, None) => is_sst_tmp_filename(filename),
+        };
+
+        if is_orphan && let Err(e) = fs::remove_file(entry.path()) {
+            eprintln!("Error removing orphaned sstable: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+impl<S: SSTableReader> LSMTree<S> {
+    /// Returns the latest entry for `key`, which may have expired.
+    ///
+    /// Merge operands are combined with the value they apply to. If there is no such value, the
+    /// operands are returned as a single merge record.
+    pub fn get(&self, key: &str) -> io::Result<Option<Entry>> {
+        // Holding on to the version keeps compaction from deleting the files we are about to read.
+        let version = self.manifest.current();
+        let candidate_ssts = version.get_candidate_sstables_for_key(self.family, key, self.comparator.as_ref());
+
+        let mut versions = Vec::new();
+
+        'candidates: for candidate in candidate_ssts {
             let candidate_chunks = self
                 .sstable_reader
-                .get_candidate_chunks_for_key(candidate.id, key)?;
+                .get_candidate_chunks_for_key(candidate.id, key, self.comparator.as_ref())?;
 
             for chunk in candidate_chunks {
                 let chunk_data = self.sstable_reader.read_chunk(candidate.id, chunk.index)?;
 
-                if let Ok(value) = chunk_data.binary_search_by_key(&key, |(k, _)| k) {
-                    return Ok(Some(chunk_data[value].1.clone()));
+                if let Ok(value) = chunk_data.binary_search_by(|(k, _)| self.comparator.compare(k, key)) {
+                    let entry = chunk_data[value].1.clone();
+                    let is_merge = matches!(entry, Entry::Merge(_));
+
+                    versions.push(entry);
+
+                    // Older versions only matter if there are operands to apply to them.
+                    if !is_merge {
+                        break 'candidates;
+                    }
+
+                    continue 'candidates;
                 }
             }
         }
 
-        Ok(None)
+        if versions.is_empty() {
+            return Ok(None);
+        }
+
+        let versions = self.blob_reader.resolve_versions(versions)?;
+
+        let operator = self.options.merge_operator.as_deref();
+        collapse(operator, key, versions, now_millis(), false).map(Some)
     }
 
+    /// Returns the latest entry of each of `keys`, in the order of `keys`, like [`LSMTree::get`].
+    ///
+    /// The keys are looked up together, so the chunk directory and every chunk of an SST is read
+    /// at most once no matter how many of the keys it holds.
+    pub fn multi_get(&self, keys: &[&str]) -> io::Result<Vec<Option<Entry>>> {
+        let comparator = self.comparator.as_ref();
+
+        // Distinct keys in sorted order, along with their versions, newest first.
+        let mut lookups: Vec<(&str, Vec<Entry>)> = keys
+            .iter()
+            .map(|key| (*key, Vec::new()))
+            .collect();
+        lookups.sort_by(|(a, _), (b, _)| comparator.compare(a, b));
+        lookups.dedup_by(|(a, _), (b, _)| comparator.compare(a, b).is_eq());
+
+        // A key is resolved once a version other than merge operands is found.
+        let is_pending = |versions: &Vec<Entry>| {
+            versions.last().is_none_or(|it| matches!(it, Entry::Merge(_)))
+        };
+
+        let version = self.manifest.current();
+
+        for candidate in version.get_candidate_sstables_for_range(self.family, .., comparator) {
+            let mut pending = lookups
+                .iter_mut()
+                .filter(|(key, versions)| {
+                    is_pending(versions)
+                        && comparator.compare(&candidate.min_key, key).is_le()
+                        && comparator.compare(&candidate.max_key, key).is_ge()
+                })
+                .peekable();
+
+            if pending.peek().is_none() {
+                continue;
+            }
+
+            let chunks = self.sstable_reader.list_chunks(candidate.id)?;
+            let mut chunks = chunks.iter().peekable();
+
+            while let Some((key, versions)) = pending.next() {
+                // Both the chunks and the keys are sorted, so the chunks before this key can't
+                // hold any of the remaining keys either.
+                while chunks.next_if(|chunk| comparator.compare(&chunk.max_key, key).is_lt()).is_some() {}
+
+                let Some(chunk) = chunks.peek() else {
+                    break;
+                };
+
+                if comparator.compare(&chunk.min_key, key).is_gt() {
+                    continue;
+                }
+
+                let chunk_data = self.sstable_reader.read_chunk(candidate.id, chunk.index)?;
+                let found = |key: &str, versions: &mut Vec<Entry>| {
+                    if let Ok(idx) = chunk_data.binary_search_by(|(k, _)| comparator.compare(k, key)) {
+                        versions.push(chunk_data[idx].1.clone());
+                    }
+                };
+
+                found(key, versions);
+
+                // Every other key in the chunk is looked up in the chunk that was just read.
+                let in_chunk = |(key, _): &&mut (&str, Vec<Entry>)| comparator.compare(&chunk.max_key, key).is_ge();
+
+                while let Some((key, versions)) = pending.next_if(in_chunk) {
+                    found(key, versions);
+                }
+            }
+        }
+
+        let operator = self.options.merge_operator.as_deref();
+        let now = now_millis();
+
+        let mut sorted_keys = Vec::with_capacity(lookups.len());
+        let mut entries = Vec::with_capacity(lookups.len());
+
+        for (key, versions) in lookups {
+            let entry = match versions.is_empty() {
+                true => None,
+                false => {
+                    let versions = self.blob_reader.resolve_versions(versions)?;
+                    Some(collapse(operator, key, versions, now, false)?)
+                }
+            };
+
+            sorted_keys.push(key);
+            entries.push(entry);
+        }
+
+        Ok(keys
+            .iter()
+            .map(|key| {
+                // SAFETY: every key was looked up
+                let idx = sorted_keys
+                    .binary_search_by(|it| comparator.compare(it, key))
+                    .unwrap();
+
+                entries[idx].clone()
+            })
+            .collect())
+    }
+
+    /// Returns the latest entry of every key in `range`, including expired ones. Merge operands are
+    /// combined like they are by [`LSMTree::get`].
+    ///
+    /// If only keys starting with `prefix` are wanted, SSTs and chunks that the prefix bloom filters
+    /// rule out are skipped. Keys without the prefix may still be returned.
     pub fn get_range<'a, R: RangeBounds<str> + Clone + 'a>(
         &'a self,
         range: R,
-    ) -> io::Result<impl Cursor + 'a> {
-        let candidate_ssts = self
-            .manifest
-            .get_candidate_sstables_for_range(range.clone());
+        prefix: Option<&str>,
+    ) -> io::Result<impl EntryCursor + 'a> {
+        let (version, iters) = self.range_cursors(range, prefix, false)?;
+
+        let operator = self.options.merge_operator.as_deref();
+        let now = now_millis();
+
+        let merged = merge_sorted_grouped_cursor(iters, self.comparator.as_ref()).map(move |item| {
+            let (key, versions) = item?;
+            let versions = self.blob_reader.resolve_versions(versions)?;
+            let entry = collapse(operator, &key, versions, now, false)?;
+
+            Ok((key, entry))
+        });
+
+        Ok(PinnedCursor {
+            inner: merged,
+            _version: version,
+        })
+    }
+
+    /// Returns the latest version of every key in `range` with its value or operands left empty,
+    /// without reading them from the SSTs. Merge operands are not combined with older versions.
+    pub fn get_range_keys<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+    ) -> io::Result<impl EntryCursor + 'a> {
+        let (version, iters) = self.range_cursors(range, None, true)?;
+
+        let merged = merge_sorted_grouped_cursor(iters, self.comparator.as_ref()).map(|item| {
+            let (key, versions) = item?;
+
+            // SAFETY: every key is yielded with at least one version
+            let latest = versions.into_iter().next().unwrap();
+
+            Ok((key, latest))
+        });
+
+        Ok(PinnedCursor {
+            inner: merged,
+            _version: version,
+        })
+    }
+
+    /// Returns a cursor over the entries in `range` of every SSTable, newest first, along with the
+    /// version that keeps them from being deleted while they are read. See [`LSMTree::get_range`]
+    /// for `prefix` and [`SSTableReader::read_chunk_keys`] for `keys_only`.
+    fn range_cursors<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+        prefix: Option<&str>,
+        keys_only: bool,
+    ) -> io::Result<(Arc<Version>, Vec<impl EntryCursor + 'a>)> {
+        let version = self.manifest.current();
+        let candidate_ssts = version.get_candidate_sstables_for_range(
+            self.family,
+            range.clone(),
+            self.comparator.as_ref(),
+        );
+
+        // Every key starting with the scanned prefix has the same extracted prefix.
+        let filter_prefix = self
+            .options
+            .prefix_extractor
+            .as_deref()
+            .and_then(|extractor| Some((extractor.name(), extractor.prefix(prefix?)?)));
 
         let mut iters = Vec::with_capacity(candidate_ssts.len());
 
         for candidate in candidate_ssts {
             let candidate_id = candidate.id;
 
-            let candidate_chunks = self
+            let mut candidate_chunks = self
                 .sstable_reader
-                .get_candidate_chunks_for_range(candidate_id, range.clone())?
-                .into_iter();
+                .get_candidate_chunks_for_range(candidate_id, range.clone(), self.comparator.as_ref())?;
+
+            if let Some((extractor, prefix)) = filter_prefix
+                && let Some(filter) = self.sstable_reader.get_prefix_filter(candidate_id)?
+                && filter.extractor == extractor
+            {
+                candidate_chunks.retain(|chunk| filter.may_contain(chunk.index, prefix));
+            }
+
+            if candidate_chunks.is_empty() {
+                continue;
+            }
+
+            let candidate_chunks = candidate_chunks.into_iter();
 
             let range = range.clone();
 
@@ -119,13 +424,15 @@ impl<S: SSTableReader> LSMTree<S> {
                 // FIXME: Evaluate if it should be OK to cache range queries.
                 // especially when they are large. I suspect this could pollute
                 // the cache with pages that might never be used again.
-                let chunk = self.sstable_reader
-                    .read_chunk(candidate_id, chunk_desc.index);
+                let chunk = match keys_only {
+                    true => self.sstable_reader.read_chunk_keys(candidate_id, chunk_desc.index),
+                    false => self.sstable_reader.read_chunk(candidate_id, chunk_desc.index),
+                };
 
-                let iter: Box<dyn Cursor> = match chunk {
+                let iter: Box<dyn EntryCursor + 'a> = match chunk {
                     Ok(chunk) =>
                         Box::new(chunk.into_iter()
-                            .filter(move |(key, _)| range.contains(key.as_str()))
+                            .filter(move |(key, _)| range_contains(self.comparator.as_ref(), &range, key))
                             .map(Ok)),
 
                     Err(e) => Box::new(std::iter::once(Err(e))),
@@ -135,32 +442,105 @@ impl<S: SSTableReader> LSMTree<S> {
             }))
         }
 
-        Ok(merge_sorted_uniq_cursor(iters))
+        Ok((version, iters))
     }
 
-    pub fn write_sstable(&self, source: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+    /// Opens every SSTable of the column family as a seekable source, newest first, along with the
+    /// version that keeps them from being deleted while they are read.
+    pub(crate) fn seekable_sources(&self) -> io::Result<(Arc<Version>, Vec<SSTableSource<'_, S>>)> {
+        let version = self.manifest.current();
+
+        let sources = version
+            .get_candidate_sstables_for_range(self.family, .., self.comparator.as_ref())
+            .into_iter()
+            .map(|sstable| SSTableSource::open(&self.sstable_reader, sstable.id, self.comparator.as_ref()))
+            .collect::<io::Result<_>>()?;
+
+        Ok((version, sources))
+    }
+
+    /// Reads values the SSTs of the column family store in blob files.
+    pub(crate) fn blob_reader(&self) -> &BlobReader {
+        &self.blob_reader
+    }
+
+    fn open_writer(&self, id: u64) -> io::Result<SSTableWriter> {
+        let mut writer = SSTableWriter::open(&self.directory, id)?;
+
+        if let Some(prefix_extractor) = self.options.prefix_extractor.clone() {
+            writer.set_prefix_extractor(prefix_extractor);
+        }
+
+        Ok(writer)
+    }
+
+    /// Writes `source`, which must be sorted by the comparator, to a new level 0 SSTable.
+    #[cfg(test)]
+    pub fn write_sstable<'e, K, I>(&self, source: I) -> io::Result<()>
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = (K, &'e Entry)>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.write_flushed_sstable(source, 0)
+    }
+
+    /// Writes `source`, which must be sorted by the comparator, to a new level 0 SSTable. The
+    /// manifest update adding it records that the entries of the column family logged up to the
+    /// WAL record `wal_sequence` are flushed.
+    pub fn write_flushed_sstable<'e, K, I>(&self, source: I, wal_sequence: u64) -> io::Result<()>
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = (K, &'e Entry)>,
+        I::IntoIter: ExactSizeIterator,
+    {
         self.compact()?;
 
-        let max_key = source
-            .iter()
-            .next_back()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Source is empty"))?
-            .0;
+        let source = source.into_iter();
 
-        let min_key = source
-            .iter()
-            .next()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Source is empty"))?
-            .0;
+        if source.len() == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Source is empty"));
+        }
 
         let mut update = self.manifest.start_update();
-        let id = update.add(0, min_key, max_key);
+        let id = update.allocate_id();
+        let (smallest_flush_seq, largest_flush_seq) =
+            update.allocate_flush_sequence(source.len() as u64);
 
-        let mut writer = SSTableWriter::open(&self.directory, id)?;
-        for (key, value) in source.iter() {
-            writer.write(key, value)?;
+        let mut written_range: Option<(String, String)> = None;
+
+        let mut blob_writer = None;
+
+        let mut writer = self.open_writer(id)?;
+        writer.set_flush_sequence_range(smallest_flush_seq, largest_flush_seq);
+        for (key, entry) in source {
+            let key = key.as_ref();
+            let separated = self.separate_value(&mut update, &mut blob_writer, key, entry)?;
+            writer.write(key, separated.as_ref().unwrap_or(entry))?;
+
+            match written_range.as_mut() {
+                Some((_, max)) => *max = key.to_owned(),
+                None => written_range = Some((key.to_owned(), key.to_owned())),
+            }
+        }
+        let props = writer.finalize()?;
+
+        if let Some(blob_writer) = blob_writer {
+            update.add_blob_file(blob_writer.finalize()?);
         }
-        writer.finalize()?;
+
+        // SAFETY: the source was checked to not be empty
+        let (min_key, max_key) = written_range.unwrap();
+
+        update.add_sstable(SSTableDesc {
+            id,
+            family: self.family,
+            level: 0,
+            min_key,
+            max_key,
+            props,
+        });
+        update.set_flushed_wal_sequence(self.family, wal_sequence);
         self.manifest.update(update)?;
 
         self.level_zero_count.fetch_add(1, Ordering::Relaxed);
@@ -168,6 +548,142 @@ impl<S: SSTableReader> LSMTree<S> {
         Ok(())
     }
 
+    /// Adds the SSTs at `paths`, written by [`crate::SstFileWriter`], to the column family with a
+    /// single manifest update.
+    ///
+    /// Each SST is placed at the level of the shallowest SST its keys overlap, counting the ones
+    /// ingested before it, or at the deepest level if there is none. Within a level newer SSTs
+    /// take precedence, so its entries shadow every existing version of their keys while
+    /// compactions have as little of it to move down as possible.
+    pub fn ingest_sstables(&self, paths: &[&Path]) -> io::Result<()> {
+        // Keeps compactions from adding SSTs the levels were picked without.
+        let _lock = self.compaction_lock.lock().unwrap();
+
+        let mut update = self.manifest.start_update();
+        let mut linked = Vec::with_capacity(paths.len());
+
+        let result = self
+            .add_external_sstables(&mut update, paths, &mut linked)
+            .and_then(|_| self.manifest.update(update));
+
+        if result.is_err() {
+            for id in linked {
+                if let Err(e) = fs::remove_file(sst_file_path(&self.directory, id)) {
+                    eprintln!("Error removing ingested sstable: {e}");
+                }
+            }
+        }
+
+        result?;
+
+        let level_zero_count = self.manifest.current().get_sstables_at_level(self.family, 0).len();
+        self.level_zero_count.store(level_zero_count as u8, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Links the SSTs into the store directory and records them in `update`, along with the IDs
+    /// of the files linked so far in `linked`.
+    fn add_external_sstables(
+        &self,
+        update: &mut ManifestUpdate,
+        paths: &[&Path],
+        linked: &mut Vec<u64>,
+    ) -> io::Result<()> {
+        let mut placed = self
+            .manifest
+            .current()
+            .get_candidate_sstables_for_range(self.family, .., self.comparator.as_ref());
+
+        for path in paths {
+            let (min_key, max_key, entry_count, tombstone_count) = self.validate_external_sstable(path)?;
+
+            let level = placed
+                .iter()
+                .filter(|it| {
+                    self.comparator.compare(&it.min_key, &max_key).is_le()
+                        && self.comparator.compare(&it.max_key, &min_key).is_ge()
+                })
+                .map(|it| it.level)
+                .min()
+                .unwrap_or(MAX_LEVEL);
+
+            let id = update.allocate_id();
+            let (smallest_flush_seq, largest_flush_seq) = update.allocate_flush_sequence(entry_count);
+
+            let sst_path = sst_file_path(&self.directory, id);
+            link_or_copy(path, &sst_path)?;
+            linked.push(id);
+
+            let created_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+
+            let desc = SSTableDesc {
+                id,
+                family: self.family,
+                level,
+                min_key,
+                max_key,
+                props: SSTableProperties {
+                    file_size: fs::metadata(&sst_path)?.len(),
+                    entry_count,
+                    tombstone_count,
+                    smallest_flush_seq,
+                    largest_flush_seq,
+                    created_at,
+                    compression: Compression::None,
+                },
+            };
+
+            placed.push(desc.clone());
+            update.add_sstable(desc);
+        }
+
+        Ok(())
+    }
+
+    /// Reads an SST from outside the store, checking that its keys are sorted by the comparator
+    /// and that it doesn't refer to blob files. Returns its key range, number of entries and
+    /// number of tombstones.
+    fn validate_external_sstable(&self, path: &Path) -> io::Result<(String, String, u64, u64)> {
+        let invalid = |reason: &str| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{path:?} can't be ingested: {reason}"))
+        };
+
+        let mut range: Option<(String, String)> = None;
+        let mut entry_count = 0;
+        let mut tombstone_count = 0;
+
+        for chunk in SSTChunkIterator::open(path.to_path_buf())? {
+            for (key, entry) in chunk? {
+                if let Some((_, last_key)) = &range
+                    && self.comparator.compare(last_key, &key).is_ge()
+                {
+                    return Err(invalid("its keys are not sorted by the comparator of the store"));
+                }
+
+                match entry {
+                    Entry::Blob { .. } => return Err(invalid("it refers to blob files")),
+                    Entry::Tombstone => tombstone_count += 1,
+                    _ => {}
+                }
+
+                entry_count += 1;
+
+                match range.as_mut() {
+                    Some((_, max)) => *max = key,
+                    None => range = Some((key.clone(), key)),
+                }
+            }
+        }
+
+        let (min_key, max_key) = range.ok_or_else(|| invalid("it has no entries"))?;
+
+        Ok((min_key, max_key, entry_count, tombstone_count))
+    }
+
     pub fn compact(&self) -> io::Result<()> {
         let diff = COMPACT_EVERY_N_SSTABLES.saturating_sub(self.level_zero_count.load(Ordering::Relaxed));
 
@@ -178,6 +694,8 @@ impl<S: SSTableReader> LSMTree<S> {
             return Ok(());
         }
 
+        let _lock = self.compaction_lock.lock().unwrap();
+
         let mut level = 0;
 
         loop {
@@ -191,16 +709,15 @@ impl<S: SSTableReader> LSMTree<S> {
     }
 
     fn compact_level(&self, level: u8) -> io::Result<bool> {
-        let to_compact = self
-            .manifest
-            .get_sstables_at_level(level);
+        let version = self.manifest.current();
+        let to_compact = version.get_sstables_at_level(self.family, level);
 
         if to_compact.len() < COMPACT_EVERY_N_SSTABLES as usize {
             return Ok(false);
         }
 
         let target_level = std::cmp::min(level + 1, MAX_LEVEL);
-        self.merge_ssts(to_compact, target_level)?;
+        self.merge_ssts(to_compact, target_level, false)?;
 
         if level == 0 {
             // We've compacted all level zero sstables, so we reset the count
@@ -210,23 +727,105 @@ impl<S: SSTableReader> LSMTree<S> {
         Ok(true)
     }
 
-    fn merge_ssts(&self, to_merge: Vec<SSTableDesc>, target_level: u8) -> io::Result<()> {
+    /// Merges all SSTables overlapping `range` into a single SSTable at `target_level`.
+    pub fn compact_range<R: RangeBounds<str>>(&self, range: R, target_level: u8) -> io::Result<()> {
+        if target_level > MAX_LEVEL {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("target level must be at most {MAX_LEVEL}"),
+            ));
+        }
+
+        let _lock = self.compaction_lock.lock().unwrap();
+
+        let version = self.manifest.current();
+        let mut to_merge =
+            version.get_candidate_sstables_for_range(self.family, range, self.comparator.as_ref());
+
+        if to_merge.is_empty() {
+            return Ok(());
+        }
+
+        // The merged SSTable spans the key range of all its inputs, which may be wider than the
+        // requested range. Pull in everything overlapping that wider range as well, otherwise the
+        // merged SSTable could shadow newer entries of an SSTable left out, or be shadowed by older
+        // ones.
+        loop {
+            let min_key = to_merge
+                .iter()
+                .map(|it| it.min_key.as_str())
+                .min_by(|a, b| self.comparator.compare(a, b))
+                .unwrap();
+
+            let max_key = to_merge
+                .iter()
+                .map(|it| it.max_key.as_str())
+                .max_by(|a, b| self.comparator.compare(a, b))
+                .unwrap();
+
+            let expanded = version.get_candidate_sstables_for_range(
+                self.family,
+                (Included(min_key), Included(max_key)),
+                self.comparator.as_ref(),
+            );
+
+            if expanded.len() == to_merge.len() {
+                break;
+            }
+
+            to_merge = expanded;
+        }
+
+        self.merge_ssts(to_merge, target_level, true)?;
+
+        let level_zero_count = self.manifest.current().get_sstables_at_level(self.family, 0).len();
+        self.level_zero_count.store(level_zero_count as u8, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn merge_ssts(
+        &self,
+        to_merge: Vec<SSTableDesc>,
+        target_level: u8,
+        is_manual: bool,
+    ) -> io::Result<()> {
         let min_key = to_merge
             .iter()
             .map(|it| it.min_key.as_str())
-            .min()
+            .min_by(|a, b| self.comparator.compare(a, b))
             .ok_or_else(|| {
-                io::Error::new(io::ErrorKind::Other, "BUG: merge_ssts called with empty Vec<SSTable>")
+                io::Error::other("BUG: merge_ssts called with empty Vec<SSTable>")
             })?;
 
         let max_key = to_merge
             .iter()
             .map(|it| it.max_key.as_str())
-            .max()
+            .max_by(|a, b| self.comparator.compare(a, b))
             // SAFETY: we know that there is at least one element in this vec since we already
             // checked that for computing min
             .unwrap();
 
+        // SSTables at the target level and below hold older entries than the ones being merged.
+        // If none of them overlap, the merged SSTable holds the only versions of its keys.
+        let is_bottommost = self
+            .manifest
+            .current()
+            .get_candidate_sstables_for_range(
+                self.family,
+                (Included(min_key), Included(max_key)),
+                self.comparator.as_ref(),
+            )
+            .iter()
+            .filter(|it| it.level >= target_level)
+            .all(|it| to_merge.iter().any(|merged| merged.id == it.id));
+
+        let context = CompactionContext {
+            target_level,
+            is_manual,
+            is_bottommost,
+        };
+
         let mut sources = Vec::with_capacity(to_merge.len());
 
         for table in to_merge.iter() {
@@ -234,14 +833,14 @@ impl<S: SSTableReader> LSMTree<S> {
             let iter = reader.chunk_iterator(table.id)?;
 
             let flattened = iter.flat_map(|chunk| {
-                chunk.map(|chunk| Box::new(chunk.into_iter().map(Ok)) as Box<dyn Cursor>)
-                    .unwrap_or_else(|e| Box::new(std::iter::once(Err(e))) as Box<dyn Cursor>)
+                chunk.map(|chunk| Box::new(chunk.into_iter().map(Ok)) as Box<dyn EntryCursor>)
+                    .unwrap_or_else(|e| Box::new(std::iter::once(Err(e))) as Box<dyn EntryCursor>)
             });
 
             sources.push(flattened);
         }
 
-        let merged = merge_sorted_uniq_cursor(sources);
+        let merged = merge_sorted_grouped_cursor(sources, self.comparator.as_ref());
 
         let mut update = self.manifest.start_update();
 
@@ -249,68 +848,197 @@ impl<S: SSTableReader> LSMTree<S> {
             update.remove(sstable.id);
         }
 
-        let sst_id = update.add(target_level, &min_key, max_key);
+        let sst_id = update.allocate_id();
 
-        let mut writer = SSTableWriter::open(&self.directory, sst_id)?;
-        for item in merged {
-            let (key, value) = item?;
-            writer.write(key, value)?;
-        }
-        writer.finalize()?;
+        let smallest_flush_seq = to_merge.iter().map(|it| it.props.smallest_flush_seq).min().unwrap();
+        let largest_flush_seq = to_merge.iter().map(|it| it.props.largest_flush_seq).max().unwrap();
 
-        self.manifest.update(update)?;
+        // Expired entries and the compaction filter may drop entries, so the merged SSTable's key
+        // range is tracked as it is written rather than derived from the inputs.
+        let mut written_range: Option<(String, String)> = None;
 
-        for table in to_merge.iter() {
-            let path = sst_file_path(&self.directory, table.id);
-            if let Err(e) = fs::remove_file(path) {
-                eprintln!("Error removing sstable: {e}");
+        let mut writer = self.open_writer(sst_id)?;
+        writer.set_flush_sequence_range(smallest_flush_seq, largest_flush_seq);
+
+        let now = now_millis();
+
+        // Values of blob files that are mostly garbage are moved to the blob file of this
+        // compaction, until none of them are referred to anymore and the file is deleted.
+        let relocate: HashSet<u64> = self
+            .manifest
+            .current()
+            .get_blob_files()
+            .iter()
+            .filter(|it| it.garbage_ratio() >= BLOB_GC_GARBAGE_RATIO)
+            .map(|it| it.id)
+            .collect();
+
+        let mut blob_writer = None;
+
+        // Number and size of the values of each blob file that the merged SST no longer refers to.
+        let mut garbage: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+
+        for item in merged {
+            let (key, versions) = item?;
+
+            let blobs: Vec<BlobRef> = versions
+                .iter()
+                .filter_map(|it| match it {
+                    Entry::Blob { blob, .. } => Some(*blob),
+                    _ => None,
+                })
+                .collect();
+
+            let mut entry = match self.compact_versions(&context, &key, versions, now, is_bottommost)? {
+                Some(entry @ Entry::Blob { blob, .. }) if relocate.contains(&blob.file_id) => {
+                    Some(self.blob_reader.resolve(entry)?)
+                }
+                entry => entry,
+            };
+
+            if let Some(kept) = &entry
+                && let Some(separated) = self.separate_value(&mut update, &mut blob_writer, &key, kept)?
+            {
+                entry = Some(separated);
             }
-        }
 
-        Ok(())
-    }
-}
+            for blob in blobs {
+                if !matches!(&entry, Some(Entry::Blob { blob: kept, .. }) if *kept == blob) {
+                    let (count, bytes) = garbage.entry(blob.file_id).or_default();
+                    *count += 1;
+                    *bytes += blob.size;
+                }
+            }
 
-impl<S: SSTableReader> Drop for LSMTree<S> {
-    fn drop(&mut self) {
-        if let Some(lock) = self.lock.take() {
-            let _ = fs2::FileExt::unlock(&lock);
-            drop(lock);
+            let Some(entry) = entry else {
+                continue;
+            };
 
-            let res = fs::remove_file(self.directory.join(DB_LOCK_FILENAME));
+            writer.write(&key, &entry)?;
 
-            if let Err(e) = res {
-                eprintln!("Error removing lock file: {e}");
+            match written_range.as_mut() {
+                Some((_, max)) => *max = key,
+                None => written_range = Some((key.clone(), key)),
             }
         }
-    }
-}
+        let props = writer.finalize()?;
+        drop(writer);
 
-impl<S: SSTableReader> Store for LSMTree<S> {
-    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
-        LSMTree::get(self, key)
-    }
+        if let Some(blob_writer) = blob_writer {
+            update.add_blob_file(blob_writer.finalize()?);
+        }
 
-    fn get_range<'a, R: RangeBounds<str> + Clone + 'a>(
-        &'a self,
-        range: R,
-    ) -> io::Result<impl Cursor + 'a> {
-        LSMTree::get_range(self, range)
-    }
+        for (file_id, (count, bytes)) in garbage {
+            update.add_blob_garbage(file_id, count, bytes);
+        }
+
+        match written_range {
+            Some((min_key, max_key)) => update.add_sstable(SSTableDesc {
+                id: sst_id,
+                family: self.family,
+                level: target_level,
+                min_key,
+                max_key,
+                props,
+            }),
+
+            // Everything was filtered out, there is no point in keeping an empty SSTable around.
+            None => fs::remove_file(sst_file_path(&self.directory, sst_id))?,
+        }
 
-    fn insert(&self, key: &str, value: &[u8]) -> io::Result<()> {
-        let mut entries = BTreeMap::new();
-        entries.insert(key.to_owned(), value.to_owned());
-        self.write_sstable(&entries)
+        // Merged SSTs are deleted once readers that might still be using them are done.
+        self.manifest.update(update)?;
+
+        Ok(())
     }
 
-    fn insert_batch(&self, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
-        self.write_sstable(entries)
+    /// Collapses the versions of a key being compacted into the entry the merged SST keeps, if
+    /// any, applying expiry and the compaction filter.
+    fn compact_versions(
+        &self,
+        context: &CompactionContext,
+        key: &str,
+        versions: Vec<Entry>,
+        now: u64,
+        is_bottommost: bool,
+    ) -> io::Result<Option<Entry>> {
+        // Values in blob files are only read if there are operands to apply to them.
+        let versions = match versions.first() {
+            Some(Entry::Merge(_)) => self.blob_reader.resolve_versions(versions)?,
+            _ => versions,
+        };
+
+        // Operands without a value to apply to are only combined if no older versions of the
+        // key exist outside this compaction.
+        let operator = self.options.merge_operator.as_deref();
+        let entry = collapse(operator, key, versions, now, is_bottommost)?;
+
+        if entry.is_expired(now) || entry == Entry::Tombstone {
+            if is_bottommost {
+                return Ok(None);
+            }
+
+            // Older versions of the key may still exist below, so the entry has to be kept to
+            // shadow them. The value of an expired entry is never read again though.
+            return Ok(Some(match entry {
+                Entry::Value { expires_at, .. } | Entry::Blob { expires_at, .. } => Entry::Value {
+                    value: Vec::new(),
+                    expires_at,
+                },
+                entry => entry,
+            }));
+        }
+
+        let Some(filter) = &self.options.compaction_filter else {
+            return Ok(Some(entry));
+        };
+
+        // Values in blob files are read for the filter, but stay where they are unless changed.
+        let decision = match &entry {
+            Entry::Value { value, .. } => filter.filter(context, key, value),
+            Entry::Blob { blob, .. } => filter.filter(context, key, &self.blob_reader.read(blob)?),
+            Entry::Merge(_) | Entry::Tombstone => Decision::Keep,
+        };
+
+        Ok(match decision {
+            Decision::Keep => Some(entry),
+            Decision::Remove => None,
+            Decision::ChangeValue(value) => match entry {
+                Entry::Value { expires_at, .. } | Entry::Blob { expires_at, .. } => {
+                    Some(Entry::Value { value, expires_at })
+                }
+                entry => Some(entry),
+            },
+        })
     }
 
-    fn flush(&self) -> io::Result<()> {
-        // This store always writes to disk on insert, so we don't really need to flush here.
-        Ok(())
+    /// Writes the value of `entry` to the blob file written along with an SST if it is at least
+    /// as large as the blob threshold, opening the blob file on the first such value. Returns the
+    /// reference to store in place of the entry, or None if the entry is stored as it is.
+    fn separate_value(
+        &self,
+        update: &mut ManifestUpdate,
+        blob_writer: &mut Option<BlobWriter>,
+        key: &str,
+        entry: &Entry,
+    ) -> io::Result<Option<Entry>> {
+        let Entry::Value { value, expires_at } = entry else {
+            return Ok(None);
+        };
+
+        if self.options.blob_threshold.is_none_or(|threshold| value.len() < threshold) {
+            return Ok(None);
+        }
+
+        let blob_writer = match blob_writer {
+            Some(blob_writer) => blob_writer,
+            None => blob_writer.insert(BlobWriter::open(&self.directory, update.allocate_id())?),
+        };
+
+        Ok(Some(Entry::Blob {
+            blob: blob_writer.write(key, value)?,
+            expires_at: *expires_at,
+        }))
     }
 }
 
@@ -318,6 +1046,24 @@ impl<S: SSTableReader> Store for LSMTree<S> {
 mod tests {
     use super::*;
 
+    use std::collections::BTreeMap;
+
+    use crate::comparator::BytewiseComparator;
+    use crate::manifest::DEFAULT_COLUMN_FAMILY;
+
+    fn open_tree(
+        directory: impl AsRef<Path>,
+        options: Options,
+    ) -> LSMTree<CachedSSTableReader<FsSSTReader>> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).unwrap();
+
+        let manifest = Manifest::open(&directory).unwrap();
+        remove_orphaned_files(&directory, &manifest).unwrap();
+
+        LSMTree::new(directory, Arc::new(manifest), DEFAULT_COLUMN_FAMILY, options)
+    }
+
     #[test]
     fn test_writing_n_sstables_compacts() {
         let filename = "test_writing_n_sstables_compacts";
@@ -326,12 +1072,12 @@ mod tests {
             fs::remove_dir_all(filename).unwrap();
         }
 
-        let mut tree = LSMTree::new(PathBuf::from(filename)).unwrap();
+        let tree = open_tree(filename, Options::default());
 
         for i in 0..(COMPACT_EVERY_N_SSTABLES * 2) + 1 {
             tree.write_sstable(&BTreeMap::from([(
                 format!("key{}", i),
-                format!("value{}", i).as_bytes().to_vec(),
+                Entry::new(format!("value{}", i).as_bytes().to_vec()),
             )]))
             .unwrap();
         }
@@ -371,11 +1117,11 @@ mod tests {
 
         for i in 0..COMPACT_EVERY_N_SSTABLES + 1 {
             for j in 0..COMPACT_EVERY_N_SSTABLES + 1 {
-                let tree = LSMTree::new(PathBuf::from(filename)).unwrap();
+                let tree = open_tree(filename, Options::default());
 
                 tree.write_sstable(&BTreeMap::from([(
                     format!("key_{}_{}", i, j),
-                    format!("value_{}_{}", i, j).as_bytes().to_vec(),
+                    Entry::new(format!("value_{}_{}", i, j).as_bytes().to_vec()),
                 )]))
                 .unwrap();
             }
@@ -410,24 +1156,24 @@ mod tests {
         let _ = fs::remove_dir_all(path.clone());
         fs::create_dir_all(path.clone()).unwrap();
 
-        let mut tree = LSMTree::new(path.clone()).unwrap();
+        let tree = open_tree(&path, Options::default());
 
         tree.write_sstable(&BTreeMap::from([
-            ("key1".to_string(), "value1".as_bytes().to_vec()),
-            ("key2".to_string(), "value2".as_bytes().to_vec()),
-            ("key3".to_string(), "value3".as_bytes().to_vec()),
+            ("key1".to_string(), Entry::new("value1".as_bytes().to_vec())),
+            ("key2".to_string(), Entry::new("value2".as_bytes().to_vec())),
+            ("key3".to_string(), Entry::new("value3".as_bytes().to_vec())),
         ]))
         .unwrap();
 
         tree.write_sstable(&BTreeMap::from([
-            ("key2".to_string(), "value2-new".as_bytes().to_vec()),
-            ("key3".to_string(), "value3-new".as_bytes().to_vec()),
+            ("key2".to_string(), Entry::new("value2-new".as_bytes().to_vec())),
+            ("key3".to_string(), Entry::new("value3-new".as_bytes().to_vec())),
         ]))
         .unwrap();
 
         let ssts = tree.manifest.get_sstables();
 
-        tree.merge_ssts(ssts, 1).unwrap();
+        tree.merge_ssts(ssts, 1, false).unwrap();
 
         drop(tree);
 
@@ -440,16 +1186,420 @@ mod tests {
         assert_eq!(sstables[0].id, 2);
         assert_eq!(sstables[0].level, 1);
         assert_eq!(sstables[0].min_key, "key1");
+        assert_eq!(sstables[0].props.entry_count, 3);
+        assert_eq!(sstables[0].props.smallest_flush_seq, 1);
+        assert_eq!(sstables[0].props.largest_flush_seq, 5);
 
         // Verify SSTable
         let sstable_reader = FsSSTReader::new(path.clone());
         let sstable = sstable_reader.read_chunk(2, 0).unwrap();
         assert_eq!(sstable.len(), 3);
         assert_eq!(sstable[0].0, "key1");
-        assert_eq!(sstable[0].1, "value1".as_bytes().to_vec());
+        assert_eq!(sstable[0].1, Entry::new("value1".as_bytes().to_vec()));
         assert_eq!(sstable[1].0, "key2");
-        assert_eq!(sstable[1].1, "value2-new".as_bytes().to_vec());
+        assert_eq!(sstable[1].1, Entry::new("value2-new".as_bytes().to_vec()));
         assert_eq!(sstable[2].0, "key3");
-        assert_eq!(sstable[2].1, "value3-new".as_bytes().to_vec());
+        assert_eq!(sstable[2].1, Entry::new("value3-new".as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_orphaned_sst_files_are_removed_on_open() {
+        let path = PathBuf::from("test_orphaned_sst_files_are_removed_on_open");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let tree = open_tree(&path, Options::default());
+        tree.write_sstable(&BTreeMap::from([
+            ("key1".to_string(), Entry::new("value1".as_bytes().to_vec())),
+        ]))
+        .unwrap();
+        drop(tree);
+
+        // An SST that never made it into the manifest and one that was never finished.
+        let mut writer = SSTableWriter::open(&path, 100).unwrap();
+        writer.write("key2", &Entry::new(b"value2".to_vec())).unwrap();
+        writer.finalize().unwrap();
+        fs::write(path.join("sstable_0000000000000101.sst.tmp"), b"partial").unwrap();
+        fs::write(path.join("unrelated"), b"keep me").unwrap();
+
+        let tree = open_tree(&path, Options::default());
+
+        assert!(sst_file_path(&path, 0).exists());
+        assert!(!sst_file_path(&path, 100).exists());
+        assert!(!path.join("sstable_0000000000000101.sst.tmp").exists());
+        assert!(path.join("unrelated").exists());
+
+        assert_eq!(tree.get("key1").unwrap().and_then(Entry::into_value), Some(b"value1".to_vec()));
+        assert_eq!(tree.get("key2").unwrap().and_then(Entry::into_value), None);
+    }
+
+    #[test]
+    fn test_cursor_can_read_sstables_removed_by_concurrent_merge() {
+        let path = PathBuf::from("test_cursor_can_read_sstables_removed_by_concurrent_merge");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let tree = open_tree(&path, Options::default());
+
+        tree.write_sstable(&BTreeMap::from([
+            ("key1".to_string(), Entry::new("value1".as_bytes().to_vec())),
+            ("key2".to_string(), Entry::new("value2".as_bytes().to_vec())),
+        ]))
+        .unwrap();
+
+        tree.write_sstable(&BTreeMap::from([
+            ("key3".to_string(), Entry::new("value3".as_bytes().to_vec())),
+        ]))
+        .unwrap();
+
+        let cursor = tree.get_range(.., None).unwrap();
+
+        tree.merge_ssts(tree.manifest.get_sstables(), 1, false).unwrap();
+
+        // Merged SSTs are still around since the cursor was created before the merge.
+        assert!(sst_file_path(&path, 0).exists());
+        assert!(sst_file_path(&path, 1).exists());
+
+        let actual: Vec<_> = cursor.map(Result::unwrap).collect();
+        assert_eq!(
+            actual,
+            vec![
+                ("key1".to_string(), Entry::new("value1".as_bytes().to_vec())),
+                ("key2".to_string(), Entry::new("value2".as_bytes().to_vec())),
+                ("key3".to_string(), Entry::new("value3".as_bytes().to_vec())),
+            ]
+        );
+
+        assert!(!sst_file_path(&path, 0).exists());
+        assert!(!sst_file_path(&path, 1).exists());
+        assert!(sst_file_path(&path, 2).exists());
+    }
+
+    #[test]
+    fn test_compact_range_pulls_in_sstables_overlapping_merged_range() {
+        let path = PathBuf::from("test_compact_range_pulls_in_sstables_overlapping_merged_range");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let tree = open_tree(&path, Options::default());
+
+        tree.write_sstable(&BTreeMap::from([
+            ("b".to_string(), Entry::new("old".as_bytes().to_vec())),
+        ]))
+        .unwrap();
+        tree.merge_ssts(tree.manifest.get_sstables(), 1, false).unwrap();
+
+        tree.write_sstable(&BTreeMap::from([
+            ("a".to_string(), Entry::new("new".as_bytes().to_vec())),
+            ("b".to_string(), Entry::new("new".as_bytes().to_vec())),
+            ("z".to_string(), Entry::new("new".as_bytes().to_vec())),
+        ]))
+        .unwrap();
+
+        // Only the level 0 SSTable overlaps the range, but the level 1 one overlaps its key range.
+        tree.compact_range((Included("m"), Excluded("n")), MAX_LEVEL).unwrap();
+
+        let sstables = tree.manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].level, MAX_LEVEL);
+        assert_eq!(sstables[0].props.entry_count, 3);
+
+        assert_eq!(tree.get("b").unwrap().and_then(Entry::into_value), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_compaction_moves_values_out_of_blob_files_full_of_garbage() {
+        let path = PathBuf::from("test_compaction_moves_values_out_of_blob_files_full_of_garbage");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let options = Options {
+            blob_threshold: Some(100),
+            ..Options::default()
+        };
+        let tree = open_tree(&path, options.clone());
+
+        tree.write_sstable(&(0..10)
+            .map(|i| (format!("key{i}"), Entry::new(vec![b'a'; 1000])))
+            .collect::<BTreeMap<_, _>>())
+            .unwrap();
+
+        let first = tree.manifest.current().get_blob_files()[0].id;
+
+        tree.write_sstable(&(0..6)
+            .map(|i| (format!("key{i}"), Entry::new(vec![b'b'; 1000])))
+            .collect::<BTreeMap<_, _>>())
+            .unwrap();
+
+        // The overwritten values are garbage now, but the file is only collected by the next
+        // compaction.
+        tree.compact_range(.., MAX_LEVEL).unwrap();
+
+        let blob_files = tree.manifest.current().get_blob_files();
+        assert_eq!(blob_files.len(), 2);
+        assert_eq!((blob_files[0].id, blob_files[0].garbage_count), (first, 6));
+        assert_eq!(blob_files[0].garbage_ratio(), 0.6);
+
+        tree.compact_range(.., MAX_LEVEL).unwrap();
+
+        let blob_files = tree.manifest.current().get_blob_files();
+        assert!(blob_files.iter().all(|it| it.id != first));
+        assert!(!crate::blob::blob_file_path(&path, first).exists());
+
+        assert_eq!(tree.get("key2").unwrap().and_then(Entry::into_value), Some(vec![b'b'; 1000]));
+        assert_eq!(tree.get("key8").unwrap().and_then(Entry::into_value), Some(vec![b'a'; 1000]));
+
+        // The garbage statistics survive reopening the manifest.
+        drop(tree);
+        let tree = open_tree(&path, options);
+        assert_eq!(tree.manifest.current().get_blob_files(), blob_files);
+        assert_eq!(tree.get("key9").unwrap().and_then(Entry::into_value), Some(vec![b'a'; 1000]));
+    }
+
+    #[test]
+    fn test_compact_range_rejects_invalid_level() {
+        let path = PathBuf::from("test_compact_range_rejects_invalid_level");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let tree = open_tree(&path, Options::default());
+        let err = tree.compact_range(.., MAX_LEVEL + 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_merge_drops_expired_entries_only_when_bottommost() {
+        let path = PathBuf::from("test_merge_drops_expired_entries_only_when_bottommost");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let tree = open_tree(&path, Options::default());
+
+        let expired = Entry::Value {
+            value: b"new".to_vec(),
+            expires_at: Some(1),
+        };
+
+        tree.write_sstable(&BTreeMap::from([
+            ("a".to_string(), Entry::new(b"old".to_vec())),
+        ]))
+        .unwrap();
+        tree.merge_ssts(tree.manifest.get_sstables(), MAX_LEVEL, false).unwrap();
+
+        tree.write_sstable(&BTreeMap::from([
+            ("a".to_string(), expired.clone()),
+            ("b".to_string(), expired.clone()),
+            ("c".to_string(), Entry::new(b"live".to_vec())),
+        ]))
+        .unwrap();
+        tree.merge_ssts(tree.manifest.current().get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 0), 1, false).unwrap();
+
+        // The level 3 SSTable still holds an older version of "a", so the expired entries are kept.
+        let merged = tree.manifest.current().get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 1);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].props.entry_count, 3);
+        assert!(tree.get("a").unwrap().unwrap().is_expired(now_millis()));
+
+        tree.compact_range(.., MAX_LEVEL).unwrap();
+
+        let sstables = tree.manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].min_key, "c");
+        assert_eq!(sstables[0].props.entry_count, 1);
+        assert_eq!(tree.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_merge_collapses_operands_only_when_bottommost() {
+        let path = PathBuf::from("test_merge_collapses_operands_only_when_bottommost");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::AppendOperator)),
+            ..Options::default()
+        };
+        let tree = open_tree(&path, options);
+
+        tree.write_sstable(&BTreeMap::from([
+            ("a".to_string(), Entry::new(b"base".to_vec())),
+        ]))
+        .unwrap();
+        tree.merge_ssts(tree.manifest.get_sstables(), MAX_LEVEL, false).unwrap();
+
+        tree.write_sstable(&BTreeMap::from([
+            ("a".to_string(), Entry::Merge(vec![b"-1".to_vec()])),
+        ]))
+        .unwrap();
+        tree.write_sstable(&BTreeMap::from([
+            ("a".to_string(), Entry::Merge(vec![b"-2".to_vec()])),
+        ]))
+        .unwrap();
+        tree.merge_ssts(tree.manifest.current().get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 0), 1, false).unwrap();
+
+        // The operands can't be applied to the value at level 3 yet, so they are kept together.
+        let merged = tree.manifest.current().get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 1);
+        let chunk = FsSSTReader::new(path.clone()).read_chunk(merged[0].id, 0).unwrap();
+        assert_eq!(chunk, vec![("a".to_string(), Entry::Merge(vec![b"-1".to_vec(), b"-2".to_vec()]))]);
+
+        assert_eq!(tree.get("a").unwrap(), Some(Entry::new(b"base-1-2".to_vec())));
+
+        tree.compact_range(.., MAX_LEVEL).unwrap();
+
+        let sstables = tree.manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        let chunk = FsSSTReader::new(path.clone()).read_chunk(sstables[0].id, 0).unwrap();
+        assert_eq!(chunk, vec![("a".to_string(), Entry::new(b"base-1-2".to_vec()))]);
+    }
+
+    #[test]
+    fn test_column_families_only_see_their_own_sstables() {
+        let path = PathBuf::from("test_column_families_only_see_their_own_sstables");
+        let _ = fs::remove_dir_all(path.clone());
+        fs::create_dir_all(path.clone()).unwrap();
+
+        let manifest = Arc::new(Manifest::open(&path).unwrap());
+        let default = LSMTree::new(path.clone(), manifest.clone(), DEFAULT_COLUMN_FAMILY, Options::default());
+        let other = LSMTree::new(path.clone(), manifest.clone(), 1, Options::default());
+
+        for (tree, value) in [(&default, "default"), (&other, "other")] {
+            for i in 0..2 {
+                tree.write_sstable(&BTreeMap::from([
+                    ("key".to_string(), Entry::new(format!("{value}{i}").into_bytes())),
+                ]))
+                .unwrap();
+            }
+        }
+
+        assert_eq!(default.get("key").unwrap(), Some(Entry::new(b"default1".to_vec())));
+        assert_eq!(other.get("key").unwrap(), Some(Entry::new(b"other1".to_vec())));
+
+        default.compact_range(.., 1).unwrap();
+
+        let version = manifest.current();
+        assert_eq!(version.get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 0).len(), 0);
+        assert_eq!(version.get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 1).len(), 1);
+        assert_eq!(version.get_sstables_at_level(1, 0).len(), 2);
+
+        let keys: Vec<_> = other.get_range(.., None).unwrap().map(|it| it.unwrap()).collect();
+        assert_eq!(keys, vec![("key".to_owned(), Entry::new(b"other1".to_vec()))]);
+    }
+
+    #[test]
+    fn test_prefix_scans_skip_sstables_ruled_out_by_filter() {
+        let path = PathBuf::from("test_prefix_scans_skip_sstables_ruled_out_by_filter");
+        let _ = fs::remove_dir_all(&path);
+
+        let options = Options {
+            prefix_extractor: Some(Arc::new(crate::FixedPrefix::new(2))),
+            ..Options::default()
+        };
+        let tree = open_tree(&path, options);
+
+        let value = || Entry::new(b"value".to_vec());
+
+        // Overlaps the scanned range but has no key of the prefix
+        tree.write_sstable(&BTreeMap::from([("a:0".to_owned(), value()), ("c:0".to_owned(), value())]))
+            .unwrap();
+        let ruled_out = tree.manifest.get_sstables()[0].id;
+
+        tree.write_sstable(&BTreeMap::from([("b:1".to_owned(), value())])).unwrap();
+
+        let scan = |tree: &LSMTree<_>| -> io::Result<Vec<String>> {
+            tree.get_range(crate::comparator::prefix_range(&BytewiseComparator, "b:"), Some("b:"))?
+                .map(|it| it.map(|(key, _)| key))
+                .collect()
+        };
+
+        assert_eq!(scan(&tree).unwrap(), vec!["b:1"]);
+
+        // The SST is never read, so removing it goes unnoticed
+        fs::remove_file(sst_file_path(&path, ruled_out)).unwrap();
+        assert_eq!(scan(&tree).unwrap(), vec!["b:1"]);
+        assert!(tree.get_range(.., None).unwrap().any(|it| it.is_err()));
+    }
+
+    #[test]
+    fn test_multi_get_reads_each_chunk_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        /// Counts the chunks read from the SSTs.
+        struct CountingReader(FsSSTReader, AtomicUsize);
+
+        impl SSTableReader for CountingReader {
+            type ChunkIterator = <FsSSTReader as SSTableReader>::ChunkIterator;
+
+            fn list_chunks(&self, sst_id: u64) -> io::Result<Vec<crate::sstable::ChunkDesc>> {
+                self.0.list_chunks(sst_id)
+            }
+
+            fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<crate::sstable::reader::Chunk> {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                self.0.read_chunk(sst_id, chunk_index)
+            }
+
+            fn chunk_iterator(&self, sst_id: u64) -> io::Result<Self::ChunkIterator> {
+                self.0.chunk_iterator(sst_id)
+            }
+        }
+
+        let path = PathBuf::from("test_multi_get_reads_each_chunk_once");
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::AppendOperator)),
+            ..Options::default()
+        };
+
+        let tree = LSMTree {
+            directory: path.clone(),
+            family: DEFAULT_COLUMN_FAMILY,
+            manifest: Arc::new(Manifest::open(&path).unwrap()),
+            sstable_reader: CountingReader(FsSSTReader::new(path.clone()), AtomicUsize::new(0)),
+            blob_reader: BlobReader::new(path.clone()),
+            level_zero_count: AtomicU8::new(0),
+            compaction_lock: Mutex::new(()),
+            comparator: options.comparator(),
+            options,
+        };
+
+        let values: BTreeMap<_, _> = (0..200)
+            .map(|i| (format!("key_{i:03}"), Entry::new(vec![b'v'; 100])))
+            .collect();
+        tree.write_sstable(&values).unwrap();
+
+        let operands: BTreeMap<_, _> = (0..200)
+            .step_by(10)
+            .map(|i| (format!("key_{i:03}"), Entry::Merge(vec![b"+".to_vec()])))
+            .collect();
+        tree.write_sstable(&operands).unwrap();
+
+        let chunk_count: usize = tree
+            .manifest
+            .get_sstables()
+            .iter()
+            .map(|it| tree.sstable_reader.list_chunks(it.id).unwrap().len())
+            .sum();
+        assert!(chunk_count > 2);
+
+        let keys: Vec<_> = (0..200)
+            .rev()
+            .step_by(2)
+            .map(|i| format!("key_{i:03}"))
+            .chain(["key_100".to_owned(), "missing".to_owned()])
+            .collect();
+        let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+
+        let entries = tree.multi_get(&keys).unwrap();
+        assert_eq!(tree.sstable_reader.1.load(Ordering::Relaxed), chunk_count);
+
+        for (key, entry) in keys.iter().zip(entries) {
+            assert_eq!(entry, tree.get(key).unwrap(), "{key}");
+        }
+
+        let mut expected = vec![b'v'; 100];
+        expected.push(b'+');
+        assert_eq!(tree.multi_get(&["key_010"]).unwrap(), vec![Some(Entry::new(expected))]);
     }
 }
@@ -1,12 +1,12 @@
-use std::cmp::Reverse;
 use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
+use std::io::Write;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::ops::Bound::*;
-use std::ops::RangeBounds;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU64;
@@ -17,86 +17,218 @@ use std::sync::Mutex;
 use fs2::FileExt;
 use arc_swap::ArcSwap;
 
+use crate::blob::BlobFileDesc;
+use crate::blob::blob_file_path;
+use crate::sstable::SSTableProperties;
+use crate::sstable::sst_file_path;
+
 pub mod reader;
+pub mod version;
 pub mod writer;
 
+pub use version::Version;
+use version::{LiveBlobFile, LiveSSTable};
+
 pub(crate) const MAGIC: u32 = 0xBEEFFE57;
+pub(crate) const VERSION: u8 = 6;
+
+/// Column family SSTs belong to unless recorded otherwise, which includes every SST recorded
+/// before column families were introduced.
+pub const DEFAULT_COLUMN_FAMILY: u32 = 0;
+
+const LOCK_FILENAME: &str = "manifest.lock";
+
+/// Name of the file that holds the name of the active manifest.
+const CURRENT_FILENAME: &str = "CURRENT";
+const CURRENT_TMP_FILENAME: &str = "CURRENT.tmp";
+
+const MANIFEST_FILENAME_PREFIX: &str = "manifest_";
+
+/// Manifest file used by stores created before manifest checkpointing.
+const LEGACY_MANIFEST_FILENAME: &str = "manifest";
+
+/// Number of entries after which the manifest is checkpointed into a new file.
+const CHECKPOINT_AFTER_ENTRIES: usize = 1024;
 
 #[derive(Debug, Clone)]
 pub struct SSTableDesc {
     pub id: u64,
+    pub family: u32,
     pub level: u8,
     pub min_key: String,
     pub max_key: String,
+    pub props: SSTableProperties,
+}
+
+/// Values of a blob file that compactions stopped referring to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobGarbage {
+    pub file_id: u64,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// Blob files added and removed by a manifest entry, along with garbage found in existing ones.
+#[derive(Debug, Clone, Default)]
+pub struct BlobFileEdit {
+    pub add: Vec<BlobFileDesc>,
+    pub garbage: Vec<BlobGarbage>,
+    pub remove: Vec<u64>,
 }
 
 pub struct Manifest {
-    sstables: ArcSwap<BTreeMap<u64, SSTableDesc>>,
+    directory: PathBuf,
+
+    current: ArcSwap<Version>,
     next_sstable_id: Arc<AtomicU64>,
+    next_flush_sequence: Arc<AtomicU64>,
+
+    // Names of the column families other than the default one, by ID.
+    column_families: Mutex<BTreeMap<u32, String>>,
+
+    // Name of the comparator the SSTs are sorted by, None if it was never recorded.
+    comparator: Mutex<Option<String>>,
 
+    // Also serves as the writer lock, only one update may be written at a time.
+    active: Mutex<ActiveManifest>,
+
+    // Number of entries the active manifest may accumulate before it is replaced by a fresh
+    // manifest containing a single snapshot entry.
+    checkpoint_after: usize,
+
+    // None for a read-only manifest, which another process may be writing to meanwhile.
+    _lock_file: Option<File>,
+}
+
+/// The manifest file updates are currently being appended to.
+struct ActiveManifest {
     file: File,
+    path: PathBuf,
+    number: u64,
 
-    _lock_path: PathBuf,
-    _lock_file: File,
+    // Number of entries in the file, including the initial snapshot entry.
+    entry_count: usize,
 
-    writer_lock: Mutex<()>,
+    // Offset just past the last entry read, and the format version of the file. Only kept up to
+    // date by a read-only manifest, which reads entries appended by another process from there.
+    read_len: u64,
+    version: u8,
 }
 
 pub struct ManifestUpdate {
     add: Vec<SSTableDesc>,
     remove: Vec<u64>,
+    add_column_families: Vec<(u32, String)>,
+    comparator: Option<String>,
+    blob_files: BlobFileEdit,
+    flushed_wal_sequences: Vec<(u32, u64)>,
     next_sstable_id: Arc<AtomicU64>,
+    next_flush_sequence: Arc<AtomicU64>,
 }
 
 impl ManifestUpdate {
-    fn new(next_sstable_id: Arc<AtomicU64>) -> Self {
+    fn new(next_sstable_id: Arc<AtomicU64>, next_flush_sequence: Arc<AtomicU64>) -> Self {
         Self {
             add: Vec::new(),
             remove: Vec::new(),
+            add_column_families: Vec::new(),
+            comparator: None,
+            blob_files: BlobFileEdit::default(),
+            flushed_wal_sequences: Vec::new(),
             next_sstable_id,
+            next_flush_sequence,
         }
     }
 
+    #[cfg(test)]
     pub fn add<K1, K2>(&mut self, level: u8, min_key: K1, max_key: K2) -> u64
     where
         K1: AsRef<str>,
         K2: AsRef<str>
     {
-        let id = self.next_sstable_id.fetch_add(1, Ordering::Relaxed);
+        let id = self.allocate_id();
 
-        self.add.push(SSTableDesc {
+        self.add_sstable(SSTableDesc {
             id,
+            family: DEFAULT_COLUMN_FAMILY,
             level,
             min_key: min_key.as_ref().to_owned(),
             max_key: max_key.as_ref().to_owned(),
+            props: SSTableProperties::default(),
         });
 
         id
     }
 
+    /// Allocates an ID for an SST that is added later through [`ManifestUpdate::add_sstable`],
+    /// usually once it has been written.
+    pub fn allocate_id(&mut self) -> u64 {
+        self.next_sstable_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn add_sstable(&mut self, desc: SSTableDesc) {
+        self.add.push(desc);
+    }
+
+    /// Allocates `count` consecutive flush sequence numbers and returns the first and the last one.
+    pub fn allocate_flush_sequence(&mut self, count: u64) -> (u64, u64) {
+        let first = self.next_flush_sequence.fetch_add(count, Ordering::Relaxed);
+        (first, first + count.saturating_sub(1))
+    }
+
     pub fn remove(&mut self, id: u64) {
         self.remove.push(id);
     }
+
+    pub fn add_column_family(&mut self, id: u32, name: &str) {
+        self.add_column_families.push((id, name.to_owned()));
+    }
+
+    /// Records the name of the comparator the SSTs are sorted by.
+    pub fn set_comparator(&mut self, name: &str) {
+        self.comparator = Some(name.to_owned());
+    }
+
+    /// Adds a blob file written along with the SSTs of the update, which must be allocated an ID
+    /// through [`ManifestUpdate::allocate_id`].
+    pub fn add_blob_file(&mut self, desc: BlobFileDesc) {
+        self.blob_files.add.push(desc);
+    }
+
+    /// Records that `count` values of the blob file, `bytes` long in total, are no longer
+    /// referred to. Blob files left without any referred to value are removed by the update.
+    pub fn add_blob_garbage(&mut self, file_id: u64, count: u64, bytes: u64) {
+        self.blob_files.garbage.push(BlobGarbage { file_id, count, bytes });
+    }
+
+    /// Records that the entries of the column family logged up to the WAL record with the given
+    /// sequence number are in its SSTs, which the update should add.
+    pub fn set_flushed_wal_sequence(&mut self, family: u32, sequence: u64) {
+        self.flushed_wal_sequences.push((family, sequence));
+    }
 }
 
 impl Manifest {
     pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
-        let manifest_file_path = path.as_ref().join("manifest");
+        let directory = path.as_ref().to_path_buf();
 
-        let _lock_path = manifest_file_path.with_extension("lock");
+        let lock_file = lock_directory(&directory)?;
 
-        let _lock_file = File::options()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&_lock_path)?;
+        let (number, manifest_file_path) = match active_manifest_path(&directory)? {
+            Some(active) => active,
 
-        _lock_file.try_lock_exclusive()?;
+            None => {
+                let filename = manifest_filename(0);
+                drop(writer::ManifestWriter::create(&directory.join(&filename))?);
+                set_current(&directory, &filename)?;
+
+                (0, directory.join(filename))
+            }
+        };
 
         let mut file = OpenOptions::new()
             .write(true)
             .read(true)
-            .create(true)
             .open(&manifest_file_path)?;
 
         // Ensure the file isn't empty and at least the header is written since our reader expects
@@ -109,137 +241,606 @@ impl Manifest {
         let state = reader::ManifestReader::new(&file).read()?;
         // after the, we are at the end of the file, which is what manifest writer expects.
 
-        Ok(Self {
-            sstables: ArcSwap::from_pointee(state.sstables),
-            next_sstable_id: Arc::new(AtomicU64::new(state.next_sst_id)),
+        remove_stale_manifests(&directory, &manifest_file_path);
 
-            file,
+        let version = state.version;
+        let manifest = Self::new(directory, state, file, manifest_file_path, number, Some(lock_file));
 
-            _lock_path,
-            _lock_file,
+        // Entries are always written in the latest format, a manifest written by an older
+        // version is therefore rewritten before anything is appended to it.
+        if version != VERSION {
+            let mut active = manifest.active.lock().unwrap();
+            manifest.checkpoint(&mut active)?;
+        }
 
-            writer_lock: Mutex::new(()),
-        })
+        Ok(manifest)
     }
 
-    #[cfg(test)]
-    pub fn get_sstables(&self) -> Vec<SSTableDesc> {
-        let mut result: Vec<_> = self
-            .sstables
-            .load()
-            .values()
-            .cloned()
-            .collect();
+    /// Opens the manifest without locking or writing anything, so the store may be open for
+    /// writing in another process. Updates are rejected, and entries appended by the other
+    /// process are read through [`Manifest::catch_up`].
+    pub fn open_read_only(path: impl AsRef<Path>) -> io::Result<Self> {
+        let directory = path.as_ref().to_path_buf();
+
+        let Some((number, manifest_file_path)) = active_manifest_path(&directory)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no store in {}", directory.display()),
+            ));
+        };
 
-        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+        let file = File::open(&manifest_file_path)?;
+        let state = reader::ManifestReader::new(&file).read()?;
 
-        result
+        Ok(Self::new(directory, state, file, manifest_file_path, number, None))
     }
 
-    pub fn get_sstables_at_level(&self, level: u8) -> Vec<SSTableDesc> {
-        let mut result: Vec<_> = self
-            .sstables
-            .load()
-            .values()
-            .filter(|it| it.level == level)
-            .cloned()
-            .collect();
+    fn new(
+        directory: PathBuf,
+        state: reader::ReadResult,
+        file: File,
+        path: PathBuf,
+        number: u64,
+        lock_file: Option<File>,
+    ) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(version_from_state(&directory, &state)),
+            next_sstable_id: Arc::new(AtomicU64::new(state.next_sst_id)),
+            next_flush_sequence: Arc::new(AtomicU64::new(next_flush_sequence(&state))),
 
-        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+            column_families: Mutex::new(state.column_families),
+            comparator: Mutex::new(state.comparator),
 
-        result
+            active: Mutex::new(ActiveManifest {
+                file,
+                path,
+                number,
+                entry_count: state.entry_count,
+                read_len: state.valid_len,
+                version: state.version,
+            }),
+
+            directory,
+            checkpoint_after: CHECKPOINT_AFTER_ENTRIES,
+
+            _lock_file: lock_file,
+        }
     }
 
-    pub fn get_candidate_sstables_for_key(&self, key: &str) -> Vec<SSTableDesc> {
-        let mut result: Vec<_> = self
-            .sstables
-            .load()
-            .values()
-            .filter(|sstable| sstable.min_key.as_str() <= key && sstable.max_key.as_str() >= key)
-            .cloned()
-            .collect();
+    /// Returns true if the manifest was opened through [`Manifest::open_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self._lock_file.is_none()
+    }
 
-        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+    /// Applies the entries another process appended to the manifest since it was last read. If
+    /// the manifest was replaced by a checkpoint in the meantime, the new one is read instead.
+    ///
+    /// Only a read-only manifest can fall behind, for any other this does nothing.
+    pub fn catch_up(&self) -> io::Result<()> {
+        if !self.is_read_only() {
+            return Ok(());
+        }
 
-        result
+        let mut active = self.active.lock().unwrap();
+
+        let Some((number, path)) = active_manifest_path(&self.directory)? else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "manifest has been removed"));
+        };
+
+        if path != active.path {
+            let file = File::open(&path)?;
+            let state = reader::ManifestReader::new(&file).read()?;
+
+            self.current.store(Arc::new(version_from_state(&self.directory, &state)));
+            self.next_sstable_id.store(state.next_sst_id, Ordering::Relaxed);
+            self.next_flush_sequence.store(next_flush_sequence(&state), Ordering::Relaxed);
+            *self.column_families.lock().unwrap() = state.column_families;
+            *self.comparator.lock().unwrap() = state.comparator;
+
+            *active = ActiveManifest {
+                file,
+                path,
+                number,
+                entry_count: state.entry_count,
+                read_len: state.valid_len,
+                version: state.version,
+            };
+
+            return Ok(());
+        }
+
+        let read_len = active.read_len;
+        active.file.seek(SeekFrom::Start(read_len))?;
+
+        // Stops before an entry that is still being written, which is read on the next call.
+        let history = reader::ManifestReader::with_version(&active.file, active.version).read_appended()?;
+
+        active.entry_count += history.entries.len();
+        active.read_len = history.valid_len;
+
+        for entry in history.entries {
+            self.apply(entry);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the current set of live SSTables.
+    ///
+    /// Files of SSTables in the returned version are not deleted for as long as it is held, even
+    /// if the SSTables are removed from the manifest in the meantime.
+    pub fn current(&self) -> Arc<Version> {
+        self.current.load_full()
     }
 
-    pub fn get_candidate_sstables_for_range<Range: RangeBounds<str>>(
+    #[cfg(test)]
+    pub fn get_sstables(&self) -> Vec<SSTableDesc> {
+        self.current().get_sstables()
+    }
+
+    pub fn get_sstable_ids(&self) -> HashSet<u64> {
+        self.current().get_sstable_ids()
+    }
+
+    pub fn get_blob_file_ids(&self) -> HashSet<u64> {
+        self.current().live_blob_files().keys().copied().collect()
+    }
+
+    #[cfg(test)]
+    pub fn get_candidate_sstables_for_range<Range: std::ops::RangeBounds<str>>(
         &self,
         range: Range,
     ) -> Vec<SSTableDesc> {
-        let is_empty = match (range.start_bound(), range.end_bound()) {
-            (Included(a), Included(b)) => a > b,
-            (Included(a), Excluded(b)) => a >= b,
-            (Excluded(a), Included(b)) => a >= b,
-            (Excluded(a), Excluded(b)) => a >= b,
-            _ => false,
+        self.current().get_candidate_sstables_for_range(
+            DEFAULT_COLUMN_FAMILY,
+            range,
+            &crate::comparator::BytewiseComparator,
+        )
+    }
+
+    /// Returns the names of the column families other than the default one, by ID.
+    pub fn column_families(&self) -> BTreeMap<u32, String> {
+        self.column_families.lock().unwrap().clone()
+    }
+
+    /// Returns the name of the comparator the SSTs are sorted by. None for stores created before
+    /// comparators were recorded, which are sorted bytewise.
+    pub fn comparator(&self) -> Option<String> {
+        self.comparator.lock().unwrap().clone()
+    }
+
+    pub fn start_update(&self) -> ManifestUpdate {
+        ManifestUpdate::new(self.next_sstable_id.clone(), self.next_flush_sequence.clone())
+    }
+
+    pub fn update(&self, mut update: ManifestUpdate) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "manifest is opened read-only",
+            ));
+        }
+
+        let mut active = self.active.lock().unwrap();
+
+        // Blob files none of whose values are referred to anymore are removed by the same entry.
+        update.blob_files.remove = self.drained_blob_files(&update.blob_files);
+
+        let entry = reader::ManifestEntry {
+            next_sst_id: self.next_sstable_id.load(Ordering::Relaxed),
+            added: update.add,
+            removed: update.remove,
+            column_families: update.add_column_families,
+            comparator: update.comparator,
+            blob_files: update.blob_files,
+            flushed_wal_sequences: update.flushed_wal_sequences,
         };
 
-        if is_empty {
-            return vec![];
+        let mut writer = writer::ManifestWriter::open(active.file.try_clone()?)?;
+        writer.write(&entry)?;
+        drop(writer);
+
+        active.entry_count += 1;
+
+        self.apply(entry);
+
+        if active.entry_count > self.checkpoint_after {
+            // The update itself is already durable in the current manifest, failing to
+            // checkpoint only means we will try again on the next update.
+            if let Err(e) = self.checkpoint(&mut active) {
+                eprintln!("Error checkpointing manifest: {e}");
+            }
         }
 
-        let mut result: Vec<_> = self
-            .sstables
+        Ok(())
+    }
+
+    /// Returns the IDs of the blob files left without any value referred to once `edit` is
+    /// applied to the current version.
+    fn drained_blob_files(&self, edit: &BlobFileEdit) -> Vec<u64> {
+        let mut blob_files: BTreeMap<u64, BlobFileDesc> = self
+            .current
             .load()
-            .values()
-            .filter(|sstable| {
-                let min = range.start_bound();
-                let min_matches = match min {
-                    Included(x) => x <= sstable.max_key.as_str(),
-                    Excluded(x) => x < sstable.max_key.as_str(),
-                    Unbounded => true,
-                };
-
-                let max = range.end_bound();
-                let max_matches = match max {
-                    Included(x) => x >= sstable.min_key.as_str(),
-                    Excluded(x) => x > sstable.min_key.as_str(),
-                    Unbounded => true,
-                };
-
-                return min_matches && max_matches;
-            })
-            .cloned()
+            .live_blob_files()
+            .iter()
+            .map(|(id, (desc, _))| (*id, desc.clone()))
             .collect();
 
-        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+        for desc in edit.add.iter() {
+            blob_files.insert(desc.id, desc.clone());
+        }
 
-        result
-    }
+        for garbage in edit.garbage.iter() {
+            if let Some(desc) = blob_files.get_mut(&garbage.file_id) {
+                desc.garbage_count += garbage.count;
+                desc.garbage_bytes += garbage.bytes;
+            }
+        }
 
-    pub fn start_update(&self) -> ManifestUpdate {
-        ManifestUpdate::new(self.next_sstable_id.clone())
+        blob_files
+            .iter()
+            .filter(|(_, desc)| desc.is_drained())
+            .map(|(id, _)| *id)
+            .collect()
     }
 
-    pub fn update(&self, update: ManifestUpdate) -> io::Result<()> {
-        let lock = self.writer_lock.lock().unwrap();
+    /// Applies an entry written to the manifest to the current version.
+    ///
+    /// Files of the SSTs and blob files it removes are deleted once no reader holds a version
+    /// containing them, unless the manifest is read-only, since those files belong to the process
+    /// writing the store.
+    fn apply(&self, entry: reader::ManifestEntry) {
+        self.next_sstable_id.fetch_max(entry.next_sst_id, Ordering::Relaxed);
 
-        let mut writer = writer::ManifestWriter::open(self.file.try_clone()?)?;
-        writer.write(
-            &update.add,
-            &update.remove,
-            self.next_sstable_id.load(Ordering::Relaxed),
-        )?;
+        if let Some(largest_flush_seq) = entry.added.iter().map(|it| it.props.largest_flush_seq).max() {
+            self.next_flush_sequence.fetch_max(largest_flush_seq + 1, Ordering::Relaxed);
+        }
 
-        let mut state = (*self.sstables.load_full()).clone();
+        self.column_families
+            .lock()
+            .unwrap()
+            .extend(entry.column_families);
 
-        for sst in update.add {
-            state.insert(sst.id, sst);
+        if let Some(comparator) = entry.comparator {
+            *self.comparator.lock().unwrap() = Some(comparator);
         }
 
-        for id in update.remove {
-            state.remove(&id);
+        let current = self.current.load();
+        let mut sstables = current.live_sstables().clone();
+        let mut blob_files = current.live_blob_files().clone();
+        let mut flushed_wal_sequences = current.flushed_wal_sequences().clone();
+
+        for (family, sequence) in entry.flushed_wal_sequences {
+            let flushed = flushed_wal_sequences.entry(family).or_insert(0);
+            *flushed = sequence.max(*flushed);
         }
 
-        self.sstables.store(Arc::new(state));
+        for desc in entry.blob_files.add {
+            let path = blob_file_path(&self.directory, desc.id);
+            blob_files.insert(desc.id, (desc, Arc::new(LiveBlobFile::new(path))));
+        }
 
-        drop(writer);
-        drop(lock);
+        for garbage in entry.blob_files.garbage.iter() {
+            if let Some((desc, _)) = blob_files.get_mut(&garbage.file_id) {
+                desc.garbage_count += garbage.count;
+                desc.garbage_bytes += garbage.bytes;
+            }
+        }
+
+        for sst in entry.added {
+            let path = sst_file_path(&self.directory, sst.id);
+            sstables.insert(sst.id, Arc::new(LiveSSTable::new(sst, path)));
+        }
+
+        for id in entry.removed {
+            if let Some(sst) = sstables.remove(&id)
+                && !self.is_read_only()
+            {
+                sst.mark_obsolete();
+            }
+        }
+
+        for id in entry.blob_files.remove {
+            if let Some((_, file)) = blob_files.remove(&id)
+                && !self.is_read_only()
+            {
+                file.mark_obsolete();
+            }
+        }
+
+        self.current.store(Arc::new(Version::new(sstables, blob_files, flushed_wal_sequences)));
+    }
+
+    /// Replaces the active manifest with a new one that contains a single entry describing the
+    /// current set of live SSTables.
+    ///
+    /// The new manifest is fully written and synced before [`CURRENT_FILENAME`] is atomically
+    /// swapped to point to it. A crash at any point leaves [`CURRENT_FILENAME`] pointing to a
+    /// complete manifest. Leftovers of an interrupted checkpoint are removed on the next open.
+    fn checkpoint(&self, active: &mut ActiveManifest) -> io::Result<()> {
+        let number = active.number + 1;
+        let filename = manifest_filename(number);
+        let path = self.directory.join(&filename);
+
+        let file = self.snapshot().write_file(&path)?;
+
+        set_current(&self.directory, &filename)?;
+
+        let old_path = std::mem::replace(&mut active.path, path);
+        active.file = file;
+        active.number = number;
+        active.entry_count = 1;
+
+        if let Err(e) = fs::remove_file(&old_path) {
+            eprintln!("Error removing old manifest: {e}");
+        }
 
         Ok(())
     }
+
+    /// Returns the current version along with the rest of the state a manifest records, as a
+    /// whole. The next SST ID and the column families are read after the version, so they cover
+    /// every SST and column family it refers to.
+    pub fn snapshot(&self) -> ManifestSnapshot {
+        let version = self.current();
+
+        ManifestSnapshot {
+            version,
+            next_sst_id: self.next_sstable_id.load(Ordering::Relaxed),
+            column_families: self.column_families(),
+            comparator: self.comparator(),
+        }
+    }
+}
+
+/// The state of a manifest at one point in time, see [`Manifest::snapshot`].
+pub struct ManifestSnapshot {
+    pub version: Arc<Version>,
+    pub next_sst_id: u64,
+    pub column_families: BTreeMap<u32, String>,
+    pub comparator: Option<String>,
+}
+
+impl ManifestSnapshot {
+    /// Writes a manifest describing the snapshot as the only manifest of the store in
+    /// `directory`, which is otherwise expected to be empty.
+    pub fn write(&self, directory: &Path) -> io::Result<()> {
+        let filename = manifest_filename(0);
+        self.write_file(&directory.join(&filename))?;
+
+        set_current(directory, &filename)
+    }
+
+    /// Writes a manifest file containing a single entry that adds every SST and blob file of the
+    /// version, along with the column families and the comparator of the store.
+    fn write_file(&self, path: &Path) -> io::Result<File> {
+        let version = &self.version;
+
+        let entry = reader::ManifestEntry {
+            next_sst_id: self.next_sst_id,
+            added: version.get_sstables(),
+            removed: Vec::new(),
+            column_families: self.column_families.clone().into_iter().collect(),
+            comparator: self.comparator.clone(),
+            blob_files: BlobFileEdit {
+                add: version.get_blob_files(),
+                ..Default::default()
+            },
+            flushed_wal_sequences: version.flushed_wal_sequences().iter().map(|(family, sequence)| (*family, *sequence)).collect(),
+        };
+
+        let mut writer = writer::ManifestWriter::create(path)?;
+        writer.write(&entry)?;
+
+        writer.sync()
+    }
+}
+
+fn manifest_filename(number: u64) -> String {
+    format!("{MANIFEST_FILENAME_PREFIX}{number:016}")
+}
+
+fn parse_manifest_filename(filename: &str) -> Option<u64> {
+    if filename == LEGACY_MANIFEST_FILENAME {
+        return Some(0);
+    }
+
+    filename
+        .strip_prefix(MANIFEST_FILENAME_PREFIX)?
+        .parse()
+        .ok()
+}
+
+/// Returns the number and path of the active manifest of the store in `directory`, or None if
+/// it has none yet.
+fn active_manifest_path(directory: &Path) -> io::Result<Option<(u64, PathBuf)>> {
+    match read_current(directory)? {
+        Some(filename) => {
+            let number = parse_manifest_filename(&filename).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{CURRENT_FILENAME} points to an invalid manifest: {filename:?}"),
+                )
+            })?;
+
+            Ok(Some((number, directory.join(filename))))
+        }
+
+        // Stores created before manifest checkpointing was introduced have a single
+        // manifest file and no CURRENT file. Keep using it until the first checkpoint.
+        None if directory.join(LEGACY_MANIFEST_FILENAME).exists() => {
+            Ok(Some((0, directory.join(LEGACY_MANIFEST_FILENAME))))
+        }
+
+        None => Ok(None),
+    }
+}
+
+/// Builds the version of the SSTs and blob files a manifest records, along with the WAL records
+/// flushed to them.
+fn version_from_state(directory: &Path, state: &reader::ReadResult) -> Version {
+    Version::new(
+        state
+            .sstables
+            .iter()
+            .map(|(id, desc)| (*id, Arc::new(LiveSSTable::new(desc.clone(), sst_file_path(directory, *id)))))
+            .collect(),
+        state
+            .blob_files
+            .iter()
+            .map(|(id, desc)| (*id, (desc.clone(), Arc::new(LiveBlobFile::new(blob_file_path(directory, *id))))))
+            .collect(),
+        state.flushed_wal_sequences.clone(),
+    )
+}
+
+/// Flush sequence numbers are assigned as entries are written to SSTs, the next one follows the
+/// largest flush sequence number of any live SST. They start at 1, 0 means unknown.
+fn next_flush_sequence(state: &reader::ReadResult) -> u64 {
+    state
+        .sstables
+        .values()
+        .map(|it| it.props.largest_flush_seq)
+        .max()
+        .unwrap_or(0) + 1
+}
+
+/// Reads the active manifest of the store in `directory` without opening it, so nothing is
+/// created, locked or rewritten. Returns the path of the manifest along with its state.
+pub(crate) fn read_active_manifest(directory: &Path) -> io::Result<(PathBuf, reader::ReadResult)> {
+    let path = match read_current(directory)? {
+        Some(filename) if parse_manifest_filename(&filename).is_some() => directory.join(filename),
+
+        Some(filename) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{CURRENT_FILENAME} points to an invalid manifest: {filename:?}"),
+            ));
+        }
+
+        None => directory.join(LEGACY_MANIFEST_FILENAME),
+    };
+
+    let state = reader::ManifestReader::new(File::open(&path)?).read()?;
+
+    Ok((path, state))
+}
+
+/// Returns true if the file name is that of a manifest, whether it is the active one or not.
+pub(crate) fn is_manifest_filename(filename: &str) -> bool {
+    parse_manifest_filename(filename).is_some()
+}
+
+/// Locks the store in `directory` the way opening it does, failing if it is already open. The
+/// lock is held until the returned file is dropped.
+pub(crate) fn lock_directory(directory: &Path) -> io::Result<File> {
+    let lock_file = File::options()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(directory.join(LOCK_FILENAME))?;
+
+    lock_file.try_lock_exclusive()?;
+
+    Ok(lock_file)
+}
+
+/// Writes a manifest that adds `sstables` and `blob_files` to an otherwise empty store, and makes
+/// it the active manifest of the store in `directory` in place of whatever manifest it had. The
+/// store must be locked through [`lock_directory`].
+pub(crate) fn write_repaired_manifest(
+    directory: &Path,
+    sstables: &[SSTableDesc],
+    column_families: &[(u32, String)],
+    comparator: Option<&str>,
+    blob_files: Vec<BlobFileDesc>,
+    flushed_wal_sequences: Vec<(u32, u64)>,
+    next_sst_id: u64,
+) -> io::Result<()> {
+    // Numbered after every manifest file left, so none of them is overwritten.
+    let mut number = 0;
+
+    for entry in fs::read_dir(directory)? {
+        let filename = entry?.file_name();
+
+        if let Some(found) = filename.to_str().and_then(parse_manifest_filename) {
+            number = number.max(found + 1);
+        }
+    }
+
+    let filename = manifest_filename(number);
+    let entry = reader::ManifestEntry {
+        next_sst_id,
+        added: sstables.to_vec(),
+        removed: Vec::new(),
+        column_families: column_families.to_vec(),
+        comparator: comparator.map(str::to_owned),
+        blob_files: BlobFileEdit {
+            add: blob_files,
+            ..Default::default()
+        },
+        flushed_wal_sequences,
+    };
+
+    let mut writer = writer::ManifestWriter::create(&directory.join(&filename))?;
+    writer.write(&entry)?;
+    writer.sync()?;
+
+    set_current(directory, &filename)
+}
+
+/// Returns the name of the active manifest file as recorded in [`CURRENT_FILENAME`].
+fn read_current(directory: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(directory.join(CURRENT_FILENAME)) {
+        Ok(content) => Ok(Some(content.trim_end().to_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Atomically points [`CURRENT_FILENAME`] to the given manifest file.
+fn set_current(directory: &Path, filename: &str) -> io::Result<()> {
+    let tmp_path = directory.join(CURRENT_TMP_FILENAME);
+
+    let mut tmp = File::create(&tmp_path)?;
+    tmp.write_all(filename.as_bytes())?;
+    tmp.write_all(b"\n")?;
+    tmp.sync_all()?;
+    drop(tmp);
+
+    fs::rename(&tmp_path, directory.join(CURRENT_FILENAME))?;
+
+    #[cfg(unix)]
+    File::open(directory)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Removes manifest files other than the active one, left behind by an interrupted checkpoint.
+fn remove_stale_manifests(directory: &Path, active: &Path) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error listing manifests: {e}");
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        let is_manifest = path
+            .file_name()
+            .and_then(|it| it.to_str())
+            .and_then(parse_manifest_filename)
+            .is_some();
+
+        if is_manifest && path != active
+            && let Err(e) = fs::remove_file(&path) {
+            eprintln!("Error removing stale manifest: {e}");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -247,6 +848,7 @@ mod test {
     use super::*;
 
     use std::ops::Bound;
+    use std::ops::Bound::*;
     use std::fs;
 
     #[test]
@@ -387,5 +989,255 @@ mod test {
             manifest = Manifest::open(&path).unwrap();
         }
     }
-}
 
+    fn list_manifests(path: &Path) -> Vec<String> {
+        let mut result: Vec<_> = fs::read_dir(path)
+            .unwrap()
+            .map(|it| it.unwrap().file_name().into_string().unwrap())
+            .filter(|it| parse_manifest_filename(it).is_some())
+            .collect();
+
+        result.sort();
+        result
+    }
+
+    #[test]
+    fn test_manifest_is_checkpointed_after_n_entries() {
+        let path = PathBuf::from("test_manifest_is_checkpointed_after_n_entries");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir(&path).unwrap();
+
+        let mut manifest = Manifest::open(&path).unwrap();
+        manifest.checkpoint_after = 4;
+
+        let mut live = Vec::new();
+
+        for i in 0..10 {
+            let mut update = manifest.start_update();
+            live.push(update.add(0, format!("key{i}"), format!("key{i}")));
+
+            // Remove every other table so the snapshot has to account for removals too.
+            if i % 2 == 1 {
+                update.remove(live.remove(0));
+            }
+
+            manifest.update(update).unwrap();
+        }
+
+        assert_eq!(list_manifests(&path), vec![manifest_filename(2)]);
+        assert_eq!(read_current(&path).unwrap(), Some(manifest_filename(2)));
+
+        let expected: Vec<_> = manifest.get_sstables().iter().map(|it| it.id).collect();
+        drop(manifest);
+
+        let manifest = Manifest::open(&path).unwrap();
+        let actual: Vec<_> = manifest.get_sstables().iter().map(|it| it.id).collect();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.len(), 5);
+
+        // Snapshot entry and the last update written after it.
+        assert_eq!(manifest.active.lock().unwrap().entry_count, 2);
+
+        let mut update = manifest.start_update();
+        let id = update.add(0, "key", "key");
+        manifest.update(update).unwrap();
+        assert_eq!(id, 10);
+    }
+
+    #[test]
+    fn test_read_only_manifest_catches_up_across_checkpoints() {
+        let path = PathBuf::from("test_read_only_manifest_catches_up_across_checkpoints");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir(&path).unwrap();
+
+        let mut manifest = Manifest::open(&path).unwrap();
+        manifest.checkpoint_after = 4;
+
+        let add = |manifest: &Manifest, key: &str| {
+            let mut update = manifest.start_update();
+            update.add(0, key, key);
+            manifest.update(update).unwrap();
+        };
+
+        add(&manifest, "key0");
+        let first = read_current(&path).unwrap();
+
+        let reader = Manifest::open_read_only(&path).unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.get_sstables().len(), 1);
+
+        let update = reader.start_update();
+        assert_eq!(
+            reader.update(update).err().map(|it| it.kind()),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+
+        // Appended to the same manifest file.
+        add(&manifest, "key1");
+        reader.catch_up().unwrap();
+        assert_eq!(reader.get_sstables().len(), 2);
+
+        // Past a checkpoint, CURRENT points to a new manifest file.
+        for i in 2..6 {
+            add(&manifest, &format!("key{i}"));
+        }
+        assert_ne!(read_current(&path).unwrap(), first);
+
+        reader.catch_up().unwrap();
+        let expected: Vec<_> = manifest.get_sstables().iter().map(|it| it.id).collect();
+        let actual: Vec<_> = reader.get_sstables().iter().map(|it| it.id).collect();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.len(), 6);
+
+        drop(reader);
+        drop(manifest);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_manifest_is_read_and_replaced_on_checkpoint() {
+        let path = PathBuf::from("test_legacy_manifest_is_read_and_replaced_on_checkpoint");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir(&path).unwrap();
+
+        let mut writer = writer::ManifestWriter::create(&path.join(LEGACY_MANIFEST_FILENAME)).unwrap();
+        let legacy_sst = SSTableDesc {
+            id: 0,
+            family: DEFAULT_COLUMN_FAMILY,
+            level: 1,
+            min_key: "key1".to_owned(),
+            max_key: "key2".to_owned(),
+            props: SSTableProperties::default(),
+        };
+        let entry = reader::ManifestEntry {
+            next_sst_id: 1,
+            added: vec![legacy_sst],
+            removed: Vec::new(),
+            column_families: Vec::new(),
+            comparator: None,
+            blob_files: BlobFileEdit::default(),
+            flushed_wal_sequences: Vec::new(),
+        };
+        writer.write(&entry).unwrap();
+        drop(writer);
+
+        let mut manifest = Manifest::open(&path).unwrap();
+        manifest.checkpoint_after = 1;
+
+        assert_eq!(read_current(&path).unwrap(), None);
+        assert_eq!(manifest.get_sstables().len(), 1);
+
+        let mut update = manifest.start_update();
+        update.add(0, "key3", "key4");
+        manifest.update(update).unwrap();
+
+        assert_eq!(read_current(&path).unwrap(), Some(manifest_filename(1)));
+        assert_eq!(list_manifests(&path), vec![manifest_filename(1)]);
+        drop(manifest);
+
+        let manifest = Manifest::open(&path).unwrap();
+        let sstables = manifest.get_sstables();
+        assert_eq!(sstables.len(), 2);
+        assert_eq!(sstables[0].level, 0);
+        assert_eq!(sstables[1].level, 1);
+    }
+
+    #[test]
+    fn test_sstable_properties_persist_on_reopen() {
+        let path = PathBuf::from("test_sstable_properties_persist_on_reopen");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir(&path).unwrap();
+
+        let props = SSTableProperties {
+            file_size: 4096,
+            entry_count: 10,
+            tombstone_count: 2,
+            smallest_flush_seq: 1,
+            largest_flush_seq: 10,
+            created_at: 1_700_000_000,
+            compression: crate::sstable::Compression::None,
+        };
+
+        let manifest = Manifest::open(&path).unwrap();
+        let mut update = manifest.start_update();
+        let id = update.allocate_id();
+        assert_eq!(update.allocate_flush_sequence(10), (1, 10));
+        update.add_sstable(SSTableDesc {
+            id,
+            family: DEFAULT_COLUMN_FAMILY,
+            level: 0,
+            min_key: "key1".to_owned(),
+            max_key: "key2".to_owned(),
+            props: props.clone(),
+        });
+        manifest.update(update).unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.get_sstables()[0].props, props);
+
+        // Sequence numbers continue after the largest one of the live SSTs.
+        let mut update = manifest.start_update();
+        assert_eq!(update.allocate_flush_sequence(5), (11, 15));
+    }
+
+    #[test]
+    fn test_version_1_manifest_is_read_and_upgraded() {
+        use crate::io_ext::WriteExt;
+
+        let path = PathBuf::from("test_version_1_manifest_is_read_and_upgraded");
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+
+        fs::create_dir(&path).unwrap();
+
+        let mut entry = Vec::new();
+        entry.write_u64(1).unwrap(); // next SST ID
+        entry.write_u64(1).unwrap(); // added count
+        entry.write_u64(0).unwrap(); // ID
+        entry.write_u8(1).unwrap(); // level
+        entry.write_string("key1").unwrap();
+        entry.write_string("key2").unwrap();
+        entry.write_u64(0).unwrap(); // removed count
+
+        let mut file = Vec::new();
+        file.write_u32(MAGIC).unwrap();
+        file.write_u8(1).unwrap();
+        file.write_u32(crate::crc::crc32c(&entry)).unwrap();
+        file.write_u32(entry.len() as u32).unwrap();
+        file.extend_from_slice(&entry);
+
+        fs::write(path.join(LEGACY_MANIFEST_FILENAME), file).unwrap();
+
+        let manifest = Manifest::open(&path).unwrap();
+        let sstables = manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].min_key, "key1");
+        assert_eq!(sstables[0].props, SSTableProperties::default());
+
+        // The manifest is rewritten in the current format right away.
+        assert_eq!(read_current(&path).unwrap(), Some(manifest_filename(1)));
+
+        let mut update = manifest.start_update();
+        let id = update.add(0, "key3", "key4");
+        manifest.update(update).unwrap();
+        drop(manifest);
+
+        let manifest = Manifest::open(&path).unwrap();
+        let ids: Vec<_> = manifest.get_sstables().iter().map(|it| it.id).collect();
+        assert_eq!(ids, vec![id, 0]);
+    }
+}
@@ -7,33 +7,81 @@ use std::io::Cursor;
 use crate::crc::crc32c;
 use crate::io_ext::ReadExt;
 
+use crate::blob::BlobFileDesc;
+use crate::sstable::SSTableProperties;
+
+use super::BlobFileEdit;
+use super::BlobGarbage;
+use super::DEFAULT_COLUMN_FAMILY;
 use super::MAGIC;
 use super::SSTableDesc;
+use super::VERSION;
 
 enum ReadEntryResult {
     Invalid,
-    Update {
-        next_sst_id: u64,
-        added: Vec<SSTableDesc>,
-        removed: Vec<u64>,
-    },
+    Update(ManifestEntry),
+}
+
+/// A single entry of the manifest, as written by one update.
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub next_sst_id: u64,
+    pub added: Vec<SSTableDesc>,
+    pub removed: Vec<u64>,
+    pub column_families: Vec<(u32, String)>,
+    pub comparator: Option<String>,
+    pub blob_files: BlobFileEdit,
+
+    /// Sequence number of the last WAL record flushed, by column family.
+    pub flushed_wal_sequences: Vec<(u32, u64)>,
+}
+
+/// Entries of a manifest file, without applying them to each other.
+pub struct History {
+    pub entries: Vec<ManifestEntry>,
+
+    /// Offset just past the last valid entry. Anything after it is a torn or corrupted entry.
+    pub valid_len: u64,
+
+    /// Format version of the manifest file.
+    pub version: u8,
 }
 
 pub struct ReadResult {
     pub sstables: BTreeMap<u64, SSTableDesc>,
     pub next_sst_id: u64,
+    pub column_families: BTreeMap<u32, String>,
+    pub comparator: Option<String>,
+    pub blob_files: BTreeMap<u64, BlobFileDesc>,
+    pub flushed_wal_sequences: BTreeMap<u32, u64>,
+
+    /// Number of valid entries read.
+    pub entry_count: usize,
+
+    /// Offset just past the last valid entry. Anything after it is a torn or corrupted entry.
+    pub valid_len: u64,
+
+    /// Format version of the manifest file.
+    pub version: u8,
 }
 
-pub struct ManifestReader<R>(R)
+pub struct ManifestReader<R>
 where
-    R: Read + Seek;
+    R: Read + Seek,
+{
+    inner: R,
+    version: u8,
+}
 
 impl<R> ManifestReader<R>
 where
     R: Read + Seek,
 {
     pub fn new(inner: R) -> Self {
-        Self(inner)
+        Self {
+            inner,
+            version: VERSION,
+        }
     }
 
     pub fn read(mut self) -> Result<ReadResult, io::Error> {
@@ -41,9 +89,47 @@ where
         self.read_entries()
     }
 
+    /// Creates a reader positioned past the header of a file of the given format version, such
+    /// as where an earlier read stopped.
+    pub fn with_version(inner: R, version: u8) -> Self {
+        Self { inner, version }
+    }
+
+    /// Reads the entries up to the first torn or corrupted one, which [`ManifestReader::read`]
+    /// applies one after another.
+    pub fn read_history(mut self) -> io::Result<History> {
+        self.read_validate_header()?;
+        self.read_appended()
+    }
+
+    /// Reads the entries from the current position on, up to the first torn or corrupted one.
+    pub fn read_appended(mut self) -> io::Result<History> {
+        let mut entries = Vec::new();
+        let mut valid_len = self.inner.stream_position()?;
+
+        loop {
+            match self.read_entry() {
+                Ok(ReadEntryResult::Update(entry)) => {
+                    entries.push(entry);
+                    valid_len = self.inner.stream_position()?;
+                }
+
+                Ok(ReadEntryResult::Invalid) => break,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(History {
+            entries,
+            valid_len,
+            version: self.version,
+        })
+    }
+
     fn read_validate_header(&mut self) -> io::Result<()> {
-        let magic = self.0.read_u32()?;
-        let version = self.0.read_u8()?;
+        let magic = self.inner.read_u32()?;
+        let version = self.inner.read_u8()?;
 
         if magic != MAGIC {
             return Err(io::Error::new(
@@ -52,36 +138,48 @@ where
             ));
         }
 
-        if version != 1 {
+        if !(1..=VERSION).contains(&version) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Unsupported manifest version: {version}"),
             ));
         }
 
+        self.version = version;
+
         Ok(())
     }
 
     fn read_entries(&mut self) -> io::Result<ReadResult> {
         let mut sstables = BTreeMap::new();
         let mut next_sst_id: u64 = 0;
+        let mut column_families = BTreeMap::new();
+        let mut comparator = None;
+        let mut blob_files = BTreeMap::new();
+        let mut flushed_wal_sequences = BTreeMap::new();
+        let mut entry_count = 0;
+        let mut valid_len = self.inner.stream_position()?;
 
         loop {
             let entry = self.read_entry();
 
             match entry {
-                Ok(ReadEntryResult::Update {
+                Ok(ReadEntryResult::Update(ManifestEntry {
                     next_sst_id: sst_id_update,
                     added,
                     removed,
-                }) => {
-
+                    column_families: added_column_families,
+                    comparator: entry_comparator,
+                    blob_files: blob_file_edit,
+                    flushed_wal_sequences: flushed,
+                })) => {
+                    entry_count += 1;
+                    valid_len = self.inner.stream_position()?;
                     next_sst_id = sst_id_update;
 
                     for id in removed {
                         if sstables.remove(&id).is_none() {
-                            return Err(io::Error::new(
-                                    io::ErrorKind::Other,
+                            return Err(io::Error::other(
                                     "invalid remove entry: SST ID doesn't exist."
                             ));
                         }
@@ -89,13 +187,56 @@ where
 
                     for sstable in added {
                         if sstables.insert(sstable.id, sstable).is_some() {
-                            return Err(io::Error::new(
-                                    io::ErrorKind::Other,
+                            return Err(io::Error::other(
                                     "invalid add entry: SST ID already exists."
                             ));
                         }
 
                     }
+
+                    if entry_comparator.is_some() {
+                        comparator = entry_comparator;
+                    }
+
+                    for blob_file in blob_file_edit.add {
+                        if blob_files.insert(blob_file.id, blob_file).is_some() {
+                            return Err(io::Error::other(
+                                    "invalid blob file entry: ID already exists."
+                            ));
+                        }
+                    }
+
+                    for garbage in blob_file_edit.garbage {
+                        let Some(blob_file) = blob_files.get_mut(&garbage.file_id) else {
+                            return Err(io::Error::other(
+                                    "invalid blob garbage entry: blob file ID doesn't exist."
+                            ));
+                        };
+
+                        blob_file.garbage_count += garbage.count;
+                        blob_file.garbage_bytes += garbage.bytes;
+                    }
+
+                    for id in blob_file_edit.remove {
+                        if blob_files.remove(&id).is_none() {
+                            return Err(io::Error::other(
+                                    "invalid blob file remove entry: blob file ID doesn't exist."
+                            ));
+                        }
+                    }
+
+                    for (id, name) in added_column_families {
+                        if column_families.insert(id, name).is_some() {
+                            return Err(io::Error::other(
+                                    "invalid column family entry: ID already exists."
+                            ));
+                        }
+                    }
+
+                    for (family, sequence) in flushed {
+                        let flushed = flushed_wal_sequences.entry(family).or_insert(0);
+                        *flushed = sequence.max(*flushed);
+                    }
                 }
 
                 Ok(ReadEntryResult::Invalid) => {
@@ -114,7 +255,14 @@ where
 
         Ok(ReadResult {
             sstables,
-            next_sst_id
+            next_sst_id,
+            column_families,
+            comparator,
+            blob_files,
+            flushed_wal_sequences,
+            entry_count,
+            valid_len,
+            version: self.version,
         })
     }
 
@@ -123,9 +271,9 @@ where
         let mut added = Vec::<SSTableDesc>::new();
         let mut removed = Vec::<u64>::new();
 
-        let crc = self.0.read_u32()?;
-        let length = self.0.read_u32()?;
-        let buf = self.0.read_bytes_with_len(length as usize)?;
+        let crc = self.inner.read_u32()?;
+        let length = self.inner.read_u32()?;
+        let buf = self.inner.read_bytes_with_len(length as usize)?;
         if crc != crc32c(&buf) {
             return Ok(ReadEntryResult::Invalid);
         }
@@ -139,15 +287,40 @@ where
 
         for _ in 0..added_len {
             let id = reader.read_u64()?;
+
+            // SSTs recorded before version 3 all belong to the default column family
+            let family = if self.version >= 3 {
+                reader.read_u32()?
+            } else {
+                DEFAULT_COLUMN_FAMILY
+            };
+
             let level = reader.read_u8()?;
             let min_key = reader.read_string()?;
             let max_key = reader.read_string()?;
 
+            // Version 1 did not record any properties
+            let props = if self.version >= 2 {
+                SSTableProperties {
+                    file_size: reader.read_u64()?,
+                    entry_count: reader.read_u64()?,
+                    tombstone_count: reader.read_u64()?,
+                    smallest_flush_seq: reader.read_u64()?,
+                    largest_flush_seq: reader.read_u64()?,
+                    created_at: reader.read_u64()?,
+                    compression: reader.read_u8()?.try_into()?,
+                }
+            } else {
+                SSTableProperties::default()
+            };
+
             added.push(SSTableDesc {
                 id,
+                family,
                 level,
                 min_key,
                 max_key,
+                props,
             });
         }
 
@@ -159,11 +332,68 @@ where
             removed.push(id);
         }
 
-        Ok(ReadEntryResult::Update {
+        let mut column_families = Vec::new();
+
+        if self.version >= 3 {
+            let column_family_count = reader.read_u64()?;
+            column_families.reserve_exact(column_family_count as usize);
+
+            for _ in 0..column_family_count {
+                let id = reader.read_u32()?;
+                let name = reader.read_string()?;
+                column_families.push((id, name));
+            }
+        }
+
+        let comparator = if self.version >= 4 {
+            Some(reader.read_string()?).filter(|it| !it.is_empty())
+        } else {
+            None
+        };
+
+        let mut blob_files = BlobFileEdit::default();
+
+        if self.version >= 5 {
+            for _ in 0..reader.read_u64()? {
+                blob_files.add.push(BlobFileDesc {
+                    id: reader.read_u64()?,
+                    value_count: reader.read_u64()?,
+                    value_bytes: reader.read_u64()?,
+                    garbage_count: reader.read_u64()?,
+                    garbage_bytes: reader.read_u64()?,
+                });
+            }
+
+            for _ in 0..reader.read_u64()? {
+                blob_files.garbage.push(BlobGarbage {
+                    file_id: reader.read_u64()?,
+                    count: reader.read_u64()?,
+                    bytes: reader.read_u64()?,
+                });
+            }
+
+            for _ in 0..reader.read_u64()? {
+                blob_files.remove.push(reader.read_u64()?);
+            }
+        }
+
+        let mut flushed_wal_sequences = Vec::new();
+
+        if self.version >= 6 {
+            for _ in 0..reader.read_u64()? {
+                flushed_wal_sequences.push((reader.read_u32()?, reader.read_u64()?));
+            }
+        }
+
+        Ok(ReadEntryResult::Update(ManifestEntry {
             next_sst_id,
             added,
-            removed
-        })
+            removed,
+            column_families,
+            comparator,
+            blob_files,
+            flushed_wal_sequences,
+        }))
     }
 }
 
@@ -0,0 +1,217 @@
+//! Reference counted snapshots of the set of live SSTables and blob files.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
+use std::ops::RangeBounds;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::blob::BlobFileDesc;
+use crate::comparator::{Comparator, range_is_empty, range_overlaps};
+
+use super::SSTableDesc;
+
+/// An SSTable that is or was part of a [`Version`].
+///
+/// Once an SSTable is removed from the manifest it is marked as obsolete. The file is deleted
+/// when the last version referring to it is dropped, so readers that pinned an older version can
+/// keep reading it.
+pub(crate) struct LiveSSTable {
+    desc: SSTableDesc,
+    path: PathBuf,
+    obsolete: AtomicBool,
+}
+
+impl LiveSSTable {
+    pub(crate) fn new(desc: SSTableDesc, path: PathBuf) -> Self {
+        Self {
+            desc,
+            path,
+            obsolete: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for LiveSSTable {
+    fn drop(&mut self) {
+        if !self.obsolete.load(Ordering::Relaxed) {
+            return;
+        }
+
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Error removing sstable: {e}"),
+        }
+    }
+}
+
+/// A blob file that is or was part of a [`Version`]. Like [`LiveSSTable`]s, the file of an
+/// obsolete blob file is deleted once no version refers to it anymore.
+pub(crate) struct LiveBlobFile {
+    path: PathBuf,
+    obsolete: AtomicBool,
+}
+
+impl LiveBlobFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            obsolete: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn mark_obsolete(&self) {
+        self.obsolete.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for LiveBlobFile {
+    fn drop(&mut self) {
+        if !self.obsolete.load(Ordering::Relaxed) {
+            return;
+        }
+
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Error removing blob file: {e}"),
+        }
+    }
+}
+
+/// Blob files by ID, along with their garbage statistics as of the version.
+pub(crate) type LiveBlobFiles = BTreeMap<u64, (BlobFileDesc, Arc<LiveBlobFile>)>;
+
+/// An immutable snapshot of the SSTables and blob files listed in the manifest at some point in
+/// time.
+#[derive(Default, Clone)]
+pub struct Version {
+    sstables: BTreeMap<u64, Arc<LiveSSTable>>,
+    blob_files: LiveBlobFiles,
+
+    // Sequence number of the last WAL record flushed to the SSTs of each column family.
+    flushed_wal_sequences: BTreeMap<u32, u64>,
+}
+
+impl Version {
+    pub(crate) fn new(
+        sstables: BTreeMap<u64, Arc<LiveSSTable>>,
+        blob_files: LiveBlobFiles,
+        flushed_wal_sequences: BTreeMap<u32, u64>,
+    ) -> Self {
+        Self {
+            sstables,
+            blob_files,
+            flushed_wal_sequences,
+        }
+    }
+
+    pub(crate) fn live_sstables(&self) -> &BTreeMap<u64, Arc<LiveSSTable>> {
+        &self.sstables
+    }
+
+    pub(crate) fn live_blob_files(&self) -> &LiveBlobFiles {
+        &self.blob_files
+    }
+
+    pub(crate) fn flushed_wal_sequences(&self) -> &BTreeMap<u32, u64> {
+        &self.flushed_wal_sequences
+    }
+
+    /// Returns the sequence number of the last WAL record whose entries of the column family are
+    /// in the SSTs of this version, 0 if none is recorded. Entries of records up to it must not be
+    /// restored again.
+    pub fn flushed_wal_sequence(&self, family: u32) -> u64 {
+        self.flushed_wal_sequences.get(&family).copied().unwrap_or(0)
+    }
+
+    pub fn get_blob_files(&self) -> Vec<BlobFileDesc> {
+        self.blob_files.values().map(|(desc, _)| desc.clone()).collect()
+    }
+
+    fn descs(&self) -> impl Iterator<Item = &SSTableDesc> {
+        self.sstables.values().map(|it| &it.desc)
+    }
+
+    pub fn get_sstables(&self) -> Vec<SSTableDesc> {
+        let mut result: Vec<_> = self
+            .descs()
+            .cloned()
+            .collect();
+
+        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+
+        result
+    }
+
+    pub fn get_sstable_ids(&self) -> HashSet<u64> {
+        self.sstables.keys().copied().collect()
+    }
+
+    /// Returns the SSTables of the given column family.
+    fn family_descs(&self, family: u32) -> impl Iterator<Item = &SSTableDesc> {
+        self.descs().filter(move |it| it.family == family)
+    }
+
+    pub fn get_sstables_at_level(&self, family: u32, level: u8) -> Vec<SSTableDesc> {
+        let mut result: Vec<_> = self
+            .family_descs(family)
+            .filter(|it| it.level == level)
+            .cloned()
+            .collect();
+
+        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+
+        result
+    }
+
+    pub fn get_candidate_sstables_for_key(
+        &self,
+        family: u32,
+        key: &str,
+        comparator: &dyn Comparator,
+    ) -> Vec<SSTableDesc> {
+        let mut result: Vec<_> = self
+            .family_descs(family)
+            .filter(|sstable| {
+                comparator.compare(&sstable.min_key, key).is_le()
+                    && comparator.compare(&sstable.max_key, key).is_ge()
+            })
+            .cloned()
+            .collect();
+
+        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+
+        result
+    }
+
+    pub fn get_candidate_sstables_for_range<Range: RangeBounds<str>>(
+        &self,
+        family: u32,
+        range: Range,
+        comparator: &dyn Comparator,
+    ) -> Vec<SSTableDesc> {
+        if range_is_empty(comparator, &range) {
+            return vec![];
+        }
+
+        let mut result: Vec<_> = self
+            .family_descs(family)
+            .filter(|sstable| range_overlaps(comparator, &range, &sstable.min_key, &sstable.max_key))
+            .cloned()
+            .collect();
+
+        result.sort_unstable_by_key(|it| (it.level, Reverse(it.id)));
+
+        result
+    }
+}
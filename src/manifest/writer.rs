@@ -1,19 +1,20 @@
-/// Manifest file readering and writing routines.
-/// Manifest file format is specified in [docs/manifest-file-spec.md](docs/manifest-file-spec.md).
+//! Manifest file readering and writing routines.
+//! Manifest file format is specified in [docs/manifest-file-spec.md](docs/manifest-file-spec.md).
 
 use std::fs::File;
+use std::fs::OpenOptions;
 use std::io;
 use std::io::Write;
 use std::io::SeekFrom;
 use std::io::Seek;
-use std::sync::atomic::Ordering;
+use std::path::Path;
 
 use crate::crc::crc32c;
 use crate::io_ext::WriteExt;
 
-use super::ManifestUpdate;
-use super::SSTableDesc;
+use super::reader::ManifestEntry;
 use super::MAGIC;
+use super::VERSION;
 
 /// Writer for manifest files.
 ///
@@ -51,6 +52,28 @@ impl ManifestWriter {
         })
     }
 
+    /// Creates a new, empty manifest file at the given path, replacing any existing file.
+    pub fn create(path: &Path) -> io::Result<ManifestWriter> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .read(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        Self::write_header(&mut file)?;
+
+        Ok(ManifestWriter {
+            file,
+        })
+    }
+
+    /// Flushes written entries to disk and returns the underlying file.
+    pub fn sync(self) -> io::Result<File> {
+        self.file.sync_all()?;
+        Ok(self.file)
+    }
+
     pub fn ensure_header(file: &mut File) -> io::Result<()> {
         let pos = file.seek(SeekFrom::End(0))?;
 
@@ -62,29 +85,70 @@ impl ManifestWriter {
         Ok(())
     }
 
-    pub fn write(
-        &mut self,
-        add: &[SSTableDesc],
-        remove: &[u64],
-        next_sst_id: u64
-    ) -> io::Result<()> {
+    pub fn write(&mut self, entry: &ManifestEntry) -> io::Result<()> {
         let mut buf = Vec::new();
 
-        buf.write_u64(next_sst_id)?;
+        buf.write_u64(entry.next_sst_id)?;
 
-        buf.write_u64(add.len() as u64)?;
-        for sst in add.iter() {
+        buf.write_u64(entry.added.len() as u64)?;
+        for sst in entry.added.iter() {
             buf.write_u64(sst.id)?;
+            buf.write_u32(sst.family)?;
             buf.write_u8(sst.level)?;
             buf.write_string(&sst.min_key)?;
             buf.write_string(&sst.max_key)?;
+            buf.write_u64(sst.props.file_size)?;
+            buf.write_u64(sst.props.entry_count)?;
+            buf.write_u64(sst.props.tombstone_count)?;
+            buf.write_u64(sst.props.smallest_flush_seq)?;
+            buf.write_u64(sst.props.largest_flush_seq)?;
+            buf.write_u64(sst.props.created_at)?;
+            buf.write_u8(sst.props.compression as u8)?;
         }
 
-        buf.write_u64(remove.len() as u64)?;
-        for sst_id in remove.iter() {
+        buf.write_u64(entry.removed.len() as u64)?;
+        for sst_id in entry.removed.iter() {
             buf.write_u64(*sst_id)?;
         }
 
+        buf.write_u64(entry.column_families.len() as u64)?;
+        for (id, name) in entry.column_families.iter() {
+            buf.write_u32(*id)?;
+            buf.write_string(name)?;
+        }
+
+        // Empty if the entry doesn't record a comparator
+        buf.write_string(entry.comparator.as_deref().unwrap_or_default())?;
+
+        let blob_files = &entry.blob_files;
+
+        buf.write_u64(blob_files.add.len() as u64)?;
+        for blob_file in blob_files.add.iter() {
+            buf.write_u64(blob_file.id)?;
+            buf.write_u64(blob_file.value_count)?;
+            buf.write_u64(blob_file.value_bytes)?;
+            buf.write_u64(blob_file.garbage_count)?;
+            buf.write_u64(blob_file.garbage_bytes)?;
+        }
+
+        buf.write_u64(blob_files.garbage.len() as u64)?;
+        for garbage in blob_files.garbage.iter() {
+            buf.write_u64(garbage.file_id)?;
+            buf.write_u64(garbage.count)?;
+            buf.write_u64(garbage.bytes)?;
+        }
+
+        buf.write_u64(blob_files.remove.len() as u64)?;
+        for id in blob_files.remove.iter() {
+            buf.write_u64(*id)?;
+        }
+
+        buf.write_u64(entry.flushed_wal_sequences.len() as u64)?;
+        for (family, sequence) in entry.flushed_wal_sequences.iter() {
+            buf.write_u32(*family)?;
+            buf.write_u64(*sequence)?;
+        }
+
         let crc = crc32c(&buf);
         let length = buf.len() as u32;
 
@@ -103,7 +167,7 @@ impl ManifestWriter {
         file.write_u32(MAGIC)?;
 
         // Version
-        file.write_u8(1)?;
+        file.write_u8(VERSION)?;
 
         file.sync_data()?;
         Ok(())
@@ -0,0 +1,101 @@
+//! In-memory table of the latest writes, sorted by the comparator of the store.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use crate::comparator::{Comparator, range_is_empty};
+use crate::entry::Entry;
+
+/// A key ordered by a comparator rather than by its bytes.
+#[derive(Clone)]
+struct MemtableKey {
+    key: String,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl PartialEq for MemtableKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MemtableKey {}
+
+impl PartialOrd for MemtableKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MemtableKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.comparator.compare(&self.key, &other.key)
+    }
+}
+
+pub(crate) struct Memtable {
+    entries: BTreeMap<MemtableKey, Entry>,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl Memtable {
+    pub fn new(comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            comparator,
+        }
+    }
+
+    fn key(&self, key: &str) -> MemtableKey {
+        MemtableKey {
+            key: key.to_owned(),
+            comparator: self.comparator.clone(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.get(&self.key(key))
+    }
+
+    pub fn insert(&mut self, key: String, entry: Entry) {
+        let key = MemtableKey {
+            key,
+            comparator: self.comparator.clone(),
+        };
+
+        self.entries.insert(key, entry);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the entries in the order of the comparator.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&str, &Entry)> {
+        self.entries.iter().map(|(key, entry)| (key.key.as_str(), entry))
+    }
+
+    /// Returns the entries in `range`, the bounds of which are ordered by the comparator.
+    pub fn range<R: RangeBounds<str>>(&self, range: R) -> Box<dyn Iterator<Item = (&str, &Entry)> + '_> {
+        // BTreeMap panics on ranges that end before they start.
+        if range_is_empty(self.comparator.as_ref(), &range) {
+            return Box::new(std::iter::empty());
+        }
+
+        let bound = |bound: Bound<&str>| bound.map(|key| self.key(key));
+        let range = (bound(range.start_bound()), bound(range.end_bound()));
+
+        Box::new(
+            self.entries
+                .range(range)
+                .map(|(key, entry)| (key.key.as_str(), entry)),
+        )
+    }
+}
@@ -0,0 +1,189 @@
+//! Read-modify-write updates that are combined lazily by a user-supplied operator.
+
+use std::io;
+
+use crate::entry::Entry;
+
+/// Combines operands written through [`crate::Store::merge`] with the value of a key.
+///
+/// Operands are stored as they are written and only combined when the key is read, or when they
+/// meet an older value of the key during compaction. An operator may therefore be invoked on a
+/// value it returned earlier, together with more operands.
+pub trait MergeOperator: Send + Sync {
+    /// Applies `operands`, oldest first, to the `existing` value of `key`, which is None if the key
+    /// has no value.
+    fn merge(&self, key: &str, existing: Option<&[u8]>, operands: &[Vec<u8>]) -> io::Result<Vec<u8>>;
+}
+
+/// Adds operands to the value, all of them being big endian u64s. Overflow wraps around.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U64AddOperator;
+
+impl U64AddOperator {
+    fn decode(key: &str, bytes: &[u8]) -> io::Result<u64> {
+        let bytes: [u8; 8] = bytes.try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("value of '{key}' is not a u64"),
+            )
+        })?;
+
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn merge(&self, key: &str, existing: Option<&[u8]>, operands: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+        let mut sum = match existing {
+            Some(existing) => Self::decode(key, existing)?,
+            None => 0,
+        };
+
+        for operand in operands {
+            sum = sum.wrapping_add(Self::decode(key, operand)?);
+        }
+
+        Ok(sum.to_be_bytes().to_vec())
+    }
+}
+
+/// Appends operands to the value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn merge(&self, _: &str, existing: Option<&[u8]>, operands: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+        let mut value = existing.map(<[u8]>::to_vec).unwrap_or_default();
+
+        for operand in operands {
+            value.extend_from_slice(operand);
+        }
+
+        Ok(value)
+    }
+}
+
+/// Collapses versions of `key`, newest first, into a single entry.
+///
+/// Merge operands are combined with the first value or tombstone found below them, an expired
+/// value counting as no value, as does a tombstone. Versions after that value are ignored. If there is no such value, operands are
+/// combined on their own when `complete` is true, meaning no older versions of the key exist
+/// elsewhere, and are otherwise kept as a single merge record.
+pub(crate) fn collapse<I>(
+    operator: Option<&dyn MergeOperator>,
+    key: &str,
+    versions: I,
+    now: u64,
+    complete: bool,
+) -> io::Result<Entry>
+where
+    I: IntoIterator<Item = Entry>,
+{
+    // Operand lists, newest first
+    let mut pending: Vec<Vec<Vec<u8>>> = Vec::new();
+    let mut base = None;
+
+    for version in versions {
+        match version {
+            Entry::Merge(operands) => pending.push(operands),
+            value => {
+                base = Some(value);
+                break;
+            }
+        }
+    }
+
+    if pending.is_empty() {
+        return base.ok_or_else(|| io::Error::other("BUG: collapse called without versions"));
+    }
+
+    let operands: Vec<Vec<u8>> = pending.into_iter().rev().flatten().collect();
+
+    let (existing, expires_at) = match base {
+        Some(Entry::Value { value, expires_at }) if expires_at.is_none_or(|it| it > now) => {
+            (Some(value), expires_at)
+        }
+
+        // The value has expired or the key was deleted
+        Some(_) => (None, None),
+        None if complete => (None, None),
+        None => return Ok(Entry::Merge(operands)),
+    };
+
+    let operator = operator.ok_or_else(|| {
+        io::Error::other(format!("found merge operands for '{key}' but no merge operator is set"))
+    })?;
+
+    Ok(Entry::Value {
+        value: operator.merge(key, existing.as_deref(), &operands)?,
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u64_entry(n: u64) -> Entry {
+        Entry::new(n.to_be_bytes().to_vec())
+    }
+
+    fn u64_operands(ns: &[u64]) -> Entry {
+        Entry::Merge(ns.iter().map(|it| it.to_be_bytes().to_vec()).collect())
+    }
+
+    #[test]
+    fn test_collapse_combines_operands_with_first_value() {
+        let operator = U64AddOperator;
+
+        let collapsed = collapse(
+            Some(&operator),
+            "key",
+            vec![u64_operands(&[3]), u64_operands(&[1, 2]), u64_entry(10), u64_entry(100)],
+            0,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(collapsed, u64_entry(16));
+    }
+
+    #[test]
+    fn test_collapse_keeps_operands_without_value_unless_complete() {
+        let operator = AppendOperator;
+        let versions = vec![
+            Entry::Merge(vec![b"c".to_vec()]),
+            Entry::Merge(vec![b"a".to_vec(), b"b".to_vec()]),
+        ];
+
+        let collapsed = collapse(Some(&operator), "key", versions.clone(), 0, false).unwrap();
+        assert_eq!(collapsed, Entry::Merge(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]));
+
+        let collapsed = collapse(Some(&operator), "key", versions, 0, true).unwrap();
+        assert_eq!(collapsed, Entry::new(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn test_collapse_ignores_expired_value() {
+        let operator = AppendOperator;
+        let versions = vec![
+            Entry::Merge(vec![b"new".to_vec()]),
+            Entry::Value {
+                value: b"old".to_vec(),
+                expires_at: Some(1),
+            },
+        ];
+
+        let collapsed = collapse(Some(&operator), "key", versions, 2, false).unwrap();
+        assert_eq!(collapsed, Entry::new(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_u64_add_rejects_malformed_operands() {
+        let err = U64AddOperator
+            .merge("key", None, &[b"short".to_vec()])
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,85 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::compaction_filter::CompactionFilter;
+use crate::merge_operator::MergeOperator;
+use crate::prefix_extractor::PrefixExtractor;
+
+/// Options a store is opened with.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Order of the keys, [`BytewiseComparator`] if None. It can't be changed once the store is
+    /// created. Column families use the comparator of the store and may only leave this None or
+    /// set it to a comparator of the same name.
+    pub comparator: Option<Arc<dyn Comparator>>,
+
+    /// Filter applied to entries as they are compacted.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// TTL of entries inserted without one through [`crate::Store::insert`] and
+    /// [`crate::Store::insert_batch`]. Entries never expire if this is None.
+    pub default_ttl: Option<Duration>,
+
+    /// Operator combining operands written through [`crate::Store::merge`] with values.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+
+    /// Extracts the key prefixes that SSTs keep bloom filters of, letting
+    /// [`crate::Store::scan_prefix`] skip SSTs and chunks without keys of the scanned prefix. SSTs
+    /// written before it was set, or with an extractor of another name, are not skipped.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+
+    /// Values at least this many bytes long are moved to blob files as they are written to SSTs,
+    /// which only keep a reference to them, so compactions don't rewrite them. Every value is
+    /// kept in the SSTs if this is None.
+    pub blob_threshold: Option<usize>,
+
+    /// Directory the records of the WAL are copied to before it is truncated, so writes can be
+    /// replayed onto a backup up to a point in time, see
+    /// [`crate::BackupEngine::restore_backup_until`]. Records are discarded if this is None. The
+    /// WAL is shared by all column families, so this is only read from the store options.
+    ///
+    /// Archived files are kept until [`crate::BackupEngine::purge_wal_archive`] removes them.
+    pub wal_archive_dir: Option<PathBuf>,
+}
+
+impl Options {
+    pub(crate) fn comparator(&self) -> Arc<dyn Comparator> {
+        self.comparator
+            .clone()
+            .unwrap_or_else(|| Arc::new(BytewiseComparator))
+    }
+}
+
+/// Bounds on the entries returned by a range read.
+#[derive(Debug, Clone, Default)]
+pub struct RangeOptions {
+    /// Number of entries at the start of the range to skip.
+    pub offset: usize,
+
+    /// Largest number of entries to return, after skipping `offset` entries. Unbounded if None.
+    pub limit: Option<usize>,
+}
+
+impl RangeOptions {
+    /// Applies the offset and limit to `items`. Errors are never skipped.
+    pub(crate) fn apply<T, I>(&self, items: I) -> impl Iterator<Item = io::Result<T>> + use<T, I>
+    where
+        I: Iterator<Item = io::Result<T>>,
+    {
+        let mut to_skip = self.offset;
+
+        items
+            .filter(move |item| {
+                if item.is_err() || to_skip == 0 {
+                    return true;
+                }
+
+                to_skip -= 1;
+                false
+            })
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+}
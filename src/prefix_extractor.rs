@@ -0,0 +1,94 @@
+//! Prefixes of keys that SSTs keep bloom filters of.
+
+/// Extracts the prefix of a key that [`crate::Store::scan_prefix`] is usually called with.
+///
+/// Every SST records a bloom filter of the prefixes of its keys, per chunk, which lets prefix scans
+/// skip SSTs and chunks that have no key of the scanned prefix.
+pub trait PrefixExtractor: Send + Sync {
+    /// Identifies the extractor. SSTs record the name of the extractor their filters were built
+    /// with, and filters of another extractor are not used.
+    fn name(&self) -> &str;
+
+    /// Returns the prefix of `key`, or None if it has none.
+    ///
+    /// The prefix of every key starting with the returned prefix must be that same prefix.
+    fn prefix<'k>(&self, key: &'k str) -> Option<&'k str>;
+}
+
+/// Takes the first `len` bytes of keys as their prefix. Shorter keys have no prefix.
+#[derive(Debug, Clone)]
+pub struct FixedPrefix {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefix {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("sand.fixed.{len}"),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'k>(&self, key: &'k str) -> Option<&'k str> {
+        key.get(..self.len)
+    }
+}
+
+/// Takes keys up to and including the `count`th occurrence of `delimiter` as their prefix, so with
+/// a count of 2 the prefix of `user:123:name` is `user:123:`. Keys with fewer delimiters have no
+/// prefix.
+#[derive(Debug, Clone)]
+pub struct DelimitedPrefix {
+    delimiter: char,
+    count: usize,
+    name: String,
+}
+
+impl DelimitedPrefix {
+    pub fn new(delimiter: char, count: usize) -> Self {
+        Self {
+            delimiter,
+            count,
+            name: format!("sand.delimited.{count}.{delimiter}"),
+        }
+    }
+}
+
+impl PrefixExtractor for DelimitedPrefix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'k>(&self, key: &'k str) -> Option<&'k str> {
+        let (index, delimiter) = key
+            .match_indices(self.delimiter)
+            .nth(self.count.checked_sub(1)?)?;
+
+        Some(&key[..index + delimiter.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extractors_take_prefixes() {
+        let fixed = FixedPrefix::new(5);
+        assert_eq!(fixed.prefix("user:123"), Some("user:"));
+        assert_eq!(fixed.prefix("user"), None);
+
+        let delimited = DelimitedPrefix::new(':', 2);
+        assert_eq!(delimited.prefix("user:123:name"), Some("user:123:"));
+        assert_eq!(delimited.prefix("user:123:"), Some("user:123:"));
+        assert_eq!(delimited.prefix("user:123"), None);
+        assert_eq!(DelimitedPrefix::new(':', 0).prefix("user:123:name"), None);
+    }
+}
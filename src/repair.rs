@@ -0,0 +1,415 @@
+//! Recovery of a store whose manifest is lost or corrupted.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::blob::{blob_file_path, parse_blob_filename, read_blob_file_desc};
+use crate::comparator::{Comparator, builtin_comparators};
+use crate::entry::Entry;
+use crate::manifest::reader::ReadResult;
+use crate::manifest::{
+    DEFAULT_COLUMN_FAMILY, SSTableDesc, lock_directory, read_active_manifest,
+    write_repaired_manifest,
+};
+use crate::sstable::reader::{RawSSTableReader, SSTChunkIterator};
+use crate::sstable::{Compression, SSTableProperties, parse_sst_filename, sst_file_path};
+use crate::wal;
+
+/// Directory, within the store directory, that files which can't be recovered are moved to.
+const LOST_DIRNAME: &str = "lost";
+
+/// Outcome of [`repair`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub sstable_count: usize,
+    pub blob_file_count: usize,
+
+    /// Number of records kept in the WAL.
+    pub wal_record_count: usize,
+
+    /// Files moved to the `lost` directory, because they are unreadable or empty, their keys are
+    /// not sorted by the comparator of the store, or the manifest removed them already.
+    pub lost: Vec<String>,
+}
+
+/// SST read back from its file.
+struct ScannedSSTable {
+    min_key: String,
+    max_key: String,
+    file_size: u64,
+    created_at: u64,
+    entry_count: u64,
+    tombstone_count: u64,
+
+    /// Whether the keys are sorted by each of [`builtin_comparators`].
+    sorted: Vec<bool>,
+
+    /// Number of values and their size in bytes, for every blob file referred to.
+    blob_refs: BTreeMap<u64, (u64, u64)>,
+}
+
+/// Rebuilds the manifest of the store in `directory` from its SST files, for when the manifest
+/// is lost or corrupted. The store must not be open.
+///
+/// Whatever can still be read of the old manifest is kept: the comparator, the column families,
+/// and the level and column family of every SST it records. If the old manifest is intact, SSTs
+/// it doesn't record with lower IDs than any it allocated are left over from before it removed
+/// them, and are moved to the `lost` directory. Other SSTs it doesn't know of are added to level
+/// 0, where the one with the highest ID takes precedence. SST files don't record their column
+/// family, so these are added to the default column family, even if they were written for
+/// another one. Without a recorded comparator, the first built-in one that sorts the keys of
+/// every SST is recorded, if any does.
+///
+/// The WAL keeps the records up to its first torn or corrupted one. Files that can't be
+/// recovered are moved to a `lost` directory rather than deleted.
+pub fn repair(directory: impl AsRef<Path>) -> io::Result<RepairReport> {
+    let directory = directory.as_ref();
+    let _lock = lock_directory(directory)?;
+
+    let salvaged = read_active_manifest(directory).ok();
+
+    // A manifest read up to its end has every SST it allocated an ID for added or removed by
+    // then, unless it was written since, so SSTs it doesn't record below that are orphans.
+    let complete = salvaged.as_ref().is_some_and(|(path, state)| {
+        fs::metadata(path).is_ok_and(|it| it.len() == state.valid_len)
+    });
+
+    let salvaged = salvaged.map(|(_, state)| state);
+    let mut report = RepairReport::default();
+
+    let mut sst_ids = Vec::new();
+    let recorded_next_sst_id = salvaged.as_ref().map(|it| it.next_sst_id).unwrap_or(0);
+    let mut next_sst_id = recorded_next_sst_id;
+
+    for entry in fs::read_dir(directory)? {
+        let filename = entry?.file_name();
+        let Some(filename) = filename.to_str() else {
+            continue;
+        };
+
+        if let Some(id) = parse_sst_filename(filename) {
+            sst_ids.push(id);
+            next_sst_id = next_sst_id.max(id + 1);
+        } else if let Some(id) = parse_blob_filename(filename) {
+            next_sst_id = next_sst_id.max(id + 1);
+        }
+    }
+
+    sst_ids.sort();
+
+    let comparators = builtin_comparators();
+    let mut scanned = BTreeMap::new();
+
+    for id in sst_ids {
+        let recorded = salvaged.as_ref().is_some_and(|it| it.sstables.contains_key(&id));
+
+        // Such as compaction inputs, which would shadow newer versions of their keys at level 0.
+        if complete && !recorded && id < recorded_next_sst_id {
+            move_to_lost(directory, &sst_file_path(directory, id), &mut report)?;
+            continue;
+        }
+
+        match scan_sstable(directory, id, &comparators) {
+            Ok(sstable) if sstable.entry_count > 0 => {
+                scanned.insert(id, sstable);
+            }
+            _ => move_to_lost(directory, &sst_file_path(directory, id), &mut report)?,
+        }
+    }
+
+    let comparator = salvaged.as_ref().and_then(|it| it.comparator.clone()).or_else(|| {
+        comparators
+            .iter()
+            .enumerate()
+            .find(|(i, _)| scanned.values().all(|it| it.sorted[*i]))
+            .map(|(_, it)| it.name().to_owned())
+    });
+
+    // Keys can only be checked against a built-in comparator.
+    if let Some(index) = comparators.iter().position(|it| Some(it.name()) == comparator.as_deref()) {
+        let unsorted: Vec<_> = scanned
+            .iter()
+            .filter(|(_, it)| !it.sorted[index])
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in unsorted {
+            scanned.remove(&id);
+            move_to_lost(directory, &sst_file_path(directory, id), &mut report)?;
+        }
+    }
+
+    let sstables: Vec<_> = scanned
+        .iter()
+        .map(|(id, sstable)| sstable_desc(*id, sstable, salvaged.as_ref()))
+        .collect();
+
+    let mut blob_refs: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    for sstable in scanned.values() {
+        for (file_id, (count, bytes)) in sstable.blob_refs.iter() {
+            let refs = blob_refs.entry(*file_id).or_default();
+            refs.0 += count;
+            refs.1 += bytes;
+        }
+    }
+
+    // Blob files nothing refers to are left for the store to remove as orphans when it's opened,
+    // and the values of missing ones stay unreadable.
+    let mut blob_files = Vec::new();
+    for (file_id, (count, bytes)) in blob_refs {
+        let recorded = salvaged.as_ref().and_then(|it| it.blob_files.get(&file_id));
+
+        let desc = match recorded {
+            Some(desc) => desc.clone(),
+            None => match read_blob_file_desc(&blob_file_path(directory, file_id), file_id) {
+                Ok(mut desc) => {
+                    desc.garbage_count = desc.value_count.saturating_sub(count);
+                    desc.garbage_bytes = desc.value_bytes.saturating_sub(bytes);
+                    desc
+                }
+                Err(_) => continue,
+            },
+        };
+
+        blob_files.push(desc);
+    }
+
+    let (column_families, flushed_wal_sequences): (Vec<_>, Vec<_>) = salvaged
+        .map(|it| {
+            (
+                it.column_families.into_iter().collect(),
+                it.flushed_wal_sequences.into_iter().collect(),
+            )
+        })
+        .unwrap_or_default();
+
+    report.sstable_count = sstables.len();
+    report.blob_file_count = blob_files.len();
+
+    write_repaired_manifest(
+        directory,
+        &sstables,
+        &column_families,
+        comparator.as_deref(),
+        blob_files,
+        flushed_wal_sequences,
+        next_sst_id,
+    )?;
+
+    let wal_path = directory.join(wal::FILENAME);
+    if wal_path.exists() {
+        report.wal_record_count = wal::salvage_wal_file(&wal_path)?;
+    }
+
+    Ok(report)
+}
+
+/// Reads every entry of an SST, failing if any part of it can't be read.
+fn scan_sstable(
+    directory: &Path,
+    id: u64,
+    comparators: &[Arc<dyn Comparator>],
+) -> io::Result<ScannedSSTable> {
+    let path = sst_file_path(directory, id);
+    let metadata = fs::metadata(&path)?;
+
+    let chunk_descs = RawSSTableReader::open(path.clone())?.list_chunks()?;
+    let (Some(first), Some(last)) = (chunk_descs.first(), chunk_descs.last()) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "SST has no chunks"));
+    };
+
+    let mut sstable = ScannedSSTable {
+        min_key: first.min_key.clone(),
+        max_key: last.max_key.clone(),
+        file_size: metadata.len(),
+        created_at: metadata
+            .modified()
+            .ok()
+            .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+            .map(|it| it.as_secs())
+            .unwrap_or(0),
+        entry_count: 0,
+        tombstone_count: 0,
+        sorted: vec![true; comparators.len()],
+        blob_refs: BTreeMap::new(),
+    };
+
+    let mut last_key: Option<String> = None;
+
+    for chunk in SSTChunkIterator::open(path)? {
+        for (key, entry) in chunk? {
+            sstable.entry_count += 1;
+
+            match entry {
+                Entry::Tombstone => sstable.tombstone_count += 1,
+                Entry::Blob { blob, .. } => {
+                    let refs = sstable.blob_refs.entry(blob.file_id).or_default();
+                    refs.0 += 1;
+                    refs.1 += blob.size;
+                }
+                _ => {}
+            }
+
+            if let Some(last_key) = &last_key {
+                for (sorted, comparator) in sstable.sorted.iter_mut().zip(comparators) {
+                    *sorted &= comparator.compare(last_key, &key).is_lt();
+                }
+            }
+
+            last_key = Some(key);
+        }
+    }
+
+    Ok(sstable)
+}
+
+/// Describes a scanned SST, taking what the file doesn't hold from the salvaged manifest.
+fn sstable_desc(id: u64, sstable: &ScannedSSTable, salvaged: Option<&ReadResult>) -> SSTableDesc {
+    let recorded = salvaged.and_then(|it| it.sstables.get(&id));
+
+    SSTableDesc {
+        id,
+        family: recorded.map(|it| it.family).unwrap_or(DEFAULT_COLUMN_FAMILY),
+        level: recorded.map(|it| it.level).unwrap_or(0),
+        min_key: sstable.min_key.clone(),
+        max_key: sstable.max_key.clone(),
+        props: SSTableProperties {
+            file_size: sstable.file_size,
+            entry_count: sstable.entry_count,
+            tombstone_count: sstable.tombstone_count,
+
+            // Flush sequence numbers are not stored in the file, 0 marks them as unknown.
+            smallest_flush_seq: recorded.map(|it| it.props.smallest_flush_seq).unwrap_or(0),
+            largest_flush_seq: recorded.map(|it| it.props.largest_flush_seq).unwrap_or(0),
+            created_at: recorded.map(|it| it.props.created_at).unwrap_or(sstable.created_at),
+            compression: Compression::None,
+        },
+    }
+}
+
+fn move_to_lost(directory: &Path, path: &Path, report: &mut RepairReport) -> io::Result<()> {
+    let lost = directory.join(LOST_DIRNAME);
+    fs::create_dir_all(&lost)?;
+
+    let Some(filename) = path.file_name() else {
+        return Ok(());
+    };
+
+    fs::rename(path, lost.join(filename))?;
+    report.lost.push(filename.to_string_lossy().into_owned());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+
+    use crate::manifest::is_manifest_filename;
+    use crate::store::Store;
+    use crate::store_impl::make_store;
+
+    #[test]
+    fn test_repair_rebuilds_lost_manifest() {
+        let dir = PathBuf::from("test_repair_rebuilds_lost_manifest");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        for i in 0..1000 {
+            store.insert(&format!("key{i:04}"), b"old").unwrap();
+        }
+        store.flush().unwrap();
+
+        // Newer versions are in the SST with the higher ID.
+        for i in 0..500 {
+            store.insert(&format!("key{i:04}"), b"new").unwrap();
+        }
+        store.flush().unwrap();
+        drop(store);
+
+        let mut sstables = Vec::new();
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let filename = path.file_name().unwrap().to_str().unwrap();
+
+            if let Some(id) = parse_sst_filename(filename) {
+                sstables.push(id);
+            } else if is_manifest_filename(filename) || filename == "CURRENT" {
+                fs::remove_file(&path).unwrap();
+            }
+        }
+        sstables.sort();
+
+        fs::write(sst_file_path(&dir, sstables[1] + 1), b"garbage").unwrap();
+
+        // Entries left unflushed, the last of which is torn.
+        let mut wal = wal::Wal::new(&dir, None).unwrap();
+        wal.records().unwrap().for_each(drop);
+        wal.log_one(DEFAULT_COLUMN_FAMILY, "unflushed", &Entry::new(b"value".to_vec())).unwrap();
+        wal.log_one(DEFAULT_COLUMN_FAMILY, "torn", &Entry::new(b"value".to_vec())).unwrap();
+        drop(wal);
+
+        let wal = OpenOptions::new().write(true).open(dir.join(wal::FILENAME)).unwrap();
+        let len = wal.metadata().unwrap().len();
+        wal.set_len(len - 3).unwrap();
+        drop(wal);
+
+        let report = repair(&dir).unwrap();
+        assert_eq!(report.sstable_count, 2);
+        assert_eq!(report.wal_record_count, 1);
+        assert_eq!(report.lost, vec![format!("sstable_{:016}.sst", sstables[1] + 1)]);
+
+        let store = make_store(dir.clone()).unwrap();
+        assert_eq!(store.get("key0000").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get("key0500").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(store.get("unflushed").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(store.get("torn").unwrap(), None);
+
+        // New SSTs don't reuse the IDs of the recovered ones.
+        store.flush().unwrap();
+        assert_eq!(store.get("key0000").unwrap(), Some(b"new".to_vec()));
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_moves_sstables_removed_by_intact_manifest_to_lost() {
+        let dir = PathBuf::from("test_repair_moves_sstables_removed_by_intact_manifest_to_lost");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("key", b"old").unwrap();
+        store.flush().unwrap();
+
+        let input = sst_file_path(&dir, 0);
+        let contents = fs::read(&input).unwrap();
+
+        store.insert("key", b"new").unwrap();
+        store.compact_range(.., 1).unwrap();
+        drop(store);
+
+        // A compaction input whose deletion didn't happen before a crash.
+        assert!(!input.exists());
+        fs::write(&input, contents).unwrap();
+
+        let report = repair(&dir).unwrap();
+        assert_eq!(report.sstable_count, 1);
+        assert_eq!(report.lost, vec!["sstable_0000000000000000.sst".to_owned()]);
+
+        let store = make_store(dir.clone()).unwrap();
+        assert_eq!(store.get("key").unwrap(), Some(b"new".to_vec()));
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
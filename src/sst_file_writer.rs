@@ -0,0 +1,105 @@
+//! SSTs built outside of a store, for bulk loading.
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::comparator::Comparator;
+use crate::entry::Entry;
+use crate::options::Options;
+use crate::sstable::writer::SSTableWriter;
+
+/// Writes an SST outside of any store, to be added to one through
+/// [`crate::Store::ingest_external_files`]. This skips the WAL, the memtable and the compactions
+/// that loading the same entries through writes would go through.
+///
+/// Keys must be added in ascending order of the comparator, each at most once.
+pub struct SstFileWriter {
+    writer: SSTableWriter,
+    comparator: Arc<dyn Comparator>,
+    last_key: Option<String>,
+    entry_count: u64,
+    finished: bool,
+}
+
+impl SstFileWriter {
+    /// Creates the SST at `path`. The comparator and prefix extractor of `options` should be the
+    /// ones of the column family the SST is ingested into, other options are ignored.
+    pub fn create(path: impl AsRef<Path>, options: &Options) -> io::Result<Self> {
+        let mut writer = SSTableWriter::create(path.as_ref())?;
+
+        if let Some(prefix_extractor) = options.prefix_extractor.clone() {
+            writer.set_prefix_extractor(prefix_extractor);
+        }
+
+        Ok(Self {
+            writer,
+            comparator: options.comparator(),
+            last_key: None,
+            entry_count: 0,
+            finished: false,
+        })
+    }
+
+    pub fn put(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.write(key, &Entry::new(value.to_owned()))
+    }
+
+    /// Adds a value that is treated as absent from `expires_at` on, in milliseconds since the UNIX
+    /// epoch.
+    pub fn put_with_expiry(&mut self, key: &str, value: &[u8], expires_at: u64) -> io::Result<()> {
+        let entry = Entry::Value {
+            value: value.to_owned(),
+            expires_at: Some(expires_at),
+        };
+
+        self.write(key, &entry)
+    }
+
+    /// Adds a merge operand, combined with the version of the key the store already has once the
+    /// SST is ingested.
+    pub fn merge(&mut self, key: &str, operand: &[u8]) -> io::Result<()> {
+        self.write(key, &Entry::Merge(vec![operand.to_owned()]))
+    }
+
+    /// Deletes the key, shadowing the versions of it the store already has once the SST is
+    /// ingested.
+    pub fn delete(&mut self, key: &str) -> io::Result<()> {
+        self.write(key, &Entry::Tombstone)
+    }
+
+    /// Completes the SST, syncing it to disk, and returns the number of entries written.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.finished = true;
+        self.writer.finalize()?;
+
+        Ok(self.entry_count)
+    }
+
+    fn write(&mut self, key: &str, entry: &Entry) -> io::Result<()> {
+        if let Some(last_key) = &self.last_key
+            && self.comparator.compare(last_key, key).is_ge()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("key '{key}' is not greater than the previous key '{last_key}'"),
+            ));
+        }
+
+        self.writer.write(key, entry)?;
+        self.last_key = Some(key.to_owned());
+        self.entry_count += 1;
+
+        Ok(())
+    }
+}
+
+impl Drop for SstFileWriter {
+    /// Completes an SST that was dropped without being finished, such as when the caller ran into
+    /// an error, which [`SSTableWriter`] would otherwise report as a bug.
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.writer.finalize();
+        }
+    }
+}
@@ -1,5 +1,5 @@
 const MAGIC: u32 = 0xFAA7BEEF;
-const VERSION: u8 = 1;
+const VERSION: u8 = 5;
 
 const OS_PAGE_SIZE: usize = 4096; // 4 KiB
 
@@ -14,8 +14,11 @@ const CHUNK_SIZE_TARGET: usize = OS_PAGE_SIZE;
 pub mod reader;
 pub mod writer;
 
+use std::io;
 use std::path::{Path, PathBuf};
 
+use crate::datastructure::bloom::{self, BloomFilter};
+
 #[derive(Debug, Clone)]
 pub struct ChunkDesc {
     pub index: usize,
@@ -24,10 +27,97 @@ pub struct ChunkDesc {
     pub max_key: String,
 }
 
+/// Bloom filters of the key prefixes in each chunk of an SST.
+#[derive(Debug, Clone)]
+pub struct PrefixFilter {
+    /// Name of the prefix extractor the prefixes were taken with.
+    pub extractor: String,
+
+    /// Filter of each chunk, by chunk index.
+    pub chunks: Vec<BloomFilter>,
+}
+
+impl PrefixFilter {
+    /// Returns false if no key of the chunk certainly has `prefix`.
+    pub fn may_contain(&self, chunk_index: usize, prefix: &str) -> bool {
+        self.chunks
+            .get(chunk_index)
+            .is_none_or(|it| it.may_contain(bloom::hash(prefix.as_bytes())))
+    }
+}
+
+/// Compression codec applied to the chunks of an SST.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    #[default]
+    None = 0,
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> io::Result<Self> {
+        match value {
+            0 => Ok(Compression::None),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown compression codec: {value}"),
+            )),
+        }
+    }
+}
+
+/// Properties of an SST collected while it is being written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SSTableProperties {
+    pub file_size: u64,
+    pub entry_count: u64,
+    pub tombstone_count: u64,
+
+    /// Range of flush sequence numbers of the entries in the SST. These count entries flushed or
+    /// ingested into the store and are unrelated to WAL record sequence numbers. Both are 0 if
+    /// unknown, which is the case for SSTs recorded by older manifest versions.
+    pub smallest_flush_seq: u64,
+    pub largest_flush_seq: u64,
+
+    /// Creation time in seconds since the UNIX epoch.
+    pub created_at: u64,
+
+    pub compression: Compression,
+}
+
+const SST_FILENAME_PREFIX: &str = "sstable_";
+const SST_FILENAME_EXTENSION: &str = ".sst";
+
+/// Extension appended to SST files while they are being written.
+const SST_TMP_EXTENSION: &str = ".tmp";
+
 fn sst_filename(id: u64) -> String {
-    format!("sstable_{id:016}.sst")
+    format!("{SST_FILENAME_PREFIX}{id:016}{SST_FILENAME_EXTENSION}")
 }
 
-fn sst_file_path(directory: &Path, id: u64) -> PathBuf {
+pub(crate) fn sst_file_path(directory: &Path, id: u64) -> PathBuf {
     directory.join(sst_filename(id))
 }
+
+pub(crate) fn sst_tmp_file_path(directory: &Path, id: u64) -> PathBuf {
+    directory.join(format!("{}{SST_TMP_EXTENSION}", sst_filename(id)))
+}
+
+/// Returns the ID of the SST if the given file name is that of a complete SST file.
+pub(crate) fn parse_sst_filename(filename: &str) -> Option<u64> {
+    filename
+        .strip_prefix(SST_FILENAME_PREFIX)?
+        .strip_suffix(SST_FILENAME_EXTENSION)?
+        .parse()
+        .ok()
+}
+
+/// Returns true if the given file name is that of an SST that was never completely written.
+pub(crate) fn is_sst_tmp_filename(filename: &str) -> bool {
+    filename
+        .strip_suffix(SST_TMP_EXTENSION)
+        .and_then(parse_sst_filename)
+        .is_some()
+}
@@ -1,31 +1,57 @@
-use crate::{datastructure::lru::LruCache, io_ext::ReadExt};
+use crate::{datastructure::lru::LruCache, entry::Entry, io_ext::ReadExt};
 use std::{
     fs::File,
     io::{self, Read, Seek, SeekFrom},
     ops::RangeBounds,
     path::PathBuf,
 };
+use std::sync::Arc;
 use std::sync::Mutex;
-use std::ops::Bound::*;
 
-use super::{ChunkDesc, sst_file_path};
+use crate::comparator::{Comparator, range_is_empty, range_overlaps};
+
+use crate::datastructure::bloom::BloomFilter;
+
+use super::{ChunkDesc, PrefixFilter, sst_file_path};
 use super::MAGIC;
 use super::VERSION;
 
+/// Decoded items of a single SST chunk.
+pub type Chunk = Vec<(String, Entry)>;
+
 pub trait SSTableReader {
-    type ChunkIterator: Iterator<Item = io::Result<Vec<(String, Vec<u8>)>>> + 'static;
+    type ChunkIterator: Iterator<Item = io::Result<Chunk>> + 'static;
 
     fn list_chunks(&self, sst_id: u64) -> io::Result<Vec<ChunkDesc>>;
 
-    fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<Vec<(String, Vec<u8>)>>;
+    fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<Chunk>;
 
     fn chunk_iterator(&self, sst_id: u64) -> io::Result<Self::ChunkIterator>;
 
-    fn get_candidate_chunks_for_key(&self, sst_id: u64, key: &str) -> io::Result<Vec<ChunkDesc>> {
+    /// Reads a chunk for its keys only. The values and merge operands of its entries may be left
+    /// empty, saving the cost of reading them.
+    fn read_chunk_keys(&self, sst_id: u64, chunk_index: usize) -> io::Result<Chunk> {
+        self.read_chunk(sst_id, chunk_index)
+    }
+
+    /// Returns the prefix bloom filters of the SST, if it has any.
+    fn get_prefix_filter(&self, _sst_id: u64) -> io::Result<Option<Arc<PrefixFilter>>> {
+        Ok(None)
+    }
+
+    fn get_candidate_chunks_for_key(
+        &self,
+        sst_id: u64,
+        key: &str,
+        comparator: &dyn Comparator,
+    ) -> io::Result<Vec<ChunkDesc>> {
         let chunks = self.list_chunks(sst_id)?;
         Ok(chunks
             .into_iter()
-            .filter(move |chunk| chunk.min_key.as_str() <= key && chunk.max_key.as_str() >= key)
+            .filter(move |chunk| {
+                comparator.compare(&chunk.min_key, key).is_le()
+                    && comparator.compare(&chunk.max_key, key).is_ge()
+            })
             .collect())
     }
 
@@ -33,16 +59,9 @@ pub trait SSTableReader {
         &self,
         sst_id: u64,
         range: Range,
+        comparator: &dyn Comparator,
     ) -> io::Result<Vec<ChunkDesc>> {
-        let is_empty = match (range.start_bound(), range.end_bound()) {
-            (Included(min), Included(max)) => min > max,
-            (Included(min), Excluded(max)) => min >= max,
-            (Excluded(min), Included(max)) => min >= max,
-            (Excluded(min), Excluded(max)) => min >= max,
-            _ => false,
-        };
-
-        if is_empty {
+        if range_is_empty(comparator, &range) {
             return Ok(vec![]);
         }
 
@@ -53,36 +72,8 @@ pub trait SSTableReader {
             // we can fist skip the chunks that don't fall in the given range and then take the
             // ones that do and drop everything that comes after. With this, we don't have to
             // check all the chunks.
-            .skip_while(|chunk| {
-                let min_matches = match range.start_bound() {
-                    Included(x) => x <= chunk.max_key.as_str(),
-                    Excluded(x) => x < chunk.max_key.as_str(),
-                    Unbounded => true,
-                };
-
-                let max_matches = match range.end_bound() {
-                    Included(x) => x >= chunk.min_key.as_str(),
-                    Excluded(x) => x > chunk.min_key.as_str(),
-                    Unbounded => true,
-                };
-
-                !min_matches || !max_matches
-            })
-            .take_while(|chunk| {
-                let min_matches = match range.start_bound() {
-                    Included(x) => x <= chunk.max_key.as_str(),
-                    Excluded(x) => x < chunk.max_key.as_str(),
-                    Unbounded => true,
-                };
-
-                let max_matches = match range.end_bound() {
-                    Included(x) => x >= chunk.min_key.as_str(),
-                    Excluded(x) => x > chunk.min_key.as_str(),
-                    Unbounded => true,
-                };
-
-                min_matches && max_matches
-            })
+            .skip_while(|chunk| !range_overlaps(comparator, &range, &chunk.min_key, &chunk.max_key))
+            .take_while(|chunk| range_overlaps(comparator, &range, &chunk.min_key, &chunk.max_key))
             .collect())
     }
 }
@@ -114,16 +105,31 @@ impl SSTableReader for FsSSTReader {
         SSTChunkIterator::open(sstable_path)
     }
 
-    fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<Vec<(String, Vec<u8>)>> {
+    fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<Chunk> {
         let sstable_path = sst_file_path(&self.directory, sst_id);
         RawSSTableReader::open(sstable_path)?
             .read_chunk_at_index(chunk_index)
     }
+
+    fn read_chunk_keys(&self, sst_id: u64, chunk_index: usize) -> io::Result<Chunk> {
+        let sstable_path = sst_file_path(&self.directory, sst_id);
+        RawSSTableReader::open(sstable_path)?
+            .read_chunk_keys_at_index(chunk_index)
+    }
+
+    fn get_prefix_filter(&self, sst_id: u64) -> io::Result<Option<Arc<PrefixFilter>>> {
+        let sstable_path = sst_file_path(&self.directory, sst_id);
+
+        Ok(RawSSTableReader::open(sstable_path)?
+            .read_prefix_filter()?
+            .map(Arc::new))
+    }
 }
 
 pub struct CachedSSTableReader<S: SSTableReader> {
     chunk_desc_cache: Mutex<LruCache<String, Vec<ChunkDesc>>>,
-    chunk_cache: Mutex<LruCache<(u64, usize), Vec<(String, Vec<u8>)>>>,
+    chunk_cache: Mutex<LruCache<(u64, usize), Chunk>>,
+    prefix_filter_cache: Mutex<LruCache<u64, Option<Arc<PrefixFilter>>>>,
     source: S,
 }
 
@@ -132,6 +138,7 @@ impl<S: SSTableReader> CachedSSTableReader<S> {
         Self {
             chunk_desc_cache: Mutex::new(LruCache::new(512)),
             chunk_cache: Mutex::new(LruCache::new(1024)),
+            prefix_filter_cache: Mutex::new(LruCache::new(512)),
             source,
         }
     }
@@ -159,7 +166,7 @@ impl<S: SSTableReader> SSTableReader for CachedSSTableReader<S> {
         self.source.chunk_iterator(sst_id)
     }
 
-    fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<Vec<(String, Vec<u8>)>> {
+    fn read_chunk(&self, sst_id: u64, chunk_index: usize) -> io::Result<Chunk> {
         let key = (sst_id, chunk_index);
 
         let mut chunk_cache = self.chunk_cache.lock().expect("unable to acquire LRU cache mutex");
@@ -177,6 +184,35 @@ impl<S: SSTableReader> SSTableReader for CachedSSTableReader<S> {
                 chunk
             })
     }
+
+    /// Strips the values off the chunk if it is cached. Otherwise the keys are read without
+    /// caching them, so the cache never holds chunks without their values.
+    fn read_chunk_keys(&self, sst_id: u64, chunk_index: usize) -> io::Result<Chunk> {
+        let chunk_cache = self.chunk_cache.lock().expect("unable to acquire LRU cache mutex");
+
+        if let Some(chunk) = chunk_cache.get(&(sst_id, chunk_index)) {
+            return Ok(chunk
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.without_values()))
+                .collect());
+        }
+
+        drop(chunk_cache);
+        self.source.read_chunk_keys(sst_id, chunk_index)
+    }
+
+    fn get_prefix_filter(&self, sst_id: u64) -> io::Result<Option<Arc<PrefixFilter>>> {
+        let mut prefix_filter_cache = self.prefix_filter_cache.lock().expect("unable to acquire LRU cache mutex");
+
+        if let Some(filter) = prefix_filter_cache.get(&sst_id) {
+            return Ok(filter.clone());
+        }
+
+        let filter = self.source.get_prefix_filter(sst_id)?;
+        prefix_filter_cache.put(sst_id, filter.clone());
+
+        Ok(filter)
+    }
 }
 
 pub struct RawSSTableReader<F>
@@ -184,11 +220,24 @@ where
     F: Read + Seek,
 {
     file: F,
+
+    // Format version read from the header. Items of version 1 SSTs carry no expiry time and items
+    // of version 2 SSTs are always values.
+    version: u8,
+}
+
+pub struct Footer {
+    /// None for SSTs older than version 4, which have no filter.
+    pub filter_pos: Option<u64>,
+    pub chunk_dir_pos: u64,
+    pub chunk_count: u32,
 }
 
-struct Footer {
-    chunk_dir_pos: u64,
-    chunk_count: u32,
+/// Header preceding the items of a chunk.
+pub struct ChunkHeader {
+    pub item_count: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
 }
 
 impl RawSSTableReader<File> {
@@ -203,7 +252,10 @@ where
     F: Read + Seek,
 {
     pub fn new(file: F) -> RawSSTableReader<F> {
-        RawSSTableReader { file }
+        RawSSTableReader {
+            file,
+            version: VERSION,
+        }
     }
 
     pub fn list_chunks(&mut self) -> io::Result<Vec<ChunkDesc>> {
@@ -213,7 +265,45 @@ where
         self.read_chunk_directory(footer.chunk_dir_pos, footer.chunk_count)
     }
 
-    pub fn read_chunk_at_index(mut self, chunk_index: usize) -> io::Result<Vec<(String, Vec<u8>)>> {
+    /// Returns the format version of the SST.
+    pub fn version(&mut self) -> io::Result<u8> {
+        self.validate_header()?;
+        Ok(self.version)
+    }
+
+    pub fn footer(&mut self) -> io::Result<Footer> {
+        self.validate_header()?;
+        self.read_footer()
+    }
+
+    /// Reads the header of the chunk at `pos`, see [`ChunkDesc::pos`].
+    pub fn read_chunk_header(&mut self, pos: u64) -> io::Result<ChunkHeader> {
+        self.file.seek(SeekFrom::Start(pos))?;
+
+        Ok(ChunkHeader {
+            item_count: self.file.read_u32()?,
+            compressed_size: self.file.read_u64()?,
+            uncompressed_size: self.file.read_u64()?,
+        })
+    }
+
+    /// Reads the items of the chunk at `pos`, see [`ChunkDesc::pos`].
+    pub fn read_chunk_at(&mut self, pos: u64) -> io::Result<Chunk> {
+        self.validate_header()?;
+        self.read_chunk(pos, false)
+    }
+
+    pub fn read_chunk_at_index(self, chunk_index: usize) -> io::Result<Chunk> {
+        self.read_chunk_at_index_with(chunk_index, false)
+    }
+
+    /// Reads a chunk, skipping over the values of its entries, see
+    /// [`Entry::decode_without_values`].
+    pub fn read_chunk_keys_at_index(self, chunk_index: usize) -> io::Result<Chunk> {
+        self.read_chunk_at_index_with(chunk_index, true)
+    }
+
+    fn read_chunk_at_index_with(mut self, chunk_index: usize, keys_only: bool) -> io::Result<Chunk> {
         self.validate_header()?;
         let footer = self.read_footer()?;
 
@@ -221,33 +311,73 @@ where
         let chunk_desc = chunk_descs.get(chunk_index);
 
         if let Some(chunk_desc) = chunk_desc {
-            self.read_chunk(chunk_desc.pos)
+            self.read_chunk(chunk_desc.pos, keys_only)
         } else {
             Err(io::Error::new(io::ErrorKind::NotFound, "Chunk index out of range"))
         }
     }
 
+    /// Reads the prefix bloom filters of the SST. Returns None if it was written without a prefix
+    /// extractor.
+    pub fn read_prefix_filter(&mut self) -> io::Result<Option<PrefixFilter>> {
+        self.validate_header()?;
+        let footer = self.read_footer()?;
+
+        let Some(filter_pos) = footer.filter_pos else {
+            return Ok(None);
+        };
+
+        self.file.seek(SeekFrom::Start(filter_pos))?;
+
+        let extractor = self.file.read_string()?;
+        if extractor.is_empty() {
+            return Ok(None);
+        }
+
+        let mut chunks = Vec::with_capacity(footer.chunk_count as usize);
+
+        for _ in 0..footer.chunk_count {
+            let hash_count = self.file.read_u32()?;
+            let bits = self.file.read_bytes()?;
+
+            chunks.push(BloomFilter::from_parts(bits, hash_count));
+        }
+
+        Ok(Some(PrefixFilter { extractor, chunks }))
+    }
+
     fn validate_header(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+
         let magic = self.file.read_u32()?;
         if magic != MAGIC {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid SST file magic number."));
         }
 
         let version = self.file.read_u8()?;
-        if version != VERSION {
+        if !(1..=VERSION).contains(&version) {
             return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported SST file version."));
         }
 
+        self.version = version;
+
         Ok(())
     }
 
     fn read_footer(&mut self) -> io::Result<Footer> {
-        self.file.seek(SeekFrom::End(-12))?;
+        // The position of the filter was added to the footer in version 4.
+        let footer_size = if self.version < 4 { 12 } else { 20 };
+        self.file.seek(SeekFrom::End(-footer_size))?;
+
+        let filter_pos = (self.version >= 4)
+            .then(|| self.file.read_u64())
+            .transpose()?;
 
         let chunk_dir_pos = self.file.read_u64()?;
         let chunk_count = self.file.read_u32()?;
 
         Ok(Footer {
+            filter_pos,
             chunk_dir_pos,
             chunk_count,
         })
@@ -274,14 +404,9 @@ where
         Ok(chunk_descs)
     }
 
-    fn read_chunk(&mut self, pos: u64) -> io::Result<Vec<(String, Vec<u8>)>> {
-        self.file.seek(SeekFrom::Start(pos))?;
-
-        let item_count = self.file.read_u32()?;
-
+    fn read_chunk(&mut self, pos: u64, keys_only: bool) -> io::Result<Chunk> {
         // Compressed size and uncompressed size not used yet
-        let _ = self.file.read_u64()?;
-        let _ = self.file.read_u64()?;
+        let item_count = self.read_chunk_header(pos)?.item_count;
 
         let mut result = Vec::with_capacity(item_count as usize);
 
@@ -290,7 +415,12 @@ where
         for _ in 0..item_count {
             let prefix_len = self.file.read_u64()? as usize;
             let mut suffix = self.file.read_bytes()?;
-            let value = self.file.read_bytes()?;
+            let entry = match self.version {
+                1 => Entry::new(self.file.read_bytes()?),
+                2 => Entry::decode_legacy_value(&mut self.file)?,
+                _ if keys_only => Entry::decode_without_values(&mut self.file)?,
+                _ => Entry::decode(&mut self.file)?,
+            };
 
             let mut key_bytes = last_key
                 .get(..prefix_len)
@@ -313,7 +443,7 @@ where
                 )
             })?;
 
-            result.push((key, value));
+            result.push((key, entry));
         }
 
         Ok(result)
@@ -344,13 +474,13 @@ impl SSTChunkIterator {
 }
 
 impl Iterator for SSTChunkIterator {
-    type Item = io::Result<Vec<(String, Vec<u8>)>>;
+    type Item = io::Result<Chunk>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let chunk_desc = self.chunk_descs.get(self.current_chunk_index);
 
         if let Some(chunk_desc) = chunk_desc {
-            let chunk = self.reader.read_chunk(chunk_desc.pos);
+            let chunk = self.reader.read_chunk(chunk_desc.pos, false);
             self.current_chunk_index += 1;
             Some(chunk)
         } else {
@@ -364,6 +494,9 @@ mod test {
     use super::*;
 
     use std::ops::Bound;
+    use std::ops::Bound::*;
+
+    use crate::comparator::BytewiseComparator;
 
     #[test]
     fn test_retrive_candidate_chunks_in_range() {
@@ -389,7 +522,7 @@ mod test {
                 Ok(self.0.clone())
             }
 
-            fn read_chunk(&self, _: u64, _: usize) -> io::Result<Vec<(String, Vec<u8>)>> {
+            fn read_chunk(&self, _: u64, _: usize) -> io::Result<Chunk> {
                 unimplemented!()
             }
 
@@ -408,7 +541,7 @@ mod test {
         );
 
         let get_candidates = |range: (Bound<&str>, Bound<&str>)| {
-            reader.get_candidate_chunks_for_range(0, range)
+            reader.get_candidate_chunks_for_range(0, range, &BytewiseComparator)
                 .unwrap()
                 .iter()
                 .map(|it| it.index)
@@ -451,5 +584,40 @@ mod test {
         assert_eq!(get_candidates((Excluded("key20"), Included("key35"))), vec![1, 2]);
         assert_eq!(get_candidates((Excluded("key20"), Excluded("key35"))), vec![1, 2]);
     }
-}
 
+    #[test]
+    fn test_keys_can_be_read_without_values() {
+        use crate::sstable::writer::SSTableWriter;
+
+        let path = PathBuf::from("test_keys_can_be_read_without_values");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+
+        let entries = [
+            ("key1", Entry::Value { value: b"value".to_vec(), expires_at: Some(10) }),
+            ("key2", Entry::Merge(vec![b"a".to_vec(), b"b".to_vec()])),
+            ("key3", Entry::Tombstone),
+            ("key4", Entry::new(b"value".to_vec())),
+        ];
+
+        let mut writer = SSTableWriter::open(&path, 0).unwrap();
+        for (key, entry) in entries.iter() {
+            writer.write(key, entry).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let reader = FsSSTReader::new(path.clone());
+
+        let expected: Chunk = entries
+            .iter()
+            .map(|(key, entry)| (key.to_string(), entry.without_values()))
+            .collect();
+        assert_eq!(reader.read_chunk_keys(0, 0).unwrap(), expected);
+
+        // Chunks read for their keys are not cached, cached ones are stripped
+        let reader = reader.cached();
+        assert_eq!(reader.read_chunk_keys(0, 0).unwrap(), expected);
+        assert_eq!(reader.read_chunk(0, 0).unwrap()[0].1, entries[0].1);
+        assert_eq!(reader.read_chunk_keys(0, 0).unwrap(), expected);
+    }
+}
@@ -3,15 +3,27 @@ use std::io;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::mem;
+use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use std::sync::Arc;
+
+use crate::datastructure::bloom::{self, BloomFilter};
+use crate::entry::Entry;
 use crate::io_ext::WriteExt;
+use crate::prefix_extractor::PrefixExtractor;
 
 use super::CHUNK_SIZE_TARGET;
 use super::MAGIC;
 use super::VERSION;
 use super::ChunkDesc;
+use super::Compression;
+use super::SSTableProperties;
 use super::sst_file_path;
+use super::sst_tmp_file_path;
 
 
 const CHUNK_HEADER_SIZE: usize = 20;
@@ -19,12 +31,28 @@ const CHUNK_HEADER_SIZE: usize = 20;
 pub struct SSTableWriter {
     file: Option<File>,
 
+    // The SST is written to a temporary file which is renamed to its final path once finalized,
+    // so a partially written SST never appears under a valid SST file name.
+    tmp_path: Option<PathBuf>,
+    path: Option<PathBuf>,
+
     chunks: Vec<ChunkDesc>,
     curr_chunk_written: usize,
     curr_chunk_count: u32,
 
     // Last key written to current chunk
     curr_chunk_last_key: Option<String>,
+
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+
+    // Hashes of the key prefixes of the current chunk, and the filters of the finished chunks
+    curr_chunk_prefixes: Vec<u64>,
+    prefix_filters: Vec<BloomFilter>,
+
+    entry_count: u64,
+    tombstone_count: u64,
+    smallest_flush_seq: u64,
+    largest_flush_seq: u64,
 }
 
 impl SSTableWriter {
@@ -32,10 +60,19 @@ impl SSTableWriter {
         directory: &Path,
         sst_id: u64
     ) -> io::Result<Self> {
-        let file_path = sst_file_path(&directory, sst_id);
-        let file = File::create(file_path)?;
+        let tmp_path = sst_tmp_file_path(directory, sst_id);
+        let file = File::create(&tmp_path)?;
 
-        SSTableWriter::new(file)
+        let mut writer = SSTableWriter::new(file)?;
+        writer.tmp_path = Some(tmp_path);
+        writer.path = Some(sst_file_path(directory, sst_id));
+
+        Ok(writer)
+    }
+
+    /// Writes the SST straight to `path`, outside of any store directory.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        SSTableWriter::new(File::create(path)?)
     }
 
     fn new(mut file: File) -> io::Result<Self> {
@@ -43,10 +80,19 @@ impl SSTableWriter {
 
         let mut ret = SSTableWriter {
             file: Some(file),
+            tmp_path: None,
+            path: None,
             chunks: Vec::new(),
             curr_chunk_written: CHUNK_HEADER_SIZE,
             curr_chunk_count: 0,
             curr_chunk_last_key: None,
+            prefix_extractor: None,
+            curr_chunk_prefixes: Vec::new(),
+            prefix_filters: Vec::new(),
+            entry_count: 0,
+            tombstone_count: 0,
+            smallest_flush_seq: 0,
+            largest_flush_seq: 0,
         };
 
         ret.write_header()?;
@@ -55,15 +101,8 @@ impl SSTableWriter {
         Ok(ret)
     }
 
-    pub fn write<K, V>(&mut self, key: K, value: V) -> io::Result<()>
-    where
-        K: AsRef<str>,
-        V: AsRef<[u8]>,
-    {
-
-
+    pub fn write<K: AsRef<str>>(&mut self, key: K, entry: &Entry) -> io::Result<()> {
         let key = key.as_ref();
-        let value = value.as_ref();
 
         let last_key = self.curr_chunk_last_key
             .as_ref()
@@ -84,8 +123,8 @@ impl SSTableWriter {
 
         let entry_size =
             suffix.len()
-            + value.len()
-            + 24; // prefix length (8) + suffix length (8) + value length (8)
+            + entry.encoded_len()
+            + 16; // prefix length (8) + suffix length (8)
 
         // Tolerate exceeding the target if this is the first key being written to this chunk. This
         // avoids creating an empty chunk in case of a single large key.
@@ -97,24 +136,23 @@ impl SSTableWriter {
 
             // We just started a new chunk, which means our previous calculations are invalid.
             // There is no prefix since this is now the first key.
-            suffix = &key_bytes;
+            suffix = key_bytes;
             prefix_len = 0;
         }
 
         let file = self.file
             .as_mut()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "writer already finalized."))?;
+            .ok_or_else(|| io::Error::other("writer already finalized."))?;
 
         let index = self.chunks.len() - 1;
         let curr = &mut self.chunks[index];
 
         file.write_u64(prefix_len as u64)?;
         file.write_bytes(suffix)?;
-        file.write_bytes(value)?;
+        entry.encode(file)?;
 
-        if key > curr.max_key.as_str() {
-            curr.max_key = key.to_string();
-        }
+        // Keys are written in order, so the last one written is the largest.
+        curr.max_key = key.to_string();
 
         if self.curr_chunk_count == 0 {
             // If this is the first item we are writing for this chunk,
@@ -122,35 +160,90 @@ impl SSTableWriter {
             curr.min_key = key.to_string();
         }
 
+        if let Some(prefix) = self.prefix_extractor.as_ref().and_then(|it| it.prefix(key)) {
+            let hash = bloom::hash(prefix.as_bytes());
+
+            // Keys sharing a prefix are usually written one after another.
+            if self.curr_chunk_prefixes.last() != Some(&hash) {
+                self.curr_chunk_prefixes.push(hash);
+            }
+        }
+
         self.curr_chunk_written += entry_size;
         self.curr_chunk_count += 1;
         self.curr_chunk_last_key = Some(key.to_string());
+        self.entry_count += 1;
+
+        if *entry == Entry::Tombstone {
+            self.tombstone_count += 1;
+        }
 
         Ok(())
     }
 
-    pub fn finalize(&mut self) -> io::Result<()> {
+    /// Sets the range of flush sequence numbers covered by the entries of this SST.
+    ///
+    /// Flush sequence numbers are not stored with individual entries, they are only recorded in the
+    /// properties returned by [`SSTableWriter::finalize`].
+    pub fn set_flush_sequence_range(&mut self, smallest_flush_seq: u64, largest_flush_seq: u64) {
+        self.smallest_flush_seq = smallest_flush_seq;
+        self.largest_flush_seq = largest_flush_seq;
+    }
+
+    /// Records a bloom filter of the key prefixes taken by `prefix_extractor` for every chunk. Must
+    /// be set before anything is written.
+    pub fn set_prefix_extractor(&mut self, prefix_extractor: Arc<dyn PrefixExtractor>) {
+        self.prefix_extractor = Some(prefix_extractor);
+    }
+
+    pub fn finalize(&mut self) -> io::Result<SSTableProperties> {
         self.end_chunk()?;
 
         let mut file = mem::take(&mut self.file)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "writer already finalized."))?;
+            .ok_or_else(|| io::Error::other("writer already finalized."))?;
 
-        let chunk_dir_pos = file.seek(SeekFrom::Current(0))?;
+        let chunk_dir_pos = file.stream_position()?;
         self.write_chunk_directory(&mut file)?;
-        self.write_footer(&mut file, chunk_dir_pos)?;
+
+        let filter_pos = file.stream_position()?;
+        self.write_prefix_filter(&mut file)?;
+
+        self.write_footer(&mut file, filter_pos, chunk_dir_pos)?;
 
         file.sync_all()?;
 
-        Ok(())
+        if let (Some(tmp_path), Some(path)) = (self.tmp_path.take(), self.path.take()) {
+            fs::rename(tmp_path, &path)?;
+
+            #[cfg(unix)]
+            if let Some(directory) = path.parent() {
+                File::open(directory)?.sync_all()?;
+            }
+        }
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Ok(SSTableProperties {
+            file_size: file.stream_position()?,
+            entry_count: self.entry_count,
+            tombstone_count: self.tombstone_count,
+            smallest_flush_seq: self.smallest_flush_seq,
+            largest_flush_seq: self.largest_flush_seq,
+            created_at,
+            compression: Compression::None,
+        })
     }
 
     fn end_chunk(&mut self) -> io::Result<()> {
         let file = self.file
             .as_mut()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "writer already finalized."))?;
+            .ok_or_else(|| io::Error::other("writer already finalized."))?;
 
         let chunk = &self.chunks[self.chunks.len() - 1];
-        let old_pos = file.seek(SeekFrom::Current(0))?;
+        let old_pos = file.stream_position()?;
         file.seek(SeekFrom::Start(chunk.pos))?;
 
 
@@ -162,15 +255,20 @@ impl SSTableWriter {
 
         file.seek(SeekFrom::Start(old_pos))?;
 
+        if self.prefix_extractor.is_some() {
+            let prefixes = mem::take(&mut self.curr_chunk_prefixes);
+            self.prefix_filters.push(BloomFilter::from_hashes(&prefixes));
+        }
+
         Ok(())
     }
 
     fn start_chunk(&mut self) -> io::Result<()> {
         let file = self.file
             .as_mut()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "writer already finalized."))?;
+            .ok_or_else(|| io::Error::other("writer already finalized."))?;
 
-        let chunk_pos = file.seek(SeekFrom::Current(0))?;
+        let chunk_pos = file.stream_position()?;
         self.chunks.push(ChunkDesc {
             index: self.chunks.len(),
             min_key: "".to_string(),
@@ -193,14 +291,15 @@ impl SSTableWriter {
     fn write_header(&mut self) -> io::Result<()> {
         let file = self.file
             .as_mut()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "writer already finalized."))?;
+            .ok_or_else(|| io::Error::other("writer already finalized."))?;
 
         file.write_u32(MAGIC)?;
         file.write_u8(VERSION)?;
         Ok(())
     }
 
-    fn write_footer(&mut self, file: &mut File, chunk_dir_pos: u64) -> io::Result<()>{
+    fn write_footer(&mut self, file: &mut File, filter_pos: u64, chunk_dir_pos: u64) -> io::Result<()>{
+        file.write_u64(filter_pos)?;
         file.write_u64(chunk_dir_pos)?;
         file.write_u32(self.chunks.len() as u32)?;
         Ok(())
@@ -215,6 +314,22 @@ impl SSTableWriter {
 
         Ok(())
     }
+
+    fn write_prefix_filter(&mut self, file: &mut File) -> io::Result<()> {
+        let Some(prefix_extractor) = self.prefix_extractor.as_ref() else {
+            // An empty name marks the SST as having no filter.
+            return file.write_string("");
+        };
+
+        file.write_string(prefix_extractor.name())?;
+
+        for filter in self.prefix_filters.iter() {
+            file.write_u32(filter.hash_count())?;
+            file.write_bytes(filter.bits())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for SSTableWriter {
@@ -244,7 +359,7 @@ mod test {
 
         let mut writer = SSTableWriter::new(File::create(path.clone()).unwrap()).unwrap();
 
-        writer.write(&large_value, &large_value.as_bytes()).unwrap();
+        writer.write(&large_value, &Entry::new(large_value.as_bytes().to_vec())).unwrap();
         writer.finalize().unwrap();
         assert_eq!(writer.chunks.len(), 1);
 
@@ -254,5 +369,85 @@ mod test {
         let chunks = reader.list_chunks().unwrap();
         assert_eq!(chunks.len(), 1);
     }
-}
 
+    #[test]
+    fn test_finalize_reports_properties() {
+        let path = PathBuf::from("test_finalize_reports_properties");
+        let _ = fs::remove_file(&path);
+
+        let mut writer = SSTableWriter::new(File::create(path.clone()).unwrap()).unwrap();
+        writer.set_flush_sequence_range(5, 7);
+        writer.write("key1", &Entry::new(b"value1".to_vec())).unwrap();
+        writer.write("key2", &Entry::new(b"value2".to_vec())).unwrap();
+        writer.write("key3", &Entry::new(b"value3".to_vec())).unwrap();
+        writer.write("key4", &Entry::Tombstone).unwrap();
+
+        let props = writer.finalize().unwrap();
+
+        assert_eq!(props.entry_count, 4);
+        assert_eq!(props.tombstone_count, 1);
+        assert_eq!(props.smallest_flush_seq, 5);
+        assert_eq!(props.largest_flush_seq, 7);
+        assert_eq!(props.file_size, fs::metadata(&path).unwrap().len());
+        assert!(props.created_at > 0);
+    }
+
+    #[test]
+    fn test_sst_appears_under_its_name_only_once_finalized() {
+        let path = PathBuf::from("test_sst_appears_under_its_name_only_once_finalized");
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        let mut writer = SSTableWriter::open(&path, 0).unwrap();
+        writer.write("key1", &Entry::new(b"value1".to_vec())).unwrap();
+
+        assert!(!sst_file_path(&path, 0).exists());
+        assert!(sst_tmp_file_path(&path, 0).exists());
+
+        writer.finalize().unwrap();
+
+        assert!(sst_file_path(&path, 0).exists());
+        assert!(!sst_tmp_file_path(&path, 0).exists());
+    }
+
+    #[test]
+    fn test_prefix_filter_is_recorded_per_chunk() {
+        use crate::sstable::reader::RawSSTableReader;
+
+        let path = PathBuf::from("test_prefix_filter_is_recorded_per_chunk");
+        let _ = fs::remove_file(&path);
+
+        let mut writer = SSTableWriter::new(File::create(path.clone()).unwrap()).unwrap();
+        writer.set_prefix_extractor(Arc::new(crate::FixedPrefix::new(5)));
+
+        let large_value = vec![0; CHUNK_SIZE_TARGET];
+        writer.write("user:1", &Entry::new(large_value.clone())).unwrap();
+        writer.write("user:2", &Entry::new(large_value)).unwrap();
+        writer.write("zone:1", &Entry::Tombstone).unwrap();
+        writer.write("z", &Entry::Tombstone).unwrap();
+        writer.finalize().unwrap();
+
+        let filter = RawSSTableReader::open(path.clone())
+            .unwrap()
+            .read_prefix_filter()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(filter.extractor, "sand.fixed.5");
+        // The large values take up a chunk each
+        assert_eq!(filter.chunks.len(), 3);
+        assert!(filter.may_contain(0, "user:"));
+        assert!(filter.may_contain(1, "user:"));
+        assert!(filter.may_contain(2, "zone:"));
+        assert!(!filter.may_contain(0, "zone:"));
+        assert!(!filter.may_contain(2, "user:"));
+
+        let mut writer = SSTableWriter::new(File::create(path.clone()).unwrap()).unwrap();
+        writer.write("user:1", &Entry::Tombstone).unwrap();
+        writer.finalize().unwrap();
+
+        let mut reader = RawSSTableReader::open(path).unwrap();
+        assert!(reader.read_prefix_filter().unwrap().is_none());
+        assert_eq!(reader.list_chunks().unwrap().len(), 1);
+    }
+}
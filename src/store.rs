@@ -1,21 +1,126 @@
 use std::collections::BTreeMap;
 use std::io;
 use std::ops::RangeBounds;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::iterator::StoreIterator;
+use crate::options::RangeOptions;
 
 pub trait Cursor: Iterator<Item = io::Result<(String, Vec<u8>)>> {}
 impl<I: Iterator<Item = io::Result<(String, Vec<u8>)>>> Cursor for I {}
 
+pub trait KeyCursor: Iterator<Item = io::Result<String>> {}
+impl<I: Iterator<Item = io::Result<String>>> KeyCursor for I {}
+
+/// Entries along with the time they expire at, see [`Store::get_range_with_expiry`].
+pub trait ExpiringCursor: Iterator<Item = io::Result<(String, Vec<u8>, Option<u64>)>> {}
+impl<I: Iterator<Item = io::Result<(String, Vec<u8>, Option<u64>)>>> ExpiringCursor for I {}
+
 pub trait Store {
     fn insert(&self, key: &str, value: &[u8]) -> io::Result<()>;
 
+    /// Inserts an entry that is treated as absent once `ttl` has passed. Expired entries are
+    /// removed from disk as they are compacted.
+    fn insert_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> io::Result<()>;
+
+    /// Records `operand` to be combined with the value of `key` by the merge operator set in
+    /// [`crate::Options`]. Fails if no merge operator is set.
+    fn merge(&self, key: &str, operand: &[u8]) -> io::Result<()>;
+
     fn insert_batch(&self, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()>;
 
+    /// Atomically replaces the value of `key` with `new` if its current value is `expected`, with
+    /// None standing for an absent key on both sides. Returns whether the value was replaced.
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> io::Result<bool>;
+
+    /// Inserts `value` if `key` has no value. Returns whether it was inserted.
+    fn insert_if_absent(&self, key: &str, value: &[u8]) -> io::Result<bool> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
     fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
 
+    /// Returns the value of each of `keys`, in the order of `keys`.
+    ///
+    /// This is faster than calling [`Store::get`] for each key, since the keys are looked up
+    /// together and every SST chunk holding some of them is read once.
+    fn multi_get(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>>;
+
     fn get_range<'a, R: RangeBounds<str> + Clone + 'a>(
         &'a self,
         range: R,
     ) -> io::Result<impl Cursor + 'a>;
 
+    /// Returns the entries in `range` like [`Store::get_range`], along with the time each of them
+    /// expires at in milliseconds since the UNIX epoch, None if it never expires.
+    fn get_range_with_expiry<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+    ) -> io::Result<impl ExpiringCursor + 'a>;
+
+    /// Returns the entries in `range` like [`Store::get_range`], skipping and limiting them as
+    /// set in `options`.
+    fn get_range_with_options<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+        options: &RangeOptions,
+    ) -> io::Result<impl Cursor + 'a> {
+        Ok(options.apply(self.get_range(range)?))
+    }
+
+    /// Returns the keys with a value in `range`, skipping and limiting them as set in `options`.
+    ///
+    /// Values are not read from the SSTs, which makes this much cheaper than
+    /// [`Store::get_range`] for large values.
+    fn get_keys<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+        options: &RangeOptions,
+    ) -> io::Result<impl KeyCursor + 'a>;
+
+    /// Returns the number of keys with a value in `range`, without reading the values.
+    fn count_range<R: RangeBounds<str> + Clone>(&self, range: R) -> io::Result<u64> {
+        self.get_keys(range, &RangeOptions::default())?
+            .try_fold(0, |count, key| key.map(|_| count + 1))
+    }
+
+    /// Returns the values of the keys starting with `prefix`, ordered by the comparator.
+    ///
+    /// With a prefix extractor set in [`crate::Options`], SSTs and chunks without keys of the
+    /// prefix are skipped when the extractor takes a prefix from `prefix` itself.
+    fn scan_prefix<'a>(&'a self, prefix: &'a str) -> io::Result<impl Cursor + 'a>;
+
+    /// Returns an iterator over the live values that can be positioned at any key and moved in
+    /// both directions. It does not see writes made after it was created.
+    fn iter(&self) -> io::Result<StoreIterator<'_>>;
+
     fn flush(&self) -> io::Result<()>;
+
+    /// Merges every SSTable overlapping the given range into a single SSTable at `target_level`,
+    /// dropping overwritten versions of keys. Returns once the compaction is complete.
+    ///
+    /// Unflushed entries are flushed first so they are compacted as well.
+    fn compact_range<R: RangeBounds<str> + Clone>(&self, range: R, target_level: u8) -> io::Result<()>;
+
+    /// Writes a consistent copy of the store to `directory`, which must not exist yet. The copy
+    /// can be opened like any other store, while writers keep going.
+    ///
+    /// Writes are only held up while the memtables are copied. SST and blob files are hard-linked
+    /// into the copy if it is on the same filesystem, so it takes up little space until the store
+    /// compacts them away.
+    fn checkpoint(&self, directory: &Path) -> io::Result<()>;
+
+    /// Adds SSTs written by [`crate::SstFileWriter`] to the store in a single step, skipping the
+    /// WAL and the memtable. Their entries take precedence over earlier writes of the same keys,
+    /// and later SSTs over earlier ones. Nothing is added if any of them is invalid.
+    ///
+    /// The files are hard-linked into the store where possible, so they must not be modified
+    /// afterwards.
+    fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<()>;
 }
@@ -1,605 +1,2789 @@
 use std::collections::BTreeMap;
 use std::io;
 use std::ops::RangeBounds;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::RwLock;
+use std::time::Duration;
 
 use crate::async_store_impl::AsyncStoreImpl;
-use crate::lsm_tree::LSMTree;
-use crate::sstable::reader::{CachedSSTableReader, FsSSTReader};
+use crate::checkpoint::{CheckpointState, write_checkpoint};
+use crate::entry::{Entry, EntryCursor, now_millis};
+use crate::comparator::{BytewiseComparator, Comparator, prefix_range};
+use crate::iterator::{SeekableSource, StoreIterator, VecSource};
+use crate::lsm_tree::{DirectoryLock, LSMTree, remove_orphaned_files};
+use crate::memtable::Memtable;
+use crate::manifest::{DEFAULT_COLUMN_FAMILY, Manifest, Version};
+use crate::merge_operator::{MergeOperator, collapse};
+use crate::options::{Options, RangeOptions};
+use crate::sstable::reader::{CachedSSTableReader, FsSSTReader, SSTableReader};
 use crate::store::Store;
-use crate::store::Cursor;
-use crate::util::merge_sorted_uniq_cursor;
-use crate::wal::Wal;
+use crate::store::{Cursor, ExpiringCursor, KeyCursor};
+use crate::util::merge_sorted_grouped_cursor;
+use crate::wal::{self, Wal, WalRecord};
+use crate::write_batch::{BatchOp, WriteBatch};
 
 const MAX_MEMTABLE_SIZE: usize = 64 * 1024; // 64 KiB
 
-pub struct StoreImpl<L: Store> {
+/// Name of the column family every store has. The [`Store`] methods of the store itself operate
+/// on it.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// A keyspace of the store with its own memtable, SSTables and options.
+struct ColumnFamily<S: SSTableReader> {
+    id: u32,
+    name: String,
     memtable_size: AtomicUsize,
-    memtable: Mutex<BTreeMap<String, Vec<u8>>>,
-    lsm_tree: L,
-    wal: Mutex<Wal>,
+    memtable: Mutex<Memtable>,
+    lsm_tree: LSMTree<S>,
+    comparator: Arc<dyn Comparator>,
+    options: Options,
 }
 
-impl StoreImpl<LSMTree<CachedSSTableReader<FsSSTReader>>> {
-    pub fn open(
-        directory: PathBuf,
-    ) -> io::Result<StoreImpl<LSMTree<CachedSSTableReader<FsSSTReader>>>> {
-        let lsm_tree = LSMTree::new(directory.clone())?;
-        let mut wal = Wal::new(&directory)?;
-
-        let batch = BTreeMap::from_iter(wal.restore()?);
+impl<S: SSTableReader> ColumnFamily<S> {
+    fn merge_operator(&self) -> Option<&dyn MergeOperator> {
+        self.options.merge_operator.as_deref()
+    }
 
-        if !batch.is_empty() {
-            lsm_tree.insert_batch(&batch)?;
-            lsm_tree.flush()?;
+    fn require_merge_operator(&self) -> io::Result<()> {
+        match self.merge_operator() {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no merge operator is set for column family '{}'", self.name),
+            )),
         }
-
-        wal.truncate()?;
-
-        StoreImpl::new(lsm_tree, wal)
     }
 
-    pub fn to_async(self) -> AsyncStoreImpl<Self> {
-        AsyncStoreImpl::new(self)
+    /// Creates a value entry with the default TTL of the column family.
+    fn value_entry(&self, value: &[u8]) -> Entry {
+        Entry::with_ttl(value.to_owned(), self.options.default_ttl)
     }
 }
 
-impl<L: Store> StoreImpl<L> {
-    fn new(lsm_tree: L, wal: Wal) -> io::Result<StoreImpl<L>> {
-        Ok(StoreImpl {
+impl ColumnFamily<CachedSSTableReader<FsSSTReader>> {
+    fn open(
+        store_directory: &Path,
+        manifest: &Arc<Manifest>,
+        id: u32,
+        name: &str,
+        options: Options,
+    ) -> Self {
+        let lsm_tree = LSMTree::new(store_directory.to_path_buf(), manifest.clone(), id, options.clone());
+
+        ColumnFamily {
+            id,
+            name: name.to_owned(),
             memtable_size: AtomicUsize::new(0),
-            memtable: Mutex::new(BTreeMap::new()),
+            memtable: Mutex::new(Memtable::new(options.comparator())),
             lsm_tree,
-            wal: Mutex::new(wal),
-        })
+            comparator: options.comparator(),
+            options,
+        }
     }
+}
 
-    fn flush_memtable(&self) -> io::Result<()> {
-        let mut memtable = self.memtable.lock().unwrap();
+pub struct StoreImpl<S: SSTableReader> {
+    default_family: Arc<ColumnFamily<S>>,
 
-        self.lsm_tree.insert_batch(&*memtable)?;
-        memtable.clear();
-        self.memtable_size.store(0, Ordering::Relaxed);
-        self.wal.lock().unwrap().truncate()?;
+    // Column families other than the default one, by name.
+    column_families: RwLock<BTreeMap<String, Arc<ColumnFamily<S>>>>,
 
-        Ok(())
+    // Shared by all column families. It is only truncated once all of them are flushed. None if
+    // the store is opened read-only.
+    wal: Option<Mutex<Wal>>,
+
+    manifest: Arc<Manifest>,
+    directory: PathBuf,
+
+    // Dropped last so the directory stays locked until everything else is closed. None if the
+    // store is opened read-only, which takes no locks.
+    _lock: Option<DirectoryLock>,
+}
+
+impl StoreImpl<CachedSSTableReader<FsSSTReader>> {
+    pub fn open(directory: PathBuf) -> io::Result<DefaultStore> {
+        Self::open_with_options(directory, Options::default())
+    }
+
+    pub fn open_with_options(directory: PathBuf, options: Options) -> io::Result<DefaultStore> {
+        Self::open_with_column_families(directory, options, BTreeMap::new())
     }
 
-    fn maybe_flush_memtable(&self) -> io::Result<()> {
-        if self.memtable_size.load(Ordering::Relaxed) > MAX_MEMTABLE_SIZE {
-            self.flush_memtable()?;
+    /// Opens the store with the given options for its default column family and for each of the
+    /// named column families, which are created if they don't exist yet. Existing column families
+    /// that are not named are opened with the default [`Options`].
+    pub fn open_with_column_families(
+        directory: PathBuf,
+        options: Options,
+        mut column_families: BTreeMap<String, Options>,
+    ) -> io::Result<DefaultStore> {
+        if column_families.contains_key(DEFAULT_COLUMN_FAMILY_NAME) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "options of the default column family are the store options",
+            ));
         }
 
-        Ok(())
-    }
+        let lock = DirectoryLock::acquire(&directory)?;
 
-    fn add_to_memtable(&self, key: &str, value: &[u8]) -> io::Result<()> {
-        self.memtable.lock().unwrap().insert(key.to_owned(), value.to_owned());
-        self.memtable_size.fetch_add(key.len() + value.len(), Ordering::Relaxed);
-        self.maybe_flush_memtable()?;
+        let manifest = Arc::new(Manifest::open(&directory)?);
+        remove_orphaned_files(&directory, &manifest)?;
 
-        Ok(())
-    }
-}
+        let comparator = options.comparator();
+        check_comparator(&manifest, comparator.as_ref())?;
 
-impl<L: Store> Store for StoreImpl<L> {
-    fn insert(&self, key: &str, value: &[u8]) -> io::Result<()> {
-        self.wal.lock().unwrap().log_one(key, value)?;
-        self.add_to_memtable(key, value)?;
+        let wal_archive_dir = options.wal_archive_dir.clone();
 
-        Ok(())
-    }
+        let default_family = ColumnFamily::open(
+            &directory,
+            &manifest,
+            DEFAULT_COLUMN_FAMILY,
+            DEFAULT_COLUMN_FAMILY_NAME,
+            options,
+        );
 
-    fn insert_batch(&self, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
-        self.wal.lock().unwrap().log_many(entries)?;
+        let existing: BTreeMap<_, _> = manifest
+            .column_families()
+            .into_iter()
+            .map(|(id, name)| {
+                let options = column_families.remove(&name).unwrap_or_default();
+                let options = with_comparator(&comparator, options)?;
+                let family = ColumnFamily::open(&directory, &manifest, id, &name, options);
 
-        for (key, value) in entries.iter() {
-            self.add_to_memtable(key, value)?;
+                Ok((name, Arc::new(family)))
+            })
+            .collect::<io::Result<_>>()?;
+
+        let mut wal = Wal::new(&directory, wal_archive_dir.as_deref())?;
+
+        let families: Vec<_> = std::iter::once(&default_family)
+            .chain(existing.values().map(|it| &**it))
+            .collect();
+        let batches = replay(wal.records()?, &manifest.current(), &families, &comparator)?;
+        let wal_sequence = wal.last_sequence();
+
+        for (id, batch) in batches {
+            let family = std::iter::once(&default_family)
+                .chain(existing.values().map(|it| &**it))
+                .find(|it| it.id == id)
+                .unwrap();
+
+            family.lsm_tree.write_flushed_sstable(batch.iter(), wal_sequence)?;
         }
 
-        Ok(())
-    }
+        wal.truncate()?;
 
-    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
-        if let Some(value) = self.memtable.lock().unwrap().get(key) {
-            return Ok(Some(value.to_owned()));
+        let store = StoreImpl {
+            default_family: Arc::new(default_family),
+            column_families: RwLock::new(existing),
+            wal: Some(Mutex::new(wal)),
+            manifest,
+            directory,
+            _lock: Some(lock),
+        };
+
+        for (name, options) in column_families {
+            store.create_column_family(&name, options)?;
         }
 
-        self.lsm_tree.get(key)
+        Ok(store)
     }
 
-    fn get_range<'a, R: RangeBounds<str> + Clone + 'a>(
-        &'a self,
-        range: R,
-    ) -> io::Result<impl Cursor + 'a> {
-        let memtable_iter = self
-            .memtable
-            .lock()
-            .unwrap()
-            .range(range.clone())
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .map(Ok)
-            // This is not a &mut method and we therefore can't just return an iterator
-            // that refrences memtable since a parallel writer may mutate that.
-            // We therefore copy the memtable into a Vec and make an iterator out of that.
-            .collect::<Vec<_>>()
-            .into_iter();
+    /// Opens the store without taking any locks, so it can be read while another process has it
+    /// open for writing. Writes are rejected.
+    ///
+    /// The store is read as it is when opened, with the entries logged to the WAL kept in private
+    /// memtables. [`StoreImpl::try_catch_up`] reads what was written since. Column families are
+    /// opened with the default [`Options`].
+    pub fn open_read_only(directory: PathBuf, options: Options) -> io::Result<DefaultStore> {
+        // The WAL is read before the manifest, so entries flushed in between are not lost. The
+        // manifest records them as flushed, and replaying skips them rather than applying merge
+        // operands twice.
+        let records = read_wal_records(&directory)?;
+        let manifest = Arc::new(Manifest::open_read_only(&directory)?);
+
+        let comparator = options.comparator();
+        check_comparator(&manifest, comparator.as_ref())?;
+
+        let default_family = ColumnFamily::open(
+            &directory,
+            &manifest,
+            DEFAULT_COLUMN_FAMILY,
+            DEFAULT_COLUMN_FAMILY_NAME,
+            options,
+        );
 
-        let lsm_tree_iter = self
-            .lsm_tree
-            .get_range(range)?;
+        let store = StoreImpl {
+            default_family: Arc::new(default_family),
+            column_families: RwLock::new(BTreeMap::new()),
+            wal: None,
+            manifest,
+            directory,
+            _lock: None,
+        };
+
+        store.open_new_column_families()?;
+        store.load_memtables(records)?;
 
-        Ok(merge_sorted_uniq_cursor(vec![
-            // Since these are entirely different types, we need to box them,
-            // monomorphization is not possible. Put them behind a trait object.
-            (Box::new(memtable_iter) as Box<dyn Cursor>),
-            (Box::new(lsm_tree_iter) as Box<dyn Cursor>),
-        ]))
+        Ok(store)
     }
 
-    fn flush(&self) -> io::Result<()> {
-        if self.memtable.lock().unwrap().is_empty() {
-            return self.lsm_tree.flush();
+    /// Catches up with the writes made since a read-only store was opened, or last caught up, by
+    /// the process that has it open for writing: re-reads the manifest entries appended since,
+    /// opens new column families, and reloads the memtables from the WAL.
+    ///
+    /// Reads of SSTs that the other process has compacted away since fail with
+    /// [`io::ErrorKind::NotFound`] until the store catches up. A store open for writing is always
+    /// up to date, for it this does nothing.
+    pub fn try_catch_up(&self) -> io::Result<()> {
+        if !self.is_read_only() {
+            return Ok(());
         }
 
-        if let Err(e) = self.flush_memtable() {
-            eprintln!("Error flushing memtable: {e}");
+        // Read before the manifest for the same reason as when opening.
+        let records = read_wal_records(&self.directory)?;
+        self.manifest.catch_up()?;
+
+        self.open_new_column_families()?;
+        self.load_memtables(records)
+    }
+
+    /// Opens the column families recorded in the manifest that are not open yet, with the
+    /// default [`Options`].
+    fn open_new_column_families(&self) -> io::Result<()> {
+        let mut column_families = self.column_families.write().unwrap();
+
+        for (id, name) in self.manifest.column_families() {
+            if column_families.contains_key(&name) {
+                continue;
+            }
+
+            let options = with_comparator(&self.default_family.comparator, Options::default())?;
+            let family = ColumnFamily::open(&self.directory, &self.manifest, id, &name, options);
+            column_families.insert(name, Arc::new(family));
         }
 
-        return self.lsm_tree.flush();
+        Ok(())
     }
-}
 
-impl<L: Store> Drop for StoreImpl<L> {
-    fn drop(&mut self) {
-        if let Err(e) = self.flush() {
-            eprintln!("Unable to flush store: {e}");
+    /// Creates a column family, recording it in the manifest. Fails if one with the same name
+    /// already exists.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        options: Options,
+    ) -> io::Result<ColumnFamilyHandle<'_, CachedSSTableReader<FsSSTReader>>> {
+        self.ensure_writable()?;
+
+        let mut column_families = self.column_families.write().unwrap();
+
+        if name == DEFAULT_COLUMN_FAMILY_NAME || column_families.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("column family '{name}' already exists"),
+            ));
         }
+
+        let id = column_families
+            .values()
+            .map(|it| it.id)
+            .max()
+            .unwrap_or(DEFAULT_COLUMN_FAMILY) + 1;
+
+        let options = with_comparator(&self.default_family.comparator, options)?;
+
+        let mut update = self.manifest.start_update();
+        update.add_column_family(id, name);
+        self.manifest.update(update)?;
+
+        let family = Arc::new(ColumnFamily::open(&self.directory, &self.manifest, id, name, options));
+        column_families.insert(name.to_owned(), family.clone());
+
+        Ok(ColumnFamilyHandle {
+            store: self,
+            family,
+        })
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_async(self) -> AsyncStoreImpl<Self> {
+        AsyncStoreImpl::new(self)
     }
 }
 
-pub type DefaultStore = StoreImpl<LSMTree<CachedSSTableReader<FsSSTReader>>>;
+/// Combines the latest entries of a key in the memtable and in the SSTables into its live value.
+fn resolve_value(
+    operator: Option<&dyn MergeOperator>,
+    key: &str,
+    memtable_entry: Option<Entry>,
+    sstable_entry: Option<Entry>,
+    now: u64,
+) -> io::Result<Option<Vec<u8>>> {
+    let versions: Vec<_> = match memtable_entry {
+        Some(Entry::Merge(operands)) => std::iter::once(Entry::Merge(operands))
+            .chain(sstable_entry)
+            .collect(),
+
+        Some(entry) => vec![entry],
+        None => sstable_entry.into_iter().collect(),
+    };
+
+    if versions.is_empty() {
+        return Ok(None);
+    }
 
-pub fn make_store(directory: PathBuf) -> io::Result<DefaultStore> {
-    Ok(StoreImpl::open(directory.clone())?)
+    let entry = collapse(operator, key, versions, now, true)?;
+
+    // An expired entry still shadows older versions of the key.
+    Ok(Some(entry)
+        .filter(|entry| !entry.is_expired(now))
+        .and_then(Entry::into_value))
 }
 
-#[cfg(test)]
-mod tests {
-    use std::fs;
-    use std::ops::Bound::*;
+/// Combines the entries of logged records, oldest first, into a memtable for each column family
+/// written to. Entries `version` records as flushed to the SSTs of their column family are
+/// skipped, since merge operands would otherwise be applied twice.
+fn replay<S: SSTableReader>(
+    records: impl IntoIterator<Item = WalRecord>,
+    version: &Version,
+    families: &[&ColumnFamily<S>],
+    comparator: &Arc<dyn Comparator>,
+) -> io::Result<BTreeMap<u32, Memtable>> {
+    let mut batches: BTreeMap<u32, Memtable> = BTreeMap::new();
+
+    let entries = records.into_iter().flat_map(|record| {
+        record
+            .entries
+            .into_iter()
+            .filter(move |(id, _, _)| record.sequence > version.flushed_wal_sequence(*id))
+    });
+
+    for (id, key, entry) in entries {
+        let family = families
+            .iter()
+            .find(|it| it.id == id)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("WAL refers to unknown column family {id}"),
+                )
+            })?;
 
-    use super::*;
+        let batch = batches
+            .entry(id)
+            .or_insert_with(|| Memtable::new(comparator.clone()));
 
+        let entry = combine_with_existing(family.merge_operator(), &key, &entry, batch.get(&key))?
+            .unwrap_or(entry);
 
-    #[test]
-    fn test_inserted_entries_can_be_retrieved() {
-        let dir = PathBuf::from("test_inserted_entries_can_be_retrieved");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
-        }
+        batch.insert(key, entry);
+    }
 
-        fs::create_dir_all(&dir).unwrap();
+    Ok(batches)
+}
 
-        let store = make_store(dir).unwrap();
+/// Reads the records of the WAL of the store in `directory` without opening it. Stops at a
+/// record that is still being written.
+fn read_wal_records(directory: &Path) -> io::Result<Vec<WalRecord>> {
+    let path = directory.join(wal::FILENAME);
 
-        let actual_value = vec![0, 1, 2];
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
 
-        store.insert("hello", actual_value.as_slice()).unwrap();
+    Ok(wal::read_valid_records(&path)?.1)
+}
 
-        let value = store.get("hello").unwrap();
+fn read_only_error() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "store is opened read-only")
+}
 
-        assert_eq!(value, Some(actual_value));
+/// Refuses to open a store with a comparator other than the one its SSTs are sorted by, and
+/// records the comparator of stores that have none recorded yet.
+fn check_comparator(manifest: &Manifest, comparator: &dyn Comparator) -> io::Result<()> {
+    let recorded = manifest.comparator();
+
+    // Stores created before the comparator was recorded are sorted bytewise. Without any SSTs,
+    // the order doesn't matter yet.
+    let expected = recorded.clone().or_else(|| {
+        let is_empty = manifest.get_sstable_ids().is_empty();
+        (!is_empty).then(|| BytewiseComparator.name().to_owned())
+    });
+
+    if let Some(expected) = expected && expected != comparator.name() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "store is sorted by comparator '{expected}' but was opened with '{}'",
+                comparator.name()
+            ),
+        ));
     }
 
-    #[test]
-    fn test_inserted_entries_can_be_retrieved_on_reopen() {
-        let dir = PathBuf::from("test_inserted_entries_can_be_retrieved_on_reopen");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
+    // A read-only store leaves recording it to the process that has the store open for writing.
+    if recorded.is_none() && !manifest.is_read_only() {
+        let mut update = manifest.start_update();
+        update.set_comparator(comparator.name());
+        manifest.update(update)?;
+    }
+
+    Ok(())
+}
+
+/// Gives column family options the comparator of the store, refusing options that set another
+/// one.
+fn with_comparator(comparator: &Arc<dyn Comparator>, mut options: Options) -> io::Result<Options> {
+    if let Some(other) = &options.comparator && other.name() != comparator.name() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "column families must use the comparator of the store '{}', not '{}'",
+                comparator.name(),
+                other.name()
+            ),
+        ));
+    }
+
+    options.comparator = Some(comparator.clone());
+
+    Ok(options)
+}
+
+/// Combines a merge record with the entry it is written over in the memtable. Returns None if the
+/// new entry simply replaces the existing one.
+fn combine_with_existing(
+    operator: Option<&dyn MergeOperator>,
+    key: &str,
+    entry: &Entry,
+    existing: Option<&Entry>,
+) -> io::Result<Option<Entry>> {
+    match (entry, existing) {
+        (Entry::Merge(_), Some(existing)) => {
+            let versions = [entry.clone(), existing.clone()];
+            collapse(operator, key, versions, now_millis(), false).map(Some)
         }
 
-        fs::create_dir_all(&dir).unwrap();
+        _ => Ok(None),
+    }
+}
 
-        let store = make_store(dir.clone()).unwrap();
+impl<S: SSTableReader> StoreImpl<S> {
+    /// Returns the column family with the given name, which is read and written through the
+    /// returned handle.
+    pub fn column_family(&self, name: &str) -> Option<ColumnFamilyHandle<'_, S>> {
+        let family = self.find_family(name).ok()?;
 
-        store.insert("hello", "world".as_bytes()).unwrap();
-        drop(store);
+        Some(ColumnFamilyHandle {
+            store: self,
+            family,
+        })
+    }
 
-        let store = make_store(dir.clone()).unwrap();
-        let value = store.get("hello").unwrap();
-        assert_eq!(value, Some("world".as_bytes().to_vec()));
+    /// Returns the names of all column families, including the default one.
+    pub fn column_family_names(&self) -> Vec<String> {
+        std::iter::once(DEFAULT_COLUMN_FAMILY_NAME.to_owned())
+            .chain(self.column_families.read().unwrap().keys().cloned())
+            .collect()
     }
 
-    #[test]
-    fn test_1000_entries_can_be_inserted_and_retrived_on_reopen() {
-        let dir = PathBuf::from("test_1000_entries_can_be_inserted_and_retrived_on_reopen");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
+    /// Applies all writes of `batch` atomically. Fails without writing anything if the batch
+    /// names a column family that doesn't exist.
+    pub fn write(&self, batch: &WriteBatch) -> io::Result<()> {
+        let mut families: BTreeMap<u32, Arc<ColumnFamily<S>>> = BTreeMap::new();
+        let mut entries = Vec::with_capacity(batch.len());
+
+        for (name, key, op) in batch.ops.iter() {
+            let family = self.find_family(name)?;
+
+            let entry = match op {
+                BatchOp::Insert(value) => family.value_entry(value),
+                BatchOp::Merge(operand) => {
+                    family.require_merge_operator()?;
+                    Entry::Merge(vec![operand.clone()])
+                }
+                BatchOp::Delete => Entry::Tombstone,
+            };
+
+            entries.push((family.id, key.as_str(), entry));
+            families.entry(family.id).or_insert(family);
         }
 
-        fs::create_dir_all(&dir).unwrap();
+        // Memtables are always locked in the order of their column family IDs.
+        let mut memtables: BTreeMap<u32, _> = families
+            .iter()
+            .map(|(id, family)| (*id, family.memtable.lock().unwrap()))
+            .collect();
 
-        let store = make_store(dir.clone()).unwrap();
+        // Merges are combined with what is already in the memtable, and with earlier writes of
+        // the batch, before anything is logged.
+        let mut combined: BTreeMap<(u32, &str), Entry> = BTreeMap::new();
 
-        for i in 0..1000 {
-            store
-                .insert(
-                    &format!("key_{:04}", i),
-                    &format!("value_{:04}", i).as_bytes(),
-                )
-                .unwrap();
+        for (id, key, entry) in entries.iter() {
+            let existing = combined
+                .get(&(*id, *key))
+                .or_else(|| memtables[id].get(key));
+
+            let operator = families[id].merge_operator();
+            let entry = combine_with_existing(operator, key, entry, existing)?
+                .unwrap_or_else(|| entry.clone());
+
+            combined.insert((*id, *key), entry);
         }
 
-        drop(store);
+        self.wal()?
+            .log_many(entries.iter().map(|(id, key, entry)| (*id, *key, entry)))?;
 
-        let store = make_store(dir.clone()).unwrap();
-        for i in 0..1000 {
-            let value = store.get(&format!("key_{:04}", i)).unwrap();
-            assert_eq!(value, Some(format!("value_{:04}", i).as_bytes().to_vec()));
+        for (id, key, entry) in entries.iter() {
+            families[id].memtable_size.fetch_add(key.len() + entry.size(), Ordering::Relaxed);
         }
-    }
 
-    #[test]
-    fn test_5000_entries_can_be_inserted_and_retrived_on_reopen() {
-        let dir = PathBuf::from("test_5000_entries_can_be_inserted_and_retrived_on_reopen");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
+        for ((id, key), entry) in combined {
+            memtables.get_mut(&id).unwrap().insert(key.to_owned(), entry);
         }
 
-        fs::create_dir_all(&dir).unwrap();
+        drop(memtables);
 
-        let store = make_store(dir.clone()).unwrap();
+        for family in families.values() {
+            self.maybe_flush_memtables(family)?;
+        }
 
-        for i in 0..5000 {
-            store
-                .insert(
-                    &format!("key_{:04}", i),
-                    &format!("value_{:04}", i).as_bytes(),
-                )
-                .unwrap();
+        Ok(())
+    }
+
+    fn find_family(&self, name: &str) -> io::Result<Arc<ColumnFamily<S>>> {
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            return Ok(self.default_family.clone());
         }
 
-        drop(store);
+        self.column_families
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("column family '{name}' does not exist"),
+                )
+            })
+    }
 
-        let store = make_store(dir.clone()).unwrap();
-        for i in 0..5000 {
-            let value = store.get(&format!("key_{:04}", i)).unwrap();
-            assert_eq!(value, Some(format!("value_{:04}", i).as_bytes().to_vec()));
+    /// Returns true if the store was opened through [`StoreImpl::open_read_only`].
+    pub fn is_read_only(&self) -> bool {
+        self.wal.is_none()
+    }
+
+    fn ensure_writable(&self) -> io::Result<()> {
+        match self.wal {
+            Some(_) => Ok(()),
+            None => Err(read_only_error()),
         }
     }
 
-    #[test]
-    fn test_memtable_flushes_after_max_size() {
-        let dir = PathBuf::from("test_memtable_flushes_after_max_size");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
+    /// Returns the WAL, which only a store opened for writing has.
+    fn wal(&self) -> io::Result<MutexGuard<'_, Wal>> {
+        match &self.wal {
+            Some(wal) => Ok(wal.lock().unwrap()),
+            None => Err(read_only_error()),
         }
+    }
 
-        fs::create_dir_all(&dir).unwrap();
+    /// Replaces the memtables of all column families with the entries of the given WAL records.
+    fn load_memtables(&self, records: Vec<WalRecord>) -> io::Result<()> {
+        let column_families = self.column_families.read().unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
+        let mut families: Vec<_> = std::iter::once(&*self.default_family)
+            .chain(column_families.values().map(|it| &**it))
+            .collect();
 
-        let file_count = fs::read_dir(&dir).unwrap().count();
+        families.sort_unstable_by_key(|it| it.id);
 
-        let key_len = "a_long_long_long_key_0000".len();
-        let value_len = "a_long_long_long_value_0000".len();
+        let mut batches = replay(records, &self.manifest.current(), &families, &self.default_family.comparator)?;
 
-        let n_items = (MAX_MEMTABLE_SIZE / (key_len + value_len)) + 1;
+        for family in families {
+            let batch = batches
+                .remove(&family.id)
+                .unwrap_or_else(|| Memtable::new(family.comparator.clone()));
 
-        for i in 0..n_items {
-            store
-                .insert(
-                    &format!("a_long_long_long_key_{:04}", i),
-                    &format!("a_long_long_long_value_{:04}", i).as_bytes(),
-                )
-                .unwrap();
+            *family.memtable.lock().unwrap() = batch;
         }
 
-        assert_eq!(fs::read_dir(&dir).unwrap().count(), file_count + 1);
+        Ok(())
+    }
 
-        drop(store);
+    fn insert_entry(&self, family: &ColumnFamily<S>, key: &str, entry: Entry) -> io::Result<()> {
+        let mut memtable = family.memtable.lock().unwrap();
+        self.insert_entry_locked(family, &mut memtable, key, entry)?;
+        drop(memtable);
+
+        self.maybe_flush_memtables(family)
     }
 
-    #[test]
-    fn test_only_one_process_can_open_the_store() {
-        let dir = PathBuf::from("test_only_one_process_can_open_the_store");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
-        }
+    /// Writes an entry to the WAL and the memtable. Holding the memtable lock orders this write
+    /// with every other one to the column family.
+    fn insert_entry_locked(
+        &self,
+        family: &ColumnFamily<S>,
+        memtable: &mut Memtable,
+        key: &str,
+        entry: Entry,
+    ) -> io::Result<()> {
+        // Combine before logging, so a merge operand the operator rejects never makes it into the
+        // WAL.
+        let combined = combine_with_existing(family.merge_operator(), key, &entry, memtable.get(key))?;
 
-        fs::create_dir_all(&dir).unwrap();
+        self.wal()?.log_one(family.id, key, &entry)?;
 
-        let store = make_store(dir.clone()).unwrap();
-        let store2 = make_store(dir.clone());
-        let store3 = make_store(dir.clone());
+        family.memtable_size.fetch_add(key.len() + entry.size(), Ordering::Relaxed);
+        memtable.insert(key.to_owned(), combined.unwrap_or(entry));
 
-        assert!(store2.is_err());
-        assert!(store3.is_err());
+        Ok(())
+    }
 
-        drop(store);
+    /// Reads the value of `key`, given its entry in the memtable if it has one.
+    fn read_value(
+        &self,
+        family: &ColumnFamily<S>,
+        key: &str,
+        memtable_entry: Option<Entry>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let sstable_entry = match memtable_entry {
+            None | Some(Entry::Merge(_)) => family.lsm_tree.get(key)?,
+            Some(_) => None,
+        };
+
+        resolve_value(family.merge_operator(), key, memtable_entry, sstable_entry, now_millis())
     }
 
-    #[test]
-    fn test_last_inserted_entries_are_not_lost_on_reopen() {
-        let dir = PathBuf::from("test_last_inserted_entries_are_not_lost_on_reopen");
-        if dir.exists() {
-            fs::remove_dir_all(&dir).unwrap();
+    /// Flushes the memtables of all column families and truncates the WAL they share.
+    fn flush_memtables(&self) -> io::Result<()> {
+        self.ensure_writable()?;
+
+        // Column families can't be created until the WAL is truncated, since writes to them
+        // would otherwise be dropped from the WAL without having been flushed.
+        let column_families = self.column_families.read().unwrap();
+
+        let mut families: Vec<_> = std::iter::once(&self.default_family)
+            .chain(column_families.values())
+            .collect();
+
+        families.sort_unstable_by_key(|it| it.id);
+
+        let mut memtables: Vec<_> = families
+            .iter()
+            .map(|it| it.memtable.lock().unwrap())
+            .collect();
+
+        // Entries are logged while their memtable is locked, so with all of them locked the
+        // memtables hold every entry logged up to the last record.
+        let wal_sequence = self.wal()?.last_sequence();
+
+        for (family, memtable) in families.iter().zip(memtables.iter_mut()) {
+            if memtable.is_empty() {
+                continue;
+            }
+
+            // Each SST records the WAL records it flushed, so if a later one fails, or the WAL
+            // isn't truncated before a crash, their entries aren't restored again.
+            family.lsm_tree.write_flushed_sstable(memtable.iter(), wal_sequence)?;
+            memtable.clear();
+            family.memtable_size.store(0, Ordering::Relaxed);
         }
 
-        fs::create_dir_all(&dir).unwrap();
+        self.wal()?.truncate()?;
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo", "bar".as_bytes()).unwrap();
-        drop(store);
+        Ok(())
+    }
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo", "baz".as_bytes()).unwrap();
-        drop(store);
+    fn checkpoint_to(&self, dest: &Path) -> io::Result<()> {
+        let state = self.checkpoint_state()?;
 
-        let store = make_store(dir.clone()).unwrap();
-        let value = store.get("foo").unwrap();
-        assert!(value.is_some());
-        assert_eq!(value, Some(b"baz".to_vec()));
+        write_checkpoint(&self.directory, &state, dest)
     }
 
-    #[test]
-    fn test_can_retrive_unbounded_range() {
-        let dir = PathBuf::from("test_can_retrive_unbounded_range");
-        let _ = fs::remove_dir_all(&dir);
-        fs::create_dir_all(&dir).unwrap();
+    /// Captures what a checkpoint taken now holds. Flushes may follow as soon as this returns,
+    /// which doesn't affect the captured state.
+    fn checkpoint_state(&self) -> io::Result<CheckpointState> {
+        let column_families = self.column_families.read().unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo", "bar".as_bytes()).unwrap();
-        store.insert("foo2", "bar2".as_bytes()).unwrap();
-        store.insert("foo3", "bar3".as_bytes()).unwrap();
+        let mut families: Vec<_> = std::iter::once(&self.default_family)
+            .chain(column_families.values())
+            .collect();
+
+        families.sort_unstable_by_key(|it| it.id);
+
+        let memtables: Vec<_> = families
+            .iter()
+            .map(|it| it.memtable.lock().unwrap())
+            .collect();
+
+        // Memtables are only flushed while all of them are locked, so together with the
+        // current version they hold every write exactly once. The rest of the manifest state
+        // is taken along with it, since flushes may update it as soon as they are unlocked.
+        let snapshot = self.manifest.snapshot();
+
+        let unflushed: Vec<_> = families
+            .iter()
+            .zip(memtables.iter())
+            .flat_map(|(family, memtable)| {
+                memtable
+                    .iter()
+                    .map(|(key, entry)| (family.id, key.to_owned(), entry.clone()))
+            })
+            .collect();
+
+        let last_sequence = self.wal()?.last_sequence();
+
+        Ok(CheckpointState {
+            snapshot,
+            unflushed,
+            last_sequence,
+        })
+    }
+
+    fn maybe_flush_memtables(&self, family: &ColumnFamily<S>) -> io::Result<()> {
+        if family.memtable_size.load(Ordering::Relaxed) > MAX_MEMTABLE_SIZE {
+            self.flush_memtables()?;
+        }
+
+        Ok(())
+    }
+
+    fn has_unflushed_entries(&self) -> bool {
+        let column_families = self.column_families.read().unwrap();
+
+        std::iter::once(&self.default_family)
+            .chain(column_families.values())
+            .any(|it| !it.memtable.lock().unwrap().is_empty())
+    }
+
+    fn insert_into(&self, family: &ColumnFamily<S>, key: &str, value: &[u8]) -> io::Result<()> {
+        self.insert_entry(family, key, family.value_entry(value))
+    }
+
+    fn insert_with_ttl_into(
+        &self,
+        family: &ColumnFamily<S>,
+        key: &str,
+        value: &[u8],
+        ttl: Duration,
+    ) -> io::Result<()> {
+        self.insert_entry(family, key, Entry::with_ttl(value.to_owned(), Some(ttl)))
+    }
+
+    fn insert_batch_into(
+        &self,
+        family: &ColumnFamily<S>,
+        entries: &BTreeMap<String, Vec<u8>>,
+    ) -> io::Result<()> {
+        let mut batch = WriteBatch::new();
+
+        for (key, value) in entries.iter() {
+            batch.insert_cf(&family.name, key, value);
+        }
+
+        self.write(&batch)
+    }
+
+    fn merge_into(&self, family: &ColumnFamily<S>, key: &str, operand: &[u8]) -> io::Result<()> {
+        family.require_merge_operator()?;
+        self.insert_entry(family, key, Entry::Merge(vec![operand.to_owned()]))
+    }
+
+    fn compare_and_swap_in(
+        &self,
+        family: &ColumnFamily<S>,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> io::Result<bool> {
+        // The memtable stays locked from reading the current value until the new one is written,
+        // so no other write can get in between.
+        let mut memtable = family.memtable.lock().unwrap();
+
+        let current = self.read_value(family, key, memtable.get(key).cloned())?;
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+
+        let entry = match new {
+            Some(value) => family.value_entry(value),
+            None => Entry::Tombstone,
+        };
+
+        self.insert_entry_locked(family, &mut memtable, key, entry)?;
+        drop(memtable);
+
+        self.maybe_flush_memtables(family)?;
+
+        Ok(true)
+    }
+
+    fn get_from(&self, family: &ColumnFamily<S>, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let entry = family.memtable.lock().unwrap().get(key).cloned();
+        self.read_value(family, key, entry)
+    }
+
+    fn multi_get_from(&self, family: &ColumnFamily<S>, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let memtable_entries: Vec<_> = {
+            let memtable = family.memtable.lock().unwrap();
+            keys.iter().map(|key| memtable.get(key).cloned()).collect()
+        };
+
+        // Keys with a value in the memtable need not be looked up in the SSTables.
+        let needs_lookup = |entry: &Option<Entry>| matches!(entry, None | Some(Entry::Merge(_)));
+
+        let lookups: Vec<_> = keys
+            .iter()
+            .zip(&memtable_entries)
+            .filter(|(_, entry)| needs_lookup(entry))
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut sstable_entries = family.lsm_tree.multi_get(&lookups)?.into_iter();
+        let now = now_millis();
+
+        keys.iter()
+            .zip(memtable_entries)
+            .map(|(key, memtable_entry)| {
+                let sstable_entry = match needs_lookup(&memtable_entry) {
+                    true => sstable_entries.next().flatten(),
+                    false => None,
+                };
+
+                resolve_value(family.merge_operator(), key, memtable_entry, sstable_entry, now)
+            })
+            .collect()
+    }
+
+    /// Returns the live values in `range`, of only the keys starting with `prefix` if one is given.
+    fn get_range_from<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        family: &'a ColumnFamily<S>,
+        range: R,
+        prefix: Option<&'a str>,
+    ) -> io::Result<impl Cursor + 'a> {
+        let entries = self.get_expiring_range_from(family, range, prefix)?;
+
+        Ok(entries.map(|item| item.map(|(key, value, _)| (key, value))))
+    }
+
+    /// Returns the live values in `range` like [`StoreImpl::get_range_from`], along with the
+    /// time they expire at.
+    fn get_expiring_range_from<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        family: &'a ColumnFamily<S>,
+        range: R,
+        prefix: Option<&'a str>,
+    ) -> io::Result<impl ExpiringCursor + 'a> {
+        let memtable_iter = family
+            .memtable
+            .lock()
+            .unwrap()
+            .range(range.clone())
+            .map(|(k, v)| (k.to_owned(), v.clone()))
+            .map(Ok)
+            // This is not a &mut method and we therefore can't just return an iterator
+            // that refrences memtable since a parallel writer may mutate that.
+            // We therefore copy the memtable into a Vec and make an iterator out of that.
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        let lsm_tree_iter = family
+            .lsm_tree
+            .get_range(range, prefix)?;
+
+        let now = now_millis();
+
+        let merged = merge_sorted_grouped_cursor(
+            vec![
+                // Since these are entirely different types, we need to box them,
+                // monomorphization is not possible. Put them behind a trait object.
+                (Box::new(memtable_iter) as Box<dyn EntryCursor>),
+                (Box::new(lsm_tree_iter) as Box<dyn EntryCursor>),
+            ],
+            family.comparator.as_ref(),
+        );
+
+        // Expired entries are dropped only after merging so they still shadow older versions.
+        Ok(merged.filter_map(move |item| {
+            let entry = item.and_then(|(key, versions)| {
+                let entry = collapse(family.merge_operator(), &key, versions, now, true)?;
+                Ok((key, entry))
+            });
+
+            match entry {
+                // The range of a prefix may hold other keys, depending on the comparator.
+                Ok((key, _)) if prefix.is_some_and(|prefix| !key.starts_with(prefix)) => None,
+                Ok((_, entry)) if entry.is_expired(now) => None,
+                Ok((key, Entry::Value { value, expires_at })) => Some(Ok((key, value, expires_at))),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            }
+        }))
+    }
+
+    fn get_keys_from<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        family: &'a ColumnFamily<S>,
+        range: R,
+        options: &RangeOptions,
+    ) -> io::Result<impl KeyCursor + 'a> {
+        let memtable_iter = family
+            .memtable
+            .lock()
+            .unwrap()
+            .range(range.clone())
+            .map(|(k, v)| Ok((k.to_owned(), v.without_values())))
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        let lsm_tree_iter = family.lsm_tree.get_range_keys(range)?;
+
+        let merged = merge_sorted_grouped_cursor(
+            vec![
+                (Box::new(memtable_iter) as Box<dyn EntryCursor>),
+                (Box::new(lsm_tree_iter) as Box<dyn EntryCursor>),
+            ],
+            family.comparator.as_ref(),
+        );
+
+        let now = now_millis();
+
+        let keys = merged.filter_map(move |item| match item {
+            Ok((key, versions)) => versions[0].is_live(now).then_some(Ok(key)),
+            Err(e) => Some(Err(e)),
+        });
+
+        Ok(options.apply(keys))
+    }
+
+    fn scan_prefix_in<'a>(
+        &'a self,
+        family: &'a ColumnFamily<S>,
+        prefix: &'a str,
+    ) -> io::Result<impl Cursor + 'a> {
+        let range = prefix_range(family.comparator.as_ref(), prefix);
+        self.get_range_from(family, range, Some(prefix))
+    }
+
+    fn iter_in<'a>(&'a self, family: &'a ColumnFamily<S>) -> io::Result<StoreIterator<'a>> {
+        let comparator = family.comparator.as_ref();
+
+        let memtable: Vec<_> = family
+            .memtable
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.clone()))
+            .collect();
+
+        let (version, sstables) = family.lsm_tree.seekable_sources()?;
+
+        let sources = std::iter::once(Box::new(VecSource::new(memtable, comparator)) as Box<dyn SeekableSource>)
+            .chain(sstables.into_iter().map(|it| Box::new(it) as Box<dyn SeekableSource>))
+            .collect();
+
+        Ok(StoreIterator::new(
+            sources,
+            comparator,
+            family.merge_operator(),
+            family.lsm_tree.blob_reader(),
+            now_millis(),
+            version,
+        ))
+    }
+
+    fn flush_all(&self) -> io::Result<()> {
+        self.ensure_writable()?;
+
+        if let Err(e) = self.flush_unflushed() {
+            eprintln!("Error flushing memtable: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the memtables if any of them holds entries, failing if they can't be flushed.
+    fn flush_unflushed(&self) -> io::Result<()> {
+        self.ensure_writable()?;
+
+        if !self.has_unflushed_entries() {
+            return Ok(());
+        }
+
+        self.flush_memtables()
+    }
+
+    fn compact_range_in<R: RangeBounds<str> + Clone>(
+        &self,
+        family: &ColumnFamily<S>,
+        range: R,
+        target_level: u8,
+    ) -> io::Result<()> {
+        self.flush_unflushed()?;
+        family.lsm_tree.compact_range(range, target_level)
+    }
+
+    fn ingest_into<P: AsRef<Path>>(&self, family: &ColumnFamily<S>, paths: &[P]) -> io::Result<()> {
+        // Otherwise older writes still in the memtable would shadow the ingested entries, so
+        // nothing is ingested if they can't be flushed.
+        self.flush_unflushed()?;
+
+        let paths: Vec<&Path> = paths.iter().map(AsRef::as_ref).collect();
+        family.lsm_tree.ingest_sstables(&paths)
+    }
+}
+
+impl<S: SSTableReader> Store for StoreImpl<S> {
+    fn insert(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.insert_into(&self.default_family, key, value)
+    }
+
+    fn insert_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> io::Result<()> {
+        self.insert_with_ttl_into(&self.default_family, key, value, ttl)
+    }
+
+    fn insert_batch(&self, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+        self.insert_batch_into(&self.default_family, entries)
+    }
+
+    fn merge(&self, key: &str, operand: &[u8]) -> io::Result<()> {
+        self.merge_into(&self.default_family, key, operand)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> io::Result<bool> {
+        self.compare_and_swap_in(&self.default_family, key, expected, new)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.get_from(&self.default_family, key)
+    }
+
+    fn multi_get(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        self.multi_get_from(&self.default_family, keys)
+    }
+
+    fn get_range<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+    ) -> io::Result<impl Cursor + 'a> {
+        self.get_range_from(&self.default_family, range, None)
+    }
+
+    fn get_range_with_expiry<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+    ) -> io::Result<impl ExpiringCursor + 'a> {
+        self.get_expiring_range_from(&self.default_family, range, None)
+    }
+
+    fn get_keys<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+        options: &RangeOptions,
+    ) -> io::Result<impl KeyCursor + 'a> {
+        self.get_keys_from(&self.default_family, range, options)
+    }
+
+    fn scan_prefix<'a>(&'a self, prefix: &'a str) -> io::Result<impl Cursor + 'a> {
+        self.scan_prefix_in(&self.default_family, prefix)
+    }
+
+    fn iter(&self) -> io::Result<StoreIterator<'_>> {
+        self.iter_in(&self.default_family)
+    }
+
+    /// Flushes the memtables of all column families, since they share the WAL.
+    fn flush(&self) -> io::Result<()> {
+        self.flush_all()
+    }
+
+    fn compact_range<R: RangeBounds<str> + Clone>(&self, range: R, target_level: u8) -> io::Result<()> {
+        self.compact_range_in(&self.default_family, range, target_level)
+    }
+
+    fn checkpoint(&self, directory: &Path) -> io::Result<()> {
+        self.checkpoint_to(directory)
+    }
+
+    fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<()> {
+        self.ingest_into(&self.default_family, paths)
+    }
+}
+
+/// A column family of a store, read and written through [`Store`].
+pub struct ColumnFamilyHandle<'a, S: SSTableReader> {
+    store: &'a StoreImpl<S>,
+    family: Arc<ColumnFamily<S>>,
+}
+
+impl<S: SSTableReader> ColumnFamilyHandle<'_, S> {
+    pub fn name(&self) -> &str {
+        &self.family.name
+    }
+}
+
+impl<S: SSTableReader> Store for ColumnFamilyHandle<'_, S> {
+    fn insert(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.store.insert_into(&self.family, key, value)
+    }
+
+    fn insert_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> io::Result<()> {
+        self.store.insert_with_ttl_into(&self.family, key, value, ttl)
+    }
+
+    fn insert_batch(&self, entries: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+        self.store.insert_batch_into(&self.family, entries)
+    }
+
+    fn merge(&self, key: &str, operand: &[u8]) -> io::Result<()> {
+        self.store.merge_into(&self.family, key, operand)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> io::Result<bool> {
+        self.store.compare_and_swap_in(&self.family, key, expected, new)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.store.get_from(&self.family, key)
+    }
+
+    fn multi_get(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        self.store.multi_get_from(&self.family, keys)
+    }
+
+    fn get_range<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+    ) -> io::Result<impl Cursor + 'a> {
+        self.store.get_range_from(&self.family, range, None)
+    }
+
+    fn get_range_with_expiry<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+    ) -> io::Result<impl ExpiringCursor + 'a> {
+        self.store.get_expiring_range_from(&self.family, range, None)
+    }
+
+    fn get_keys<'a, R: RangeBounds<str> + Clone + 'a>(
+        &'a self,
+        range: R,
+        options: &RangeOptions,
+    ) -> io::Result<impl KeyCursor + 'a> {
+        self.store.get_keys_from(&self.family, range, options)
+    }
+
+    fn scan_prefix<'a>(&'a self, prefix: &'a str) -> io::Result<impl Cursor + 'a> {
+        self.store.scan_prefix_in(&self.family, prefix)
+    }
+
+    fn iter(&self) -> io::Result<StoreIterator<'_>> {
+        self.store.iter_in(&self.family)
+    }
+
+    /// Flushes the memtables of all column families, since they share the WAL.
+    fn flush(&self) -> io::Result<()> {
+        self.store.flush_all()
+    }
+
+    fn compact_range<R: RangeBounds<str> + Clone>(&self, range: R, target_level: u8) -> io::Result<()> {
+        self.store.compact_range_in(&self.family, range, target_level)
+    }
+
+    /// Checkpoints the whole store, with every column family, not just this one.
+    fn checkpoint(&self, directory: &Path) -> io::Result<()> {
+        self.store.checkpoint_to(directory)
+    }
+
+    fn ingest_external_files<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<()> {
+        self.store.ingest_into(&self.family, paths)
+    }
+}
+
+impl<S: SSTableReader> Drop for StoreImpl<S> {
+    fn drop(&mut self) {
+        // The memtables of a read-only store only hold what is in the WAL already.
+        if self.is_read_only() {
+            return;
+        }
+
+        if let Err(e) = self.flush() {
+            eprintln!("Unable to flush store: {e}");
+        }
+    }
+}
+
+pub type DefaultStore = StoreImpl<CachedSSTableReader<FsSSTReader>>;
+
+pub fn make_store(directory: PathBuf) -> io::Result<DefaultStore> {
+    StoreImpl::open(directory)
+}
+
+pub fn make_store_with_options(directory: PathBuf, options: Options) -> io::Result<DefaultStore> {
+    StoreImpl::open_with_options(directory, options)
+}
+
+/// Opens a store along with the given column families, see
+/// [`StoreImpl::open_with_column_families`].
+pub fn make_store_with_column_families(
+    directory: PathBuf,
+    options: Options,
+    column_families: BTreeMap<String, Options>,
+) -> io::Result<DefaultStore> {
+    StoreImpl::open_with_column_families(directory, options, column_families)
+}
+
+/// Opens the store in `directory` read-only, see [`StoreImpl::open_read_only`].
+pub fn open_read_only(directory: PathBuf) -> io::Result<DefaultStore> {
+    StoreImpl::open_read_only(directory, Options::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::ops::Bound::*;
+
+    use super::*;
+    use crate::lsm_tree::MAX_LEVEL;
+    use crate::sst_file_writer::SstFileWriter;
+
+
+    #[test]
+    fn test_inserted_entries_can_be_retrieved() {
+        let dir = PathBuf::from("test_inserted_entries_can_be_retrieved");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir).unwrap();
+
+        let actual_value = vec![0, 1, 2];
+
+        store.insert("hello", actual_value.as_slice()).unwrap();
+
+        let value = store.get("hello").unwrap();
+
+        assert_eq!(value, Some(actual_value));
+    }
+
+    #[test]
+    fn test_inserted_entries_can_be_retrieved_on_reopen() {
+        let dir = PathBuf::from("test_inserted_entries_can_be_retrieved_on_reopen");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        store.insert("hello", "world".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        let value = store.get("hello").unwrap();
+        assert_eq!(value, Some("world".as_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_1000_entries_can_be_inserted_and_retrived_on_reopen() {
+        let dir = PathBuf::from("test_1000_entries_can_be_inserted_and_retrived_on_reopen");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        for i in 0..1000 {
+            store
+                .insert(
+                    &format!("key_{:04}", i),
+                    format!("value_{:04}", i).as_bytes(),
+                )
+                .unwrap();
+        }
+
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        for i in 0..1000 {
+            let value = store.get(&format!("key_{:04}", i)).unwrap();
+            assert_eq!(value, Some(format!("value_{:04}", i).as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_5000_entries_can_be_inserted_and_retrived_on_reopen() {
+        let dir = PathBuf::from("test_5000_entries_can_be_inserted_and_retrived_on_reopen");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        for i in 0..5000 {
+            store
+                .insert(
+                    &format!("key_{:04}", i),
+                    format!("value_{:04}", i).as_bytes(),
+                )
+                .unwrap();
+        }
+
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        for i in 0..5000 {
+            let value = store.get(&format!("key_{:04}", i)).unwrap();
+            assert_eq!(value, Some(format!("value_{:04}", i).as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_memtable_flushes_after_max_size() {
+        let dir = PathBuf::from("test_memtable_flushes_after_max_size");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        let file_count = fs::read_dir(&dir).unwrap().count();
+
+        let key_len = "a_long_long_long_key_0000".len();
+        let value_len = "a_long_long_long_value_0000".len();
+
+        let n_items = (MAX_MEMTABLE_SIZE / (key_len + value_len)) + 1;
+
+        for i in 0..n_items {
+            store
+                .insert(
+                    &format!("a_long_long_long_key_{:04}", i),
+                    format!("a_long_long_long_value_{:04}", i).as_bytes(),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), file_count + 1);
+
+        drop(store);
+    }
+
+    #[test]
+    fn test_only_one_process_can_open_the_store() {
+        let dir = PathBuf::from("test_only_one_process_can_open_the_store");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        let store2 = make_store(dir.clone());
+        let store3 = make_store(dir.clone());
+
+        assert!(store2.is_err());
+        assert!(store3.is_err());
+
+        drop(store);
+    }
+
+    #[test]
+    fn test_last_inserted_entries_are_not_lost_on_reopen() {
+        let dir = PathBuf::from("test_last_inserted_entries_are_not_lost_on_reopen");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo", "bar".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo", "baz".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        let value = store.get("foo").unwrap();
+        assert!(value.is_some());
+        assert_eq!(value, Some(b"baz".to_vec()));
+    }
+
+    #[test]
+    fn test_can_retrive_unbounded_range() {
+        let dir = PathBuf::from("test_can_retrive_unbounded_range");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo", "bar".as_bytes()).unwrap();
+        store.insert("foo2", "bar2".as_bytes()).unwrap();
+        store.insert("foo3", "bar3".as_bytes()).unwrap();
 
         let iter = store.get_range(..).unwrap();
         let values = iter.map(Result::unwrap).collect::<Vec<_>>();
 
-        assert_eq!(
-            values,
-            vec![
-                ("foo".to_owned(), "bar".as_bytes().to_vec()),
-                ("foo2".to_owned(), "bar2".as_bytes().to_vec()),
-                ("foo3".to_owned(), "bar3".as_bytes().to_vec())
-            ]
-        );
+        assert_eq!(
+            values,
+            vec![
+                ("foo".to_owned(), "bar".as_bytes().to_vec()),
+                ("foo2".to_owned(), "bar2".as_bytes().to_vec()),
+                ("foo3".to_owned(), "bar3".as_bytes().to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_can_retrive_bounded_range() {
+        let path: PathBuf = "test_can_retrive_bounded_range".into();
+        if path.exists() {
+            std::fs::remove_dir_all(path.clone()).unwrap();
+        }
+
+        let store = make_store(path).unwrap();
+        for i in 0..1024 {
+            store
+                .insert(
+                    &format!("key_{:04}", i),
+                    &format!("value_{:04}", i).bytes().collect::<Vec<u8>>()
+                )
+                .unwrap();
+        }
+
+        let expected: Vec<_> = (100..1000)
+            .map(|i|
+                (format!("key_{:04}", i), format!("value_{:04}", i)
+                    .bytes()
+                    .collect::<Vec<_>>()))
+            .collect();
+
+        fn assert(store: &impl Store, expected: &Vec<(String, Vec<u8>)>) {
+            let actual: Vec<_> = store.get_range((Included("key_0100"), Excluded("key_1000")))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect();
+
+            assert_eq!(&actual, expected);
+
+        }
+
+        assert(&store, &expected);
+
+        store.flush().unwrap();
+        assert(&store, &expected);
+
+    }
+
+    #[test]
+    fn test_can_retrieve_range_across_memtable_and_lsm_tree() {
+        let dir = PathBuf::from("test_can_retrieve_range_across_memtable_and_lsm_tree");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo", "bar".as_bytes()).unwrap();
+        store.insert("foo2", "bar2".as_bytes()).unwrap();
+
+        // Dropping the store flushes the memtable to the LSM tree
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+
+        // These keys should be in the memtable
+        store.insert("foo3", "bar3".as_bytes()).unwrap();
+        store.insert("foo4", "bar4".as_bytes()).unwrap();
+
+        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                ("foo".to_owned(), "bar".as_bytes().to_vec()),
+                ("foo2".to_owned(), "bar2".as_bytes().to_vec()),
+                ("foo3".to_owned(), "bar3".as_bytes().to_vec()),
+                ("foo4".to_owned(), "bar4".as_bytes().to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_can_retrieve_range_across_memtable_and_multiple_sstables() {
+        let dir = PathBuf::from("test_can_retrieve_range_across_memtable_and_multiple_sstables");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("sst1:foo0", "bar0".as_bytes()).unwrap();
+        store.insert("sst1:foo1", "bar1".as_bytes()).unwrap();
+        store.insert("sst1:foo2", "bar2".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("sst2:foo0", "bar0".as_bytes()).unwrap();
+        store.insert("sst2:foo1", "bar1".as_bytes()).unwrap();
+        store.insert("sst2:foo2", "bar2".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("sst3:foo0", "bar0".as_bytes()).unwrap();
+        store.insert("sst3:foo1", "bar1".as_bytes()).unwrap();
+        store.insert("sst3:foo2", "bar2".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("memtable:foo0", "bar0".as_bytes()).unwrap();
+        store.insert("memtable:foo1", "bar1".as_bytes()).unwrap();
+        store.insert("memtable:foo2", "bar2".as_bytes()).unwrap();
+        // this should be the last entry
+        store.insert("z:memtable:foo2", "bar2".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                ("memtable:foo0".to_owned(), "bar0".as_bytes().to_vec()),
+                ("memtable:foo1".to_owned(), "bar1".as_bytes().to_vec()),
+                ("memtable:foo2".to_owned(), "bar2".as_bytes().to_vec()),
+                ("sst1:foo0".to_owned(), "bar0".as_bytes().to_vec()),
+                ("sst1:foo1".to_owned(), "bar1".as_bytes().to_vec()),
+                ("sst1:foo2".to_owned(), "bar2".as_bytes().to_vec()),
+                ("sst2:foo0".to_owned(), "bar0".as_bytes().to_vec()),
+                ("sst2:foo1".to_owned(), "bar1".as_bytes().to_vec()),
+                ("sst2:foo2".to_owned(), "bar2".as_bytes().to_vec()),
+                ("sst3:foo0".to_owned(), "bar0".as_bytes().to_vec()),
+                ("sst3:foo1".to_owned(), "bar1".as_bytes().to_vec()),
+                ("sst3:foo2".to_owned(), "bar2".as_bytes().to_vec()),
+                ("z:memtable:foo2".to_owned(), "bar2".as_bytes().to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_reads_memtable_entreies_override_sstable_entreies() {
+        let dir = PathBuf::from("test_range_reads_memtable_entreies_override_sstable_entreies");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo", b"bar").unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo", b"bar2").unwrap();
+
+        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+
+        assert_eq!(actual, vec![("foo".to_string(), b"bar2".to_vec())]);
+    }
+
+    #[test]
+    fn test_range_reads_drop_duplicates() {
+        let dir = PathBuf::from("test_range_reads_drop_duplicates");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo0", "wrong".as_bytes()).unwrap();
+        store.insert("foo2", "right".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo0", "wrong2".as_bytes()).unwrap();
+        store.insert("foo3", "wrong3".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo0", "right".as_bytes()).unwrap();
+        store.insert("foo4", "right".as_bytes()).unwrap();
+        drop(store);
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("foo3", "right".as_bytes()).unwrap();
+
+        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+
+        assert_eq!(
+            actual,
+            vec![
+                ("foo0".to_owned(), "right".as_bytes().to_vec()),
+                ("foo2".to_owned(), "right".as_bytes().to_vec()),
+                ("foo3".to_owned(), "right".as_bytes().to_vec()),
+                ("foo4".to_owned(), "right".as_bytes().to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_large_entires_can_be_inserted() {
+        let dir = PathBuf::from("test_large_keys_can_be_inserted");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        const K: i32 = 1024;
+
+        let size_1k: String = (0..K).map(|_| 'a'.to_string()).collect();
+        let size_2k: String = (0..K*2).map(|_| 'a'.to_string()).collect();
+        let size_3k: String = (0..K*3).map(|_| 'a'.to_string()).collect();
+        let size_4k: String = (0..K*4).map(|_| 'a'.to_string()).collect();
+        let size_5k: String = (0..K*5).map(|_| 'a'.to_string()).collect();
+
+        store.insert(&size_1k, size_1k.as_bytes()).unwrap();
+        store.insert(&size_2k, size_2k.as_bytes()).unwrap();
+        store.insert(&size_3k, size_3k.as_bytes()).unwrap();
+        store.insert(&size_4k, size_4k.as_bytes()).unwrap();
+        store.insert(&size_5k, size_5k.as_bytes()).unwrap();
+
+        store.flush().unwrap();
+
+        assert_eq!(
+            Some(size_1k.as_bytes()),
+            store.get(&size_1k).unwrap().as_ref().map(|it| &it[..])
+        );
+
+        assert_eq!(
+            Some(size_2k.as_bytes()),
+            store.get(&size_2k).unwrap().as_ref().map(|it| &it[..])
+        );
+
+        assert_eq!(
+            Some(size_3k.as_bytes()),
+            store.get(&size_3k).unwrap().as_ref().map(|it| &it[..])
+        );
+
+        assert_eq!(
+            Some(size_4k.as_bytes()),
+            store.get(&size_4k).unwrap().as_ref().map(|it| &it[..])
+        );
+
+        assert_eq!(
+            Some(size_5k.as_bytes()),
+            store.get(&size_5k).unwrap().as_ref().map(|it| &it[..])
+        );
+    }
+
+    #[test]
+    fn test_large_values_are_stored_in_blob_files() {
+        let dir = PathBuf::from("test_large_values_are_stored_in_blob_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            blob_threshold: Some(1024),
+            merge_operator: Some(std::sync::Arc::new(crate::AppendOperator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+
+        let large = vec![b'a'; 4096];
+        store.insert("large", &large).unwrap();
+        store.insert("small", b"value").unwrap();
+        store.insert("log", &large).unwrap();
+        store.flush().unwrap();
+
+        let blob_files = store.manifest.current().get_blob_files();
+        assert_eq!(blob_files.len(), 1);
+        assert_eq!(blob_files[0].value_count, 2);
+
+        // Only the references are kept in the SST.
+        assert!(store.manifest.get_sstables()[0].props.file_size < 4096);
+
+        store.merge("log", b"b").unwrap();
+        store.flush().unwrap();
+
+        let mut appended = large.clone();
+        appended.push(b'b');
+
+        assert_eq!(store.get("large").unwrap(), Some(large.clone()));
+        assert_eq!(store.get("log").unwrap(), Some(appended.clone()));
+        assert_eq!(
+            store.multi_get(&["small", "large"]).unwrap(),
+            vec![Some(b"value".to_vec()), Some(large.clone())]
+        );
+
+        let range: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+        assert_eq!(range, vec![
+            ("large".to_owned(), large.clone()),
+            ("log".to_owned(), appended.clone()),
+            ("small".to_owned(), b"value".to_vec()),
+        ]);
+
+        let mut iter = store.iter().unwrap();
+        iter.seek("large").unwrap();
+        assert_eq!(iter.value(), Some(large.as_slice()));
+
+        // The merged value replaces the reference to the original one, which becomes garbage.
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
+        assert_eq!(store.get("log").unwrap(), Some(appended));
+
+        let blob_files = store.manifest.current().get_blob_files();
+        assert_eq!(blob_files.len(), 2);
+        assert_eq!((blob_files[0].garbage_count, blob_files[0].garbage_bytes), (1, 4096));
+    }
+
+    #[test]
+    fn test_checkpoint_can_be_opened_as_a_store() {
+        let dir = PathBuf::from("test_checkpoint_can_be_opened_as_a_store");
+        let dest = PathBuf::from("test_checkpoint_can_be_opened_as_a_store_copy");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&dest);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            blob_threshold: Some(1024),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options.clone()).unwrap();
+        let family = store.create_column_family("cf", options.clone()).unwrap();
+
+        store.insert("flushed", b"value").unwrap();
+        store.insert("large", &[b'a'; 4096]).unwrap();
+        family.insert("flushed", b"family").unwrap();
+        family.insert("large", &[b'b'; 4096]).unwrap();
+        store.flush().unwrap();
+
+        store.insert("unflushed", b"value").unwrap();
+        family.insert("unflushed", b"family").unwrap();
+        family.insert("unflushed_large", &[b'c'; 4096]).unwrap();
+
+        store.checkpoint(&dest).unwrap();
+
+        // Writes made after the checkpoint are not part of it.
+        store.insert("later", b"value").unwrap();
+        store.flush().unwrap();
+
+        let err = store.checkpoint(&dest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        drop(family);
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let families = BTreeMap::from([("cf".to_owned(), options.clone())]);
+        let copy = make_store_with_column_families(dest.clone(), options, families).unwrap();
+        assert!(!copy.manifest.get_blob_file_ids().is_empty());
+        assert_eq!(copy.get("flushed").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(copy.get("large").unwrap(), Some(vec![b'a'; 4096]));
+        assert_eq!(copy.get("unflushed").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(copy.get("later").unwrap(), None);
+
+        let family = copy.column_family("cf").unwrap();
+        assert_eq!(family.get("flushed").unwrap(), Some(b"family".to_vec()));
+        assert_eq!(family.get("large").unwrap(), Some(vec![b'b'; 4096]));
+        assert_eq!(family.get("unflushed").unwrap(), Some(b"family".to_vec()));
+        assert_eq!(family.get("unflushed_large").unwrap(), Some(vec![b'c'; 4096]));
+
+        drop(family);
+        drop(copy);
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_into_existing_directory_leaves_it_untouched() {
+        let dir = PathBuf::from("test_checkpoint_into_existing_directory_leaves_it_untouched");
+        let dest = PathBuf::from("test_checkpoint_into_existing_directory_leaves_it_untouched_copy");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&dest);
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("notes.txt"), b"keep me").unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("key", b"value").unwrap();
+
+        let err = store.checkpoint(&dest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        let entries: Vec<_> = fs::read_dir(&dest)
+            .unwrap()
+            .map(|it| it.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["notes.txt"]);
+        assert_eq!(fs::read(dest.join("notes.txt")).unwrap(), b"keep me");
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_holds_unflushed_writes_flushed_while_it_is_written() {
+        let dir = PathBuf::from("test_checkpoint_holds_unflushed_writes_flushed_while_it_is_written");
+        let dest = PathBuf::from("test_checkpoint_holds_unflushed_writes_flushed_while_it_is_written_copy");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&dest);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::U64AddOperator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options.clone()).unwrap();
+
+        store.insert("counter", &1u64.to_be_bytes()).unwrap();
+        store.flush().unwrap();
+        store.merge("counter", &2u64.to_be_bytes()).unwrap();
+        store.insert("unflushed", b"value").unwrap();
+
+        let state = store.checkpoint_state().unwrap();
+
+        // A flush between taking the state and writing it records a later flushed WAL sequence,
+        // which must not make the checkpoint skip its own unflushed entries.
+        store.insert("later", b"value").unwrap();
+        store.flush().unwrap();
+
+        write_checkpoint(&dir, &state, &dest).unwrap();
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let copy = make_store_with_options(dest.clone(), options).unwrap();
+        assert_eq!(copy.get("counter").unwrap(), u64_value(3));
+        assert_eq!(copy.get("unflushed").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(copy.get("later").unwrap(), None);
+
+        drop(copy);
+        fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn test_ingested_sstables_take_precedence_over_earlier_writes() {
+        let dir = PathBuf::from("test_ingested_sstables_take_precedence_over_earlier_writes");
+        let external = PathBuf::from("test_ingested_sstables_take_precedence_over_earlier_writes_external");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&external);
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(&external).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("a", b"old").unwrap();
+        store.insert("m", b"old").unwrap();
+        store.flush().unwrap();
+        store.insert("b", b"unflushed").unwrap();
+
+        let overlapping = external.join("overlapping.sst");
+        let mut writer = SstFileWriter::create(&overlapping, &Options::default()).unwrap();
+        writer.put("a", b"ingested").unwrap();
+        writer.put("b", b"ingested").unwrap();
+        writer.delete("m").unwrap();
+
+        let err = writer.put("n", b"ingested").and(writer.put("b", b"out of order")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.finish().unwrap(), 4);
+
+        let disjoint = external.join("disjoint.sst");
+        let mut writer = SstFileWriter::create(&disjoint, &Options::default()).unwrap();
+        writer.put("x", b"ingested").unwrap();
+        writer.put("y", b"ingested").unwrap();
+        writer.finish().unwrap();
+
+        // Nothing is ingested if one of the files is invalid.
+        let missing = external.join("missing.sst");
+        assert!(store.ingest_external_files(&[&overlapping, &missing]).is_err());
+        assert_eq!(store.get("a").unwrap(), Some(b"old".to_vec()));
+
+        store.ingest_external_files(&[&overlapping, &disjoint]).unwrap();
+
+        assert_eq!(store.get("a").unwrap(), Some(b"ingested".to_vec()));
+        assert_eq!(store.get("b").unwrap(), Some(b"ingested".to_vec()));
+        assert_eq!(store.get("n").unwrap(), Some(b"ingested".to_vec()));
+        assert_eq!(store.get("m").unwrap(), None);
+        assert_eq!(store.get("y").unwrap(), Some(b"ingested".to_vec()));
+
+        // Only the SST overlapping the flushed ones lands on level 0.
+        let version = store.manifest.current();
+        assert_eq!(version.get_sstables_at_level(DEFAULT_COLUMN_FAMILY, 0).len(), 3);
+        assert_eq!(version.get_sstables_at_level(DEFAULT_COLUMN_FAMILY, MAX_LEVEL).len(), 1);
+
+        drop(version);
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&external).unwrap();
+    }
+
+    #[test]
+    fn test_ingestion_and_compaction_fail_when_memtables_cant_be_flushed() {
+        let dir = PathBuf::from("test_ingestion_and_compaction_fail_when_memtables_cant_be_flushed");
+        let external = PathBuf::from("test_ingestion_and_compaction_fail_when_memtables_cant_be_flushed_external");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&external);
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(&external).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        store.insert("a", b"flushed").unwrap();
+        store.flush().unwrap();
+        store.insert("b", b"unflushed").unwrap();
+
+        let sst = external.join("ingested.sst");
+        let mut writer = SstFileWriter::create(&sst, &Options::default()).unwrap();
+        writer.put("b", b"ingested").unwrap();
+        writer.finish().unwrap();
+
+        // Directories in place of the files the next SSTs would be written to make flushes fail.
+        let next_id = store.manifest.start_update().allocate_id() + 1;
+        let blockers: Vec<_> = (next_id..next_id + 8)
+            .map(|id| crate::sstable::sst_tmp_file_path(&dir, id))
+            .collect();
+        for blocker in blockers.iter() {
+            fs::create_dir(blocker).unwrap();
+        }
+
+        let current = || fs::read_to_string(dir.join("CURRENT")).unwrap();
+        let (sstables, manifest) = (store.manifest.get_sstable_ids(), current());
+        let manifest_len = fs::metadata(dir.join(manifest.trim())).unwrap().len();
+
+        assert!(store.ingest_external_files(&[&sst]).is_err());
+        assert!(store.compact_range(.., MAX_LEVEL).is_err());
+
+        assert_eq!(store.manifest.get_sstable_ids(), sstables);
+        assert_eq!(current(), manifest);
+        assert_eq!(fs::metadata(dir.join(manifest.trim())).unwrap().len(), manifest_len);
+        assert_eq!(store.get("b").unwrap(), Some(b"unflushed".to_vec()));
+
+        for blocker in blockers.iter() {
+            fs::remove_dir(blocker).unwrap();
+        }
+
+        store.ingest_external_files(&[&sst]).unwrap();
+        assert_eq!(store.get("b").unwrap(), Some(b"ingested".to_vec()));
+
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&external).unwrap();
+    }
+
+    #[test]
+    fn test_compact_range_merges_memtable_and_sstables() {
+        let dir = PathBuf::from("test_compact_range_merges_memtable_and_sstables");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        for round in 0..3 {
+            for i in 0..10 {
+                store
+                    .insert(&format!("key_{i}"), format!("value_{i}_{round}").as_bytes())
+                    .unwrap();
+            }
+
+            if round < 2 {
+                store.flush().unwrap();
+            }
+        }
+
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
+
+        let sstables = store.manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].level, crate::MAX_LEVEL);
+        assert_eq!(sstables[0].props.entry_count, 10);
+
+        for i in 0..10 {
+            assert_eq!(
+                store.get(&format!("key_{i}")).unwrap(),
+                Some(format!("value_{i}_2").into_bytes())
+            );
+        }
+    }
+
+    struct DropExpiredFilter;
+
+    impl crate::CompactionFilter for DropExpiredFilter {
+        fn filter(&self, context: &crate::CompactionContext, key: &str, value: &[u8]) -> crate::Decision {
+            assert!(context.is_manual);
+            assert!(context.is_bottommost);
+            assert_eq!(context.target_level, crate::MAX_LEVEL);
+
+            if key.starts_with("expired_") {
+                crate::Decision::Remove
+            } else if value == b"old" {
+                crate::Decision::ChangeValue(b"new".to_vec())
+            } else {
+                crate::Decision::Keep
+            }
+        }
+    }
+
+    #[test]
+    fn test_compaction_filter_removes_and_rewrites_entries() {
+        let dir = PathBuf::from("test_compaction_filter_removes_and_rewrites_entries");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            compaction_filter: Some(std::sync::Arc::new(DropExpiredFilter)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+
+        store.insert("expired_a", b"value").unwrap();
+        store.insert("key_a", b"old").unwrap();
+        store.insert("key_b", b"value").unwrap();
+        store.flush().unwrap();
+
+        // Not compacted yet, so the filter has not seen these.
+        assert_eq!(store.get("expired_a").unwrap(), Some(b"value".to_vec()));
+
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
+
+        assert_eq!(store.get("expired_a").unwrap(), None);
+        assert_eq!(store.get("key_a").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get("key_b").unwrap(), Some(b"value".to_vec()));
+
+        let sstables = store.manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].min_key, "key_a");
+        assert_eq!(sstables[0].props.entry_count, 2);
+    }
+
+    struct RemoveAllFilter;
+
+    impl crate::CompactionFilter for RemoveAllFilter {
+        fn filter(&self, _: &crate::CompactionContext, _: &str, _: &[u8]) -> crate::Decision {
+            crate::Decision::Remove
+        }
+    }
+
+    #[test]
+    fn test_compaction_filter_removing_everything_leaves_no_sstable() {
+        let dir = PathBuf::from("test_compaction_filter_removing_everything_leaves_no_sstable");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            compaction_filter: Some(std::sync::Arc::new(RemoveAllFilter)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+
+        store.insert("key", b"value").unwrap();
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
+
+        assert!(store.manifest.get_sstables().is_empty());
+        assert_eq!(store.get("key").unwrap(), None);
+
+        let ssts = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|it| it.as_ref().unwrap().file_name().to_string_lossy().contains(".sst"))
+            .count();
+        assert_eq!(ssts, 0);
+    }
+
+    #[test]
+    fn test_expired_entries_are_absent() {
+        let dir = PathBuf::from("test_expired_entries_are_absent");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+
+        store.insert("flushed", b"old").unwrap();
+        store.flush().unwrap();
+
+        store.insert_with_ttl("flushed", b"new", Duration::from_millis(50)).unwrap();
+        store.insert_with_ttl("memtable", b"value", Duration::from_millis(50)).unwrap();
+        store.insert("permanent", b"value").unwrap();
+        store.flush().unwrap();
+        store.insert_with_ttl("memtable", b"value", Duration::from_millis(50)).unwrap();
+
+        assert_eq!(store.get("flushed").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get("memtable").unwrap(), Some(b"value".to_vec()));
+
+        std::thread::sleep(Duration::from_millis(100));
+
+        // The expired entry must not make the older version visible again.
+        assert_eq!(store.get("flushed").unwrap(), None);
+        assert_eq!(store.get("memtable").unwrap(), None);
+        assert_eq!(store.get("permanent").unwrap(), Some(b"value".to_vec()));
+
+        let keys: Vec<_> = store.get_range(..).unwrap().map(|it| it.unwrap().0).collect();
+        assert_eq!(keys, vec!["permanent".to_owned()]);
+
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
+
+        let sstables = store.manifest.get_sstables();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].props.entry_count, 1);
+    }
+
+    #[test]
+    fn test_ttl_is_kept_across_reopen() {
+        let dir = PathBuf::from("test_ttl_is_kept_across_reopen");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            default_ttl: Some(Duration::from_millis(100)),
+            ..Options::default()
+        };
+
+        let store = make_store_with_options(dir.clone(), options.clone()).unwrap();
+        store.insert("short", b"value").unwrap();
+        store.insert_with_ttl("long", b"value", Duration::from_secs(3600)).unwrap();
+
+        drop(store);
+
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+        std::thread::sleep(Duration::from_millis(150));
+
+        assert_eq!(store.get("short").unwrap(), None);
+        assert_eq!(store.get("long").unwrap(), Some(b"value".to_vec()));
+    }
+
+    fn u64_value(n: u64) -> Option<Vec<u8>> {
+        Some(n.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_merge_operands_are_combined_with_values() {
+        let dir = PathBuf::from("test_merge_operands_are_combined_with_values");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            merge_operator: Some(std::sync::Arc::new(crate::U64AddOperator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options.clone()).unwrap();
+
+        store.insert("counter", &10u64.to_be_bytes()).unwrap();
+        store.flush().unwrap();
+
+        store.merge("counter", &1u64.to_be_bytes()).unwrap();
+        store.merge("fresh", &5u64.to_be_bytes()).unwrap();
+        assert_eq!(store.get("counter").unwrap(), u64_value(11));
+        store.flush().unwrap();
+
+        store.merge("counter", &2u64.to_be_bytes()).unwrap();
+        store.merge("counter", &3u64.to_be_bytes()).unwrap();
+        assert_eq!(store.get("counter").unwrap(), u64_value(16));
+        assert_eq!(store.get("fresh").unwrap(), u64_value(5));
+
+        let range: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+        assert_eq!(
+            range,
+            vec![
+                ("counter".to_owned(), u64_value(16).unwrap()),
+                ("fresh".to_owned(), u64_value(5).unwrap()),
+            ]
+        );
+
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
+        drop(store);
+
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+        assert_eq!(store.get("counter").unwrap(), u64_value(16));
+        assert_eq!(store.get("fresh").unwrap(), u64_value(5));
+    }
+
+    #[test]
+    fn test_flushed_merge_operands_are_not_replayed_from_untruncated_wal() {
+        let dir = PathBuf::from("test_flushed_merge_operands_are_not_replayed_from_untruncated_wal");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::U64AddOperator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options.clone()).unwrap();
+
+        store.insert("counter", &10u64.to_be_bytes()).unwrap();
+        store.flush().unwrap();
+        store.merge("counter", &1u64.to_be_bytes()).unwrap();
+
+        // Flushes the memtable the way a flush does, but stops short of truncating the WAL, as a
+        // crash right after writing the SST would.
+        {
+            let mut memtable = store.default_family.memtable.lock().unwrap();
+            let wal_sequence = store.wal().unwrap().last_sequence();
+
+            store.default_family.lsm_tree.write_flushed_sstable(memtable.iter(), wal_sequence).unwrap();
+            memtable.clear();
+            store.default_family.memtable_size.store(0, Ordering::Relaxed);
+        }
+
+        drop(store);
+
+        // A record logged after the flush is still replayed.
+        let mut wal = Wal::new(&dir, None).unwrap();
+        assert_eq!(wal.records().unwrap().count(), 1);
+        wal.log_one(DEFAULT_COLUMN_FAMILY, "counter", &Entry::Merge(vec![2u64.to_be_bytes().to_vec()])).unwrap();
+        drop(wal);
+
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+        assert_eq!(store.get("counter").unwrap(), u64_value(13));
+        drop(store);
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_can_retrive_bounded_range() {
-        let path: PathBuf = "test_can_retrive_bounded_range".into();
-        if path.exists() {
-            std::fs::remove_dir_all(path.clone()).unwrap();
-        }
+    fn test_merge_fails_without_merge_operator() {
+        let dir = PathBuf::from("test_merge_fails_without_merge_operator");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
 
-        let store = make_store(path).unwrap();
-        for i in 0..1024 {
-            store
-                .insert(
-                    &format!("key_{:04}", i),
-                    &format!("value_{:04}", i).bytes().collect::<Vec<u8>>()
-                )
-                .unwrap();
-        }
+        let store = make_store(dir.clone()).unwrap();
 
-        let expected: Vec<_> = (100..1000)
-            .map(|i|
-                (format!("key_{:04}", i), format!("value_{:04}", i)
-                    .bytes()
-                    .collect::<Vec<_>>()))
-            .collect();
+        let err = store.merge("key", b"operand").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(store.get("key").unwrap(), None);
+    }
 
-        fn assert(store: &impl Store, expected: &Vec<(String, Vec<u8>)>) {
-            let actual: Vec<_> = store.get_range((Included("key_0100"), Excluded("key_1000")))
-                .unwrap()
-                .map(Result::unwrap)
-                .collect();
+    #[test]
+    fn test_compare_and_swap_replaces_only_expected_value() {
+        let dir = PathBuf::from("test_compare_and_swap_replaces_only_expected_value");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
 
-            assert_eq!(&actual, expected);
+        let store = make_store(dir.clone()).unwrap();
 
-        }
+        assert!(store.insert_if_absent("key", b"v1").unwrap());
+        assert!(!store.insert_if_absent("key", b"v2").unwrap());
+        store.flush().unwrap();
 
-        assert(&store, &expected);
+        assert!(!store.compare_and_swap("key", Some(b"v2"), Some(b"v3")).unwrap());
+        assert!(store.compare_and_swap("key", Some(b"v1"), Some(b"v3")).unwrap());
+        assert_eq!(store.get("key").unwrap(), Some(b"v3".to_vec()));
 
-        store.flush().unwrap();
-        assert(&store, &expected);
+        // Deleting shadows the flushed value, including across compaction.
+        assert!(store.compare_and_swap("key", Some(b"v3"), None).unwrap());
+        assert_eq!(store.get("key").unwrap(), None);
+        store.compact_range(.., 1).unwrap();
+        assert_eq!(store.get("key").unwrap(), None);
+        assert!(store.get_range(..).unwrap().next().is_none());
 
+        assert!(store.compare_and_swap("key", None, Some(b"v4")).unwrap());
+        assert_eq!(store.get("key").unwrap(), Some(b"v4".to_vec()));
     }
 
     #[test]
-    fn test_can_retrieve_range_across_memtable_and_lsm_tree() {
-        let dir = PathBuf::from("test_can_retrieve_range_across_memtable_and_lsm_tree");
+    fn test_concurrent_insert_if_absent_has_single_winner() {
+        let dir = PathBuf::from("test_concurrent_insert_if_absent_has_single_winner");
         let _ = fs::remove_dir_all(&dir);
         fs::create_dir_all(&dir).unwrap();
 
         let store = make_store(dir.clone()).unwrap();
-        store.insert("foo", "bar".as_bytes()).unwrap();
-        store.insert("foo2", "bar2".as_bytes()).unwrap();
 
-        // Dropping the store flushes the memtable to the LSM tree
-        drop(store);
+        let winners = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let store = &store;
+                    scope.spawn(move || {
+                        store.insert_if_absent("lock", format!("owner{i}").as_bytes()).unwrap()
+                    })
+                })
+                .collect();
 
-        let store = make_store(dir.clone()).unwrap();
+            handles.into_iter().map(|it| it.join().unwrap()).filter(|won| *won).count()
+        });
 
-        // These keys should be in the memtable
-        store.insert("foo3", "bar3".as_bytes()).unwrap();
-        store.insert("foo4", "bar4".as_bytes()).unwrap();
+        assert_eq!(winners, 1);
+    }
 
-        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+    #[test]
+    fn test_column_families_are_isolated_and_persist() {
+        let dir = PathBuf::from("test_column_families_are_isolated_and_persist");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
 
+        let counters = || Options {
+            merge_operator: Some(Arc::new(crate::U64AddOperator)),
+            ..Options::default()
+        };
+
+        let open = || {
+            let families = BTreeMap::from([("counters".to_owned(), counters())]);
+            make_store_with_column_families(dir.clone(), Options::default(), families).unwrap()
+        };
+
+        let store = open();
+        let users = store.create_column_family("users", Options::default()).unwrap();
         assert_eq!(
-            actual,
-            vec![
-                ("foo".to_owned(), "bar".as_bytes().to_vec()),
-                ("foo2".to_owned(), "bar2".as_bytes().to_vec()),
-                ("foo3".to_owned(), "bar3".as_bytes().to_vec()),
-                ("foo4".to_owned(), "bar4".as_bytes().to_vec()),
-            ]
+            store.create_column_family("users", Options::default()).err().map(|it| it.kind()),
+            Some(io::ErrorKind::AlreadyExists)
+        );
+
+        store.insert("key", b"default").unwrap();
+        users.insert("key", b"users").unwrap();
+        store.column_family("counters").unwrap().merge("key", &2u64.to_be_bytes()).unwrap();
+
+        // Only the counters family has a merge operator.
+        assert!(store.merge("key", &1u64.to_be_bytes()).is_err());
+        assert!(users.merge("key", &1u64.to_be_bytes()).is_err());
+
+        users.flush().unwrap();
+        store.column_family("counters").unwrap().merge("key", &3u64.to_be_bytes()).unwrap();
+        drop(users);
+        drop(store);
+
+        // Reopening replays the WAL into the right family, "users" is opened with default options.
+        let store = open();
+        assert_eq!(
+            store.column_family_names(),
+            vec!["default".to_owned(), "counters".to_owned(), "users".to_owned()]
         );
+
+        let users = store.column_family("users").unwrap();
+        let counters = store.column_family("counters").unwrap();
+        assert!(store.column_family("missing").is_none());
+
+        assert_eq!(store.get("key").unwrap(), Some(b"default".to_vec()));
+        assert_eq!(users.get("key").unwrap(), Some(b"users".to_vec()));
+        assert_eq!(counters.get("key").unwrap(), u64_value(5));
+
+        users.compact_range(.., 1).unwrap();
+
+        let sstables = store.manifest.get_sstables();
+        assert_eq!(sstables.iter().filter(|it| it.level == 1).count(), 1);
+        assert_eq!(sstables.iter().filter(|it| it.level == 0).count(), 3);
+
+        let entries: Vec<_> = users.get_range(..).unwrap().map(|it| it.unwrap()).collect();
+        assert_eq!(entries, vec![("key".to_owned(), b"users".to_vec())]);
     }
 
     #[test]
-    fn test_can_retrieve_range_across_memtable_and_multiple_sstables() {
-        let dir = PathBuf::from("test_can_retrieve_range_across_memtable_and_multiple_sstables");
+    fn test_write_batch_spans_column_families() {
+        let dir = PathBuf::from("test_write_batch_spans_column_families");
         let _ = fs::remove_dir_all(&dir);
         fs::create_dir_all(&dir).unwrap();
 
         let store = make_store(dir.clone()).unwrap();
-        store.insert("sst1:foo0", "bar0".as_bytes()).unwrap();
-        store.insert("sst1:foo1", "bar1".as_bytes()).unwrap();
-        store.insert("sst1:foo2", "bar2".as_bytes()).unwrap();
+        store.create_column_family("index", Options::default()).unwrap();
+        store.insert("stale", b"value").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.insert("user1", b"alice");
+        batch.insert_cf("index", "alice", b"user1");
+        batch.delete("stale");
+        store.write(&batch).unwrap();
+
+        // A batch naming an unknown family is rejected as a whole.
+        let mut batch = WriteBatch::new();
+        batch.insert("user2", b"bob");
+        batch.insert_cf("missing", "bob", b"user2");
+        assert_eq!(store.write(&batch).unwrap_err().kind(), io::ErrorKind::NotFound);
         drop(store);
 
         let store = make_store(dir.clone()).unwrap();
-        store.insert("sst2:foo0", "bar0".as_bytes()).unwrap();
-        store.insert("sst2:foo1", "bar1".as_bytes()).unwrap();
-        store.insert("sst2:foo2", "bar2".as_bytes()).unwrap();
-        drop(store);
+        let index = store.column_family("index").unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("sst3:foo0", "bar0".as_bytes()).unwrap();
-        store.insert("sst3:foo1", "bar1".as_bytes()).unwrap();
-        store.insert("sst3:foo2", "bar2".as_bytes()).unwrap();
-        drop(store);
+        assert_eq!(store.get("user1").unwrap(), Some(b"alice".to_vec()));
+        assert_eq!(store.get("user2").unwrap(), None);
+        assert_eq!(store.get("stale").unwrap(), None);
+        assert_eq!(index.get("alice").unwrap(), Some(b"user1".to_vec()));
+        assert_eq!(index.get("bob").unwrap(), None);
+    }
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("memtable:foo0", "bar0".as_bytes()).unwrap();
-        store.insert("memtable:foo1", "bar1".as_bytes()).unwrap();
-        store.insert("memtable:foo2", "bar2".as_bytes()).unwrap();
-        // this should be the last entry
-        store.insert("z:memtable:foo2", "bar2".as_bytes()).unwrap();
-        drop(store);
+    #[test]
+    fn test_keys_are_ordered_by_comparator_checked_on_open() {
+        let dir = PathBuf::from("test_keys_are_ordered_by_comparator_checked_on_open");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
-        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+        let reverse = || Options {
+            comparator: Some(Arc::new(crate::ReverseBytewiseComparator)),
+            ..Options::default()
+        };
 
+        let store = make_store_with_options(dir.clone(), reverse()).unwrap();
+
+        for i in [1, 3, 5] {
+            store.insert(&format!("key{i}"), b"sst").unwrap();
+        }
+        store.flush().unwrap();
+
+        for i in [2, 4] {
+            store.insert(&format!("key{i}"), b"memtable").unwrap();
+        }
+
+        let keys = |store: &DefaultStore, range: (std::ops::Bound<&str>, std::ops::Bound<&str>)| {
+            store
+                .get_range(range)
+                .unwrap()
+                .map(|it| it.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys(&store, (Unbounded, Unbounded)), vec!["key5", "key4", "key3", "key2", "key1"]);
+        assert_eq!(keys(&store, (Included("key4"), Excluded("key2"))), vec!["key4", "key3"]);
+        assert!(keys(&store, (Included("key2"), Included("key4"))).is_empty());
+
+        store.compact_range(.., 1).unwrap();
+        assert_eq!(keys(&store, (Excluded("key5"), Unbounded)), vec!["key4", "key3", "key2", "key1"]);
+        assert_eq!(store.get("key3").unwrap(), Some(b"sst".to_vec()));
+
+        let numeric = Options {
+            comparator: Some(Arc::new(crate::NumericSuffixComparator)),
+            ..Options::default()
+        };
         assert_eq!(
-            actual,
-            vec![
-                ("memtable:foo0".to_owned(), "bar0".as_bytes().to_vec()),
-                ("memtable:foo1".to_owned(), "bar1".as_bytes().to_vec()),
-                ("memtable:foo2".to_owned(), "bar2".as_bytes().to_vec()),
-                ("sst1:foo0".to_owned(), "bar0".as_bytes().to_vec()),
-                ("sst1:foo1".to_owned(), "bar1".as_bytes().to_vec()),
-                ("sst1:foo2".to_owned(), "bar2".as_bytes().to_vec()),
-                ("sst2:foo0".to_owned(), "bar0".as_bytes().to_vec()),
-                ("sst2:foo1".to_owned(), "bar1".as_bytes().to_vec()),
-                ("sst2:foo2".to_owned(), "bar2".as_bytes().to_vec()),
-                ("sst3:foo0".to_owned(), "bar0".as_bytes().to_vec()),
-                ("sst3:foo1".to_owned(), "bar1".as_bytes().to_vec()),
-                ("sst3:foo2".to_owned(), "bar2".as_bytes().to_vec()),
-                ("z:memtable:foo2".to_owned(), "bar2".as_bytes().to_vec()),
-            ]
+            store.create_column_family("numeric", numeric.clone()).err().map(|it| it.kind()),
+            Some(io::ErrorKind::InvalidInput)
         );
+        drop(store);
+
+        for options in [Options::default(), numeric] {
+            let err = make_store_with_options(dir.clone(), options).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let store = make_store_with_options(dir.clone(), reverse()).unwrap();
+        assert_eq!(keys(&store, (Unbounded, Included("key4"))), vec!["key5", "key4"]);
     }
 
     #[test]
-    fn test_range_reads_memtable_entreies_override_sstable_entreies() {
-        let dir = PathBuf::from("test_range_reads_memtable_entreies_override_sstable_entreies");
+    fn test_iterator_seeks_and_moves_both_ways_across_levels() {
+        let dir = PathBuf::from("test_iterator_seeks_and_moves_both_ways_across_levels");
         let _ = fs::remove_dir_all(&dir);
         fs::create_dir_all(&dir).unwrap();
 
         let store = make_store(dir.clone()).unwrap();
-        store.insert("foo", b"bar").unwrap();
-        drop(store);
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo", b"bar2").unwrap();
+        for i in 0..20 {
+            store.insert(&format!("key_{i:02}"), b"old").unwrap();
+        }
+        store.compact_range(.., crate::MAX_LEVEL).unwrap();
 
-        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+        for i in (0..20).step_by(2) {
+            store.insert(&format!("key_{i:02}"), b"new").unwrap();
+        }
+        store.flush().unwrap();
 
-        assert_eq!(actual, vec![("foo".to_string(), b"bar2".to_vec())]);
+        // Deleted in the memtable, shadowing both SSTables
+        assert!(store.compare_and_swap("key_10", Some(b"new"), None).unwrap());
+        store.insert("key_20", b"memtable").unwrap();
+
+        let mut iter = store.iter().unwrap();
+        assert!(!iter.valid());
+
+        let mut forward = Vec::new();
+        iter.seek_to_first().unwrap();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            forward.push((key.to_owned(), value.to_vec()));
+            iter.next().unwrap();
+        }
+
+        let mut backward = Vec::new();
+        iter.seek_to_last().unwrap();
+        while let (Some(key), Some(value)) = (iter.key(), iter.value()) {
+            backward.push((key.to_owned(), value.to_vec()));
+            iter.prev().unwrap();
+        }
+        backward.reverse();
+
+        let expected: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+        assert_eq!(expected.len(), 20);
+        assert_eq!(forward, expected);
+        assert_eq!(backward, expected);
+
+        iter.seek("key_09").unwrap();
+        assert_eq!(iter.key(), Some("key_09"));
+        assert_eq!(iter.value(), Some(&b"old"[..]));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), Some("key_11"));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), Some("key_09"));
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), Some("key_08"));
+        assert_eq!(iter.value(), Some(&b"new"[..]));
+        iter.next().unwrap();
+        assert_eq!(iter.key(), Some("key_09"));
+
+        iter.seek_for_prev("key_10").unwrap();
+        assert_eq!(iter.key(), Some("key_09"));
+        iter.seek_for_prev("key_205").unwrap();
+        assert_eq!(iter.key(), Some("key_20"));
+        iter.next().unwrap();
+        assert!(!iter.valid());
+        iter.seek_for_prev("a").unwrap();
+        assert!(!iter.valid());
+
+        // Writes made after the iterator was created are not seen
+        store.insert("key_21", b"late").unwrap();
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.key(), Some("key_20"));
     }
 
     #[test]
-    fn test_range_reads_drop_duplicates() {
-        let dir = PathBuf::from("test_range_reads_drop_duplicates");
+    fn test_scan_prefix_returns_only_keys_with_prefix() {
+        let dir = PathBuf::from("test_scan_prefix_returns_only_keys_with_prefix");
         let _ = fs::remove_dir_all(&dir);
         fs::create_dir_all(&dir).unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo0", "wrong".as_bytes()).unwrap();
-        store.insert("foo2", "right".as_bytes()).unwrap();
-        drop(store);
+        let options = Options {
+            prefix_extractor: Some(Arc::new(crate::DelimitedPrefix::new(':', 2))),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo0", "wrong2".as_bytes()).unwrap();
-        store.insert("foo3", "wrong3".as_bytes()).unwrap();
-        drop(store);
+        for user in 0..20 {
+            for field in ["email", "name"] {
+                store.insert(&format!("user:{user}:{field}"), field.as_bytes()).unwrap();
+            }
+        }
+        store.flush().unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo0", "right".as_bytes()).unwrap();
-        store.insert("foo4", "right".as_bytes()).unwrap();
-        drop(store);
+        store.insert("user:1:phone", b"phone").unwrap();
+        assert!(store.compare_and_swap("user:1:email", Some(b"email"), None).unwrap());
 
-        let store = make_store(dir.clone()).unwrap();
-        store.insert("foo3", "right".as_bytes()).unwrap();
+        let keys = |store: &DefaultStore, prefix: &str| {
+            store
+                .scan_prefix(prefix)
+                .unwrap()
+                .map(|it| it.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(keys(&store, "user:1:"), vec!["user:1:name", "user:1:phone"]);
+        let user_1x: Vec<_> = (10..20)
+            .flat_map(|it| [format!("user:{it}:email"), format!("user:{it}:name")])
+            .chain(["user:1:name".to_owned(), "user:1:phone".to_owned()])
+            .collect();
+        assert_eq!(keys(&store, "user:1"), user_1x);
+        assert_eq!(keys(&store, "user:7:e"), vec!["user:7:email"]);
+        assert!(keys(&store, "user:99:").is_empty());
+        assert_eq!(keys(&store, "").len(), 40);
 
-        let actual: Vec<_> = store.get_range(..).unwrap().map(Result::unwrap).collect();
+        // Keys sharing a prefix are not next to each other under this comparator
+        let dir = dir.join("numeric");
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            comparator: Some(Arc::new(crate::NumericSuffixComparator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir, options).unwrap();
+
+        for key in ["item1", "item2", "item10", "item20"] {
+            store.insert(key, b"value").unwrap();
+        }
+
+        assert_eq!(keys(&store, "item1"), vec!["item1", "item10"]);
+    }
+
+    #[test]
+    fn test_multi_get_returns_values_in_input_order() {
+        let dir = PathBuf::from("test_multi_get_returns_values_in_input_order");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::U64AddOperator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
+
+        for i in 0..100 {
+            store.insert(&format!("key_{i:02}"), &(i as u64).to_be_bytes()).unwrap();
+        }
+        store.flush().unwrap();
 
+        store.merge("key_01", &10u64.to_be_bytes()).unwrap();
+        store.insert("key_02", &20u64.to_be_bytes()).unwrap();
+        assert!(store.compare_and_swap("key_03", Some(&3u64.to_be_bytes()), None).unwrap());
+        store.insert_with_ttl("key_04", b"expired", Duration::ZERO).unwrap();
+
+        let keys = ["key_99", "key_01", "key_02", "key_03", "key_04", "missing", "key_99"];
         assert_eq!(
-            actual,
+            store.multi_get(&keys).unwrap(),
             vec![
-                ("foo0".to_owned(), "right".as_bytes().to_vec()),
-                ("foo2".to_owned(), "right".as_bytes().to_vec()),
-                ("foo3".to_owned(), "right".as_bytes().to_vec()),
-                ("foo4".to_owned(), "right".as_bytes().to_vec()),
+                u64_value(99),
+                u64_value(11),
+                u64_value(20),
+                None,
+                None,
+                None,
+                u64_value(99),
             ]
         );
+
+        for key in keys {
+            assert_eq!(store.multi_get(&[key]).unwrap(), vec![store.get(key).unwrap()]);
+        }
+
+        assert!(store.multi_get(&[]).unwrap().is_empty());
     }
 
     #[test]
-    fn test_large_entires_can_be_inserted() {
-        let dir = PathBuf::from("test_large_keys_can_be_inserted");
+    fn test_keys_and_counts_skip_dead_entries_and_respect_bounds() {
+        let dir = PathBuf::from("test_keys_and_counts_skip_dead_entries_and_respect_bounds");
         let _ = fs::remove_dir_all(&dir);
         fs::create_dir_all(&dir).unwrap();
 
-        let store = make_store(dir.clone()).unwrap();
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::AppendOperator)),
+            ..Options::default()
+        };
+        let store = make_store_with_options(dir.clone(), options).unwrap();
 
-        const K: i32 = 1024;
+        let large_value = vec![b'v'; 16 * 1024];
+        for i in 0..50 {
+            store.insert(&format!("key_{i:02}"), &large_value).unwrap();
+        }
+        store.flush().unwrap();
 
-        let size_1k: String = (0..K).map(|_| 'a'.to_string()).collect();
-        let size_2k: String = (0..K*2).map(|_| 'a'.to_string()).collect();
-        let size_3k: String = (0..K*3).map(|_| 'a'.to_string()).collect();
-        let size_4k: String = (0..K*4).map(|_| 'a'.to_string()).collect();
-        let size_5k: String = (0..K*5).map(|_| 'a'.to_string()).collect();
+        // Deleted, expired, merged into and added in the memtable
+        assert!(store.compare_and_swap("key_10", Some(&large_value), None).unwrap());
+        store.insert_with_ttl("key_11", b"expired", Duration::ZERO).unwrap();
+        store.merge("key_12", b"+").unwrap();
+        store.merge("key_50", b"+").unwrap();
 
-        store.insert(&size_1k, &size_1k.as_bytes()).unwrap();
-        store.insert(&size_2k, &size_2k.as_bytes()).unwrap();
-        store.insert(&size_3k, &size_3k.as_bytes()).unwrap();
-        store.insert(&size_4k, &size_4k.as_bytes()).unwrap();
-        store.insert(&size_5k, &size_5k.as_bytes()).unwrap();
+        let keys = |range: (std::ops::Bound<&str>, std::ops::Bound<&str>), options: &RangeOptions| {
+            store
+                .get_keys(range, options)
+                .unwrap()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+
+        let all = RangeOptions::default();
+        let expected: Vec<_> = store.get_range(..).unwrap().map(|it| it.unwrap().0).collect();
+        assert_eq!(expected.len(), 49);
+        assert_eq!(keys((Unbounded, Unbounded), &all), expected);
+        assert_eq!(store.count_range(..).unwrap(), 49);
+        assert_eq!(store.count_range((Included("key_10"), Excluded("key_20"))).unwrap(), 8);
+
+        let page = RangeOptions {
+            offset: 9,
+            limit: Some(3),
+        };
+        assert_eq!(keys((Unbounded, Unbounded), &page), vec!["key_09", "key_12", "key_13"]);
+
+        let values: Vec<_> = store
+            .get_range_with_options((Excluded("key_09"), Unbounded), &page)
+            .unwrap()
+            .map(|it| it.unwrap().0)
+            .collect();
+        assert_eq!(values, vec!["key_21", "key_22", "key_23"]);
+
+        let past_end = RangeOptions {
+            offset: 100,
+            limit: None,
+        };
+        assert!(keys((Unbounded, Unbounded), &past_end).is_empty());
+        assert!(keys((Unbounded, Unbounded), &RangeOptions { offset: 0, limit: Some(0) }).is_empty());
+    }
 
-        store.flush().unwrap();
+    #[test]
+    fn test_read_only_store_reads_while_open_for_writing() {
+        let dir = PathBuf::from("test_read_only_store_reads_while_open_for_writing");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
 
         assert_eq!(
-            Some(size_1k.as_bytes()),
-            store.get(&size_1k).unwrap().as_ref().map(|it| &it[..])
+            open_read_only(dir.clone()).err().map(|it| it.kind()),
+            Some(io::ErrorKind::NotFound)
         );
 
-        assert_eq!(
-            Some(size_2k.as_bytes()),
-            store.get(&size_2k).unwrap().as_ref().map(|it| &it[..])
-        );
+        let writer = make_store(dir.clone()).unwrap();
+        writer.insert("flushed", b"1").unwrap();
+        writer.flush().unwrap();
+        writer.insert("logged", b"2").unwrap();
 
-        assert_eq!(
-            Some(size_3k.as_bytes()),
-            store.get(&size_3k).unwrap().as_ref().map(|it| &it[..])
-        );
+        let reader = open_read_only(dir.clone()).unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.get("flushed").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(reader.get("logged").unwrap(), Some(b"2".to_vec()));
 
+        let denied = Some(io::ErrorKind::PermissionDenied);
+        assert_eq!(reader.insert("key", b"value").err().map(|it| it.kind()), denied);
+        assert_eq!(reader.flush().err().map(|it| it.kind()), denied);
+        assert_eq!(reader.compact_range(.., 1).err().map(|it| it.kind()), denied);
         assert_eq!(
-            Some(size_4k.as_bytes()),
-            store.get(&size_4k).unwrap().as_ref().map(|it| &it[..])
+            reader.create_column_family("users", Options::default()).err().map(|it| it.kind()),
+            denied
         );
 
-        assert_eq!(
-            Some(size_5k.as_bytes()),
-            store.get(&size_5k).unwrap().as_ref().map(|it| &it[..])
-        );
+        writer.insert("late", b"3").unwrap();
+        writer.flush().unwrap();
+        writer.create_column_family("users", Options::default()).unwrap().insert("key", b"4").unwrap();
+
+        assert_eq!(reader.get("late").unwrap(), None);
+        assert!(reader.column_family("users").is_none());
+
+        reader.try_catch_up().unwrap();
+        assert_eq!(reader.get("flushed").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(reader.get("logged").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(reader.get("late").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(reader.column_family("users").unwrap().get("key").unwrap(), Some(b"4".to_vec()));
+
+        // Dropping the reader leaves the writer's files alone.
+        drop(reader);
+        drop(writer);
+
+        let store = make_store(dir.clone()).unwrap();
+        assert_eq!(store.get("late").unwrap(), Some(b"3".to_vec()));
+        drop(store);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_only_store_skips_merge_operands_flushed_after_reading_wal() {
+        let dir = PathBuf::from("test_read_only_store_skips_merge_operands_flushed_after_reading_wal");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = Options {
+            merge_operator: Some(Arc::new(crate::U64AddOperator)),
+            ..Options::default()
+        };
+
+        let writer = make_store_with_options(dir.clone(), options.clone()).unwrap();
+        writer.insert("counter", &10u64.to_be_bytes()).unwrap();
+        writer.flush().unwrap();
+        writer.merge("counter", &1u64.to_be_bytes()).unwrap();
+        writer.merge("counter", &2u64.to_be_bytes()).unwrap();
+
+        // The reader reads the WAL, then the writer flushes before the reader reads the manifest.
+        let records = read_wal_records(&dir).unwrap();
+        writer.flush().unwrap();
+
+        let reader = StoreImpl::open_read_only(dir.clone(), options).unwrap();
+        reader.load_memtables(records).unwrap();
+        assert_eq!(reader.get("counter").unwrap(), u64_value(13));
+
+        writer.merge("counter", &4u64.to_be_bytes()).unwrap();
+        reader.try_catch_up().unwrap();
+        assert_eq!(reader.get("counter").unwrap(), u64_value(17));
+
+        drop(reader);
+        drop(writer);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
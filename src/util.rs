@@ -1,60 +1,64 @@
 use std::cmp::{Ordering, Reverse};
 use std::collections::BinaryHeap;
+use std::io;
 
-use crate::store::Cursor;
+use crate::comparator::Comparator;
 
 /// A wrapper around a key-value pair that implements Ord, PartialOrd, Eq, and PartialEq
-/// based only on the key.
-struct KeyOnlyOrd((String, Vec<u8>));
+/// based only on the key, as ordered by the comparator.
+pub(crate) struct KeyOnlyOrd<'c, V>(pub(crate) (String, V), pub(crate) &'c dyn Comparator);
 
-impl PartialOrd for KeyOnlyOrd {
+impl<V> PartialOrd for KeyOnlyOrd<'_, V> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.0.0.cmp(&other.0.0))
+        Some(self.cmp(other))
     }
 }
 
-impl Ord for KeyOnlyOrd {
+impl<V> Ord for KeyOnlyOrd<'_, V> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.0.0.cmp(&other.0.0)
+        self.1.compare(&self.0.0, &other.0.0)
     }
 }
 
-impl PartialEq for KeyOnlyOrd {
+impl<V> PartialEq for KeyOnlyOrd<'_, V> {
     fn eq(&self, other: &Self) -> bool {
-        self.0.0 == other.0.0
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-impl Eq for KeyOnlyOrd {}
+impl<V> Eq for KeyOnlyOrd<'_, V> {}
 
-/// Merges multiple sorted iterators into a single sorted iterator, removing duplicates.
-/// The iterators must be sorted.
+/// Merges multiple sorted iterators into a single sorted iterator, grouping duplicates.
+/// The iterators must be sorted by `comparator` and must not contain duplicates themselves.
 ///
-/// When a duplicate is encountered, the one that appears in the earliest source is kept.
+/// Every key is yielded once along with its values from all sources that have it, in the order of
+/// the sources.
 ///
 /// # Example
 /// ```ignore
-/// let iter1 = vec![1, 2, 3].into_iter();
-/// let iter2 = vec![2, 3, 4].into_iter();
-/// let iter3 = vec![3, 4, 5].into_iter();
+/// let iter1 = vec![("a", 1), ("b", 1)].into_iter();
+/// let iter2 = vec![("b", 2), ("c", 2)].into_iter();
 ///
-/// let merged = merge_sorted_uniq(vec![iter1, iter2, iter3]);
+/// let merged = merge_sorted_grouped_cursor(vec![iter1, iter2], &BytewiseComparator);
 ///
-/// assert_eq!(merged.collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+/// assert_eq!(merged.collect::<Vec<_>>(), vec![("a", vec![1]), ("b", vec![1, 2]), ("c", vec![2])]);
 /// ```
-pub(crate) fn merge_sorted_uniq_cursor<I>(mut sources: Vec<I>) -> impl Cursor 
+pub(crate) fn merge_sorted_grouped_cursor<'c, V, I>(
+    mut sources: Vec<I>,
+    comparator: &'c dyn Comparator,
+) -> impl Iterator<Item = io::Result<(String, Vec<V>)>> + 'c
 where
-    I: Cursor
+    V: 'c,
+    I: Iterator<Item = io::Result<(String, V)>> + 'c,
 {
     let mut heap = BinaryHeap::new();
     let mut error = None;
-    let mut last: Option<KeyOnlyOrd> = None;
     let mut end = false;
 
     for (idx, source) in sources.iter_mut().enumerate() {
         match source.next() {
             Some(Ok(item)) => {
-                heap.push(Reverse((KeyOnlyOrd(item), idx)))
+                heap.push(Reverse((KeyOnlyOrd(item, comparator), idx)))
             },
             Some(Err(e)) => {
                 error = Some(e)
@@ -73,31 +77,35 @@ where
             return Some(Err(error));
         };
 
-        while let Some(Reverse((item, idx))) = heap.pop() {
-            let next: Option<I::Item> = sources[idx].next();
+        let mut group: Option<(String, Vec<V>)> = None;
 
-            match next {
-                Some(Ok(next)) => heap.push(Reverse((KeyOnlyOrd(next), idx))),
-                Some(e) => {
+        // Ties on the key are broken by the source index, so values of a key are popped in the
+        // order of their sources.
+        while let Some(Reverse((KeyOnlyOrd((key, _), _), _))) = heap.peek() {
+            if group.as_ref().is_some_and(|(group_key, _)| comparator.compare(group_key, key).is_ne()) {
+                break;
+            }
+
+            let Some(Reverse((KeyOnlyOrd((key, value), _), idx))) = heap.pop() else {
+                unreachable!();
+            };
+
+            match sources[idx].next() {
+                Some(Ok(next)) => heap.push(Reverse((KeyOnlyOrd(next, comparator), idx))),
+                Some(Err(e)) => {
                     end = true;
-                    return Some(e);
+                    return Some(Err(e));
                 }
                 None => {},
             }
 
-            if last.as_ref() == Some(&item) {
-                continue;
-            }
-
-            match last.replace(item) {
-                None => continue,
-                Some(KeyOnlyOrd(prev)) => {
-                    return Some(Ok(prev));
-                }
+            match group.as_mut() {
+                Some((_, values)) => values.push(value),
+                None => group = Some((key, vec![value])),
             }
         }
 
-        last.take().map(|KeyOnlyOrd(it)| Ok(it))
+        group.map(Ok)
     })
 }
 
@@ -105,10 +113,16 @@ where
 mod tests {
     use super::*;
 
+    use crate::comparator::{BytewiseComparator, ReverseBytewiseComparator};
+
     fn p(n: i32) -> (String, Vec<u8>) {
         (format!("p{}", n), b"".to_vec())
     }
 
+    fn g(n: i32, count: usize) -> (String, Vec<Vec<u8>>) {
+        (format!("p{}", n), vec![b"".to_vec(); count])
+    }
+
     #[test]
     fn test_merge_sorted() {
 
@@ -116,25 +130,40 @@ mod tests {
         let v2 = vec![Ok(p(2)), Ok(p(5)), Ok(p(8))];
         let v3 = vec![Ok(p(2)), Ok(p(3)), Ok(p(6)), Ok(p(9))];
 
-        let merged: Vec<_> = merge_sorted_uniq_cursor(vec![v1.into_iter(), v2.into_iter(), v3.into_iter()])
+        let merged: Vec<_> = merge_sorted_grouped_cursor(vec![v1.into_iter(), v2.into_iter(), v3.into_iter()], &BytewiseComparator)
             .map(|it| it.unwrap())
             .collect();
 
-        assert_eq!(merged, vec![p(1), p(2), p(3), p(4), p(5), p(6), p(7), p(8), p(9)]);
+        assert_eq!(
+            merged,
+            vec![g(1, 1), g(2, 2), g(3, 1), g(4, 1), g(5, 1), g(6, 1), g(7, 1), g(8, 1), g(9, 1)]
+        );
     }
 
     #[test]
-    fn test_duplicates_are_dropped() {
+    fn test_duplicates_are_grouped_in_source_order() {
         let v1 = vec![Ok(("foo".to_owned(), b"bar".to_vec()))]
             .into_iter();
 
         let v2 = vec![Ok(("foo".to_owned(), b"bar2".to_vec()))]
             .into_iter();
 
-        let merged: Vec<_> = merge_sorted_uniq_cursor(vec![v2, v1])
+        let merged: Vec<_> = merge_sorted_grouped_cursor(vec![v2, v1], &BytewiseComparator)
+            .map(|it| it.unwrap())
+            .collect();
+
+        assert_eq!(merged, vec![("foo".to_owned(), vec![b"bar2".to_vec(), b"bar".to_vec()])]);
+    }
+
+    #[test]
+    fn test_merge_follows_comparator() {
+        let v1 = vec![Ok(p(7)), Ok(p(4)), Ok(p(1))];
+        let v2 = vec![Ok(p(8)), Ok(p(4))];
+
+        let merged: Vec<_> = merge_sorted_grouped_cursor(vec![v1.into_iter(), v2.into_iter()], &ReverseBytewiseComparator)
             .map(|it| it.unwrap())
             .collect();
 
-        assert_eq!(merged, vec![("foo".to_owned(), b"bar2".to_vec())]);
+        assert_eq!(merged, vec![g(8, 1), g(7, 1), g(4, 2), g(1, 1)]);
     }
 }
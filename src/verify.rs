@@ -0,0 +1,354 @@
+//! Offline consistency checks of a store directory.
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::blob::{blob_file_path, parse_blob_filename};
+use crate::comparator::{BytewiseComparator, Comparator, builtin_comparator};
+use crate::entry::Entry;
+use crate::manifest::{SSTableDesc, is_manifest_filename, read_active_manifest};
+use crate::sstable::reader::{RawSSTableReader, SSTChunkIterator};
+use crate::sstable::{is_sst_tmp_filename, parse_sst_filename, sst_file_path};
+use crate::wal;
+
+/// Findings of [`verify`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// File name of the active manifest and the number of valid entries in it.
+    pub manifest: Option<(String, usize)>,
+
+    pub sstable_count: usize,
+    pub blob_file_count: usize,
+
+    /// Number of records in the WAL.
+    pub wal_record_count: usize,
+
+    /// Store files that nothing refers to, which the store removes when it is opened.
+    pub orphans: Vec<String>,
+
+    /// Broken invariants, each naming the file it was found in.
+    pub problems: Vec<String>,
+
+    /// Checks that couldn't be made.
+    pub skipped: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Returns true if no invariant is broken. Orphaned files are not a problem.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.manifest {
+            Some((name, entry_count)) => writeln!(f, "manifest: {name} ({entry_count} entries)")?,
+            None => writeln!(f, "manifest: unreadable")?,
+        }
+
+        writeln!(f, "sstables: {}", self.sstable_count)?;
+        writeln!(f, "blob files: {}", self.blob_file_count)?;
+        writeln!(f, "wal records: {}", self.wal_record_count)?;
+
+        writeln!(f, "orphans: {}", self.orphans.len())?;
+        for orphan in self.orphans.iter() {
+            writeln!(f, "  {orphan}")?;
+        }
+
+        for skipped in self.skipped.iter() {
+            writeln!(f, "skipped: {skipped}")?;
+        }
+
+        writeln!(f, "problems: {}", self.problems.len())?;
+        for problem in self.problems.iter() {
+            writeln!(f, "  {problem}")?;
+        }
+
+        write!(f, "result: {}", if self.is_ok() { "ok" } else { "corrupted" })
+    }
+}
+
+/// Checks the invariants of the store in `directory` without opening it, so the store may be
+/// open elsewhere but nothing is repaired or removed.
+///
+/// Manifest entries must all be valid, every SST it records must exist with a valid header,
+/// footer and chunk directory, chunks must be sorted without overlapping, and keys must be sorted
+/// within the key range the manifest records. Blob files must exist and the WAL must parse.
+///
+/// Keys are checked against the comparator recorded in the manifest, which must be a built-in
+/// one for their order to be checked. Only failing to list the directory is an error, everything
+/// else ends up in the report.
+pub fn verify(directory: impl AsRef<Path>) -> io::Result<VerifyReport> {
+    let directory = directory.as_ref();
+    let mut report = VerifyReport::default();
+
+    let (manifest_name, state) = match read_active_manifest(directory) {
+        Ok((path, state)) => {
+            let name = file_name(&path);
+
+            match fs::metadata(&path) {
+                Ok(metadata) if metadata.len() > state.valid_len => report.problems.push(format!(
+                    "{name}: {} bytes after the last valid entry, which is torn or corrupted",
+                    metadata.len() - state.valid_len
+                )),
+                Ok(_) => {}
+                Err(e) => report.problems.push(format!("{name}: {e}")),
+            }
+
+            report.manifest = Some((name.clone(), state.entry_count));
+            (Some(name), Some(state))
+        }
+
+        Err(e) => {
+            report.problems.push(format!("manifest: {e}"));
+            (None, None)
+        }
+    };
+
+    let mut live_sstables = HashSet::new();
+    let mut live_blob_files = HashSet::new();
+
+    if let Some(state) = state {
+        let comparator_name = state.comparator.as_deref().unwrap_or(BytewiseComparator.name());
+        let comparator = builtin_comparator(comparator_name);
+
+        if comparator.is_none() {
+            report.skipped.push(format!(
+                "key order, since comparator {comparator_name:?} is not built in"
+            ));
+        }
+
+        live_blob_files = state.blob_files.keys().copied().collect();
+
+        for desc in state.sstables.values() {
+            live_sstables.insert(desc.id);
+            report.sstable_count += 1;
+
+            let problems = verify_sstable(directory, desc, comparator.as_ref(), &live_blob_files);
+            report.problems.extend(problems);
+        }
+
+        for id in live_blob_files.iter() {
+            report.blob_file_count += 1;
+
+            let path = blob_file_path(directory, *id);
+            if let Err(e) = fs::metadata(&path) {
+                report.problems.push(format!("{}: {e}", file_name(&path)));
+            }
+        }
+    }
+
+    let wal_path = directory.join(wal::FILENAME);
+    if wal_path.exists() {
+        match wal::read_wal_file(&wal_path) {
+            Ok(records) => report.wal_record_count = records.len(),
+            Err(e) => report.problems.push(format!("{}: {e}", wal::FILENAME)),
+        }
+    }
+
+    for entry in fs::read_dir(directory)? {
+        let filename = entry?.file_name();
+        let Some(filename) = filename.to_str() else {
+            continue;
+        };
+
+        let is_orphan = match (parse_sst_filename(filename), parse_blob_filename(filename)) {
+            (Some(id), _) => !live_sstables.contains(&id),
+            (None, Some(id)) => !live_blob_files.contains(&id),
+            (None, None) => {
+                is_sst_tmp_filename(filename)
+                    || (is_manifest_filename(filename) && manifest_name.as_deref() != Some(filename))
+            }
+        };
+
+        if is_orphan {
+            report.orphans.push(filename.to_owned());
+        }
+    }
+
+    report.orphans.sort();
+
+    Ok(report)
+}
+
+/// Checks an SST recorded in the manifest, returning the problems found.
+fn verify_sstable(
+    directory: &Path,
+    desc: &SSTableDesc,
+    comparator: Option<&Arc<dyn Comparator>>,
+    live_blob_files: &HashSet<u64>,
+) -> Vec<String> {
+    let path = sst_file_path(directory, desc.id);
+    let name = file_name(&path);
+    let mut problems = Vec::new();
+
+    let chunk_descs = match RawSSTableReader::open(path.clone()).and_then(|mut it| it.list_chunks()) {
+        Ok(chunk_descs) => chunk_descs,
+        Err(e) => {
+            problems.push(format!("{name}: {e}"));
+            return problems;
+        }
+    };
+
+    let chunks = match SSTChunkIterator::open(path) {
+        Ok(chunks) => chunks,
+        Err(e) => {
+            problems.push(format!("{name}: {e}"));
+            return problems;
+        }
+    };
+
+    let mut entry_count = 0;
+    let mut last_key: Option<String> = None;
+
+    // Blob files referred to that the manifest doesn't know, with the number of references.
+    let mut missing_blob_files: BTreeMap<u64, u64> = BTreeMap::new();
+
+    for (chunk_desc, chunk) in chunk_descs.iter().zip(chunks) {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                problems.push(format!("{name}: chunk {}: {e}", chunk_desc.index));
+                return problems;
+            }
+        };
+
+        entry_count += chunk.len() as u64;
+
+        for (_, entry) in chunk.iter() {
+            if let Entry::Blob { blob, .. } = entry
+                && !live_blob_files.contains(&blob.file_id)
+            {
+                *missing_blob_files.entry(blob.file_id).or_default() += 1;
+            }
+        }
+
+        let Some(comparator) = comparator else {
+            continue;
+        };
+
+        if let Some(last_key) = &last_key
+            && comparator.compare(last_key, &chunk_desc.min_key).is_ge()
+        {
+            problems.push(format!(
+                "{name}: chunk {} starts at {:?}, not after the previous chunk, which ends at {last_key:?}",
+                chunk_desc.index, chunk_desc.min_key
+            ));
+        }
+
+        for (key, _) in chunk.iter() {
+            if let Some(last_key) = &last_key
+                && comparator.compare(last_key, key).is_ge()
+            {
+                problems.push(format!("{name}: key {key:?} is not after the key {last_key:?} before it"));
+            }
+
+            if comparator.compare(key, &chunk_desc.min_key).is_lt()
+                || comparator.compare(key, &chunk_desc.max_key).is_gt()
+            {
+                problems.push(format!(
+                    "{name}: key {key:?} is outside the range {:?} to {:?} of chunk {}",
+                    chunk_desc.min_key, chunk_desc.max_key, chunk_desc.index
+                ));
+            }
+
+            if comparator.compare(key, &desc.min_key).is_lt()
+                || comparator.compare(key, &desc.max_key).is_gt()
+            {
+                problems.push(format!(
+                    "{name}: key {key:?} is outside the range {:?} to {:?} recorded in the manifest",
+                    desc.min_key, desc.max_key
+                ));
+            }
+
+            last_key = Some(key.clone());
+        }
+    }
+
+    for (file_id, count) in missing_blob_files {
+        problems.push(format!("{name}: {count} values refer to blob file {file_id}, which is not live"));
+    }
+
+    // Properties recorded by older manifest versions are all 0.
+    if desc.props.entry_count != 0 && desc.props.entry_count != entry_count {
+        problems.push(format!(
+            "{name}: has {entry_count} entries, the manifest records {}",
+            desc.props.entry_count
+        ));
+    }
+
+    problems
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|it| it.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::sstable::writer::SSTableWriter;
+    use crate::store::Store;
+    use crate::store_impl::make_store;
+
+    #[test]
+    fn test_verify_reports_corrupted_sstables_and_orphans() {
+        let dir = PathBuf::from("test_verify_reports_corrupted_sstables_and_orphans");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let store = make_store(dir.clone()).unwrap();
+        for i in 0..2000 {
+            store.insert(&format!("key{i:05}"), b"value").unwrap();
+        }
+        store.flush().unwrap();
+        store.insert("unflushed", b"value").unwrap();
+
+        let report = verify(&dir).unwrap();
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(report.sstable_count, 1);
+        assert_eq!(report.wal_record_count, 1);
+        assert!(report.orphans.is_empty());
+
+        drop(store);
+
+        let sstables: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|it| parse_sst_filename(it.unwrap().file_name().to_str()?))
+            .collect();
+
+        fs::copy(sst_file_path(&dir, sstables[0]), sst_file_path(&dir, 1000)).unwrap();
+
+        // Keys out of order, and outside the range recorded in the manifest.
+        let mut writer = SSTableWriter::create(&sst_file_path(&dir, sstables[0])).unwrap();
+        writer.write("key00002", &Entry::new(b"value".to_vec())).unwrap();
+        writer.write("key00001", &Entry::new(b"value".to_vec())).unwrap();
+        writer.write("other", &Entry::new(b"value".to_vec())).unwrap();
+        writer.finalize().unwrap();
+
+        let report = verify(&dir).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.orphans, vec![file_name(&sst_file_path(&dir, 1000))]);
+        assert!(report.problems.iter().any(|it| it.contains("is not after")), "{report}");
+        assert!(report.problems.iter().any(|it| it.contains("recorded in the manifest")), "{report}");
+        assert!(report.problems.iter().any(|it| it.contains("has 3 entries")), "{report}");
+
+        fs::remove_file(sst_file_path(&dir, sstables[0])).unwrap();
+
+        let report = verify(&dir).unwrap();
+        assert_eq!(report.problems.len(), 1, "{report}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
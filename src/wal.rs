@@ -1,13 +1,15 @@
-use std::collections::BTreeMap;
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
+use std::io::BufReader;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::iter::Iterator;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -18,24 +20,59 @@ use std::time::UNIX_EPOCH;
 use portable_atomic::AtomicU128;
 
 use crate::crc;
+use crate::entry::Entry;
 use crate::io_ext::ReadExt;
 use crate::io_ext::WriteExt;
+use crate::manifest::DEFAULT_COLUMN_FAMILY;
 
 const MAGIC: u32 = 0xbeef_dab3;
-const VERSION: u8 = 1;
+const VERSION: u8 = 5;
 
-const FILENAME: &'static str = "wal.log";
+pub(crate) const FILENAME: &str = "wal.log";
+const ARCHIVE_EXTENSION: &str = "log";
+
+/// Entries that were logged together, and are restored all or none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalRecord {
+    /// Position of the record among all records ever logged to the WAL, starting at 1.
+    pub sequence: u64,
+
+    /// Milliseconds since the UNIX epoch at which the record was logged. 0 for records of WAL
+    /// versions before 5.
+    pub timestamp: u64,
+
+    pub entries: Vec<(u32, String, Entry)>,
+}
 
 pub struct Wal {
     wal: File,
     fsync_thread_join_handle: Option<thread::JoinHandle<()>>,
     last_update: Arc<AtomicU128>,
     stop_fsync: Arc<AtomicBool>,
+
+    // Format version of the records in the file. Records of version 1 carry no expiry time,
+    // records of version 2 are always values and records before version 4 hold a single entry of
+    // the default column family. Records before version 5 carry no sequence number or timestamp.
+    version: u8,
+
+    // Sequence number of the last record logged, and of the last one logged before the WAL was
+    // last truncated, which is kept in the header.
+    last_sequence: u64,
+    truncated_sequence: u64,
+
+    // Directory the records are copied to before the WAL is truncated, if any.
+    archive: Option<PathBuf>,
 }
 
 impl Wal {
 
-    pub fn new(directory: &Path) -> io::Result<Self> {
+    /// Opens the WAL of the store in `directory`. If `archive` is given, records are copied to a
+    /// file in it whenever the WAL is truncated, instead of being discarded.
+    pub fn new(directory: &Path, archive: Option<&Path>) -> io::Result<Self> {
+        if let Some(archive) = archive {
+            fs::create_dir_all(archive)?;
+        }
+
         let wal = OpenOptions::new()
             .read(true)
             .write(true)
@@ -72,132 +109,389 @@ impl Wal {
             fsync_thread_join_handle,
             last_update,
             stop_fsync,
+            version: VERSION,
+            last_sequence: 0,
+            truncated_sequence: 0,
+            archive: archive.map(Path::to_path_buf),
         })
     }
 
-    pub fn log_one_no_fsync(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
-        let mut buf = Vec::new();
-        buf.write_string(key)?;
-        buf.write_bytes(value)?;
-
-        let len = buf.len() as u64;
-
-        let crc = crc::crc32c_iter(
-            len.to_be_bytes()
-                .iter()
-                .chain(buf.iter())
-                .cloned()
-        );
+    /// Sequence number of the last record logged, 0 if none ever was. Only known once the WAL
+    /// is restored or truncated.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
 
-        self.wal.write_u32(crc)?;
-        self.wal.write_u64(buf.len() as u64)?;
-        self.wal.write_all(&buf)?;
+    /// Sets the sequence number of the last record, which the next one logged follows. Meant for
+    /// a WAL that is about to be truncated.
+    pub fn set_last_sequence(&mut self, sequence: u64) {
+        self.last_sequence = sequence;
+    }
 
-        Ok(())
+    pub fn log_one(&mut self, family: u32, key: &str, entry: &Entry) -> io::Result<()> {
+        self.log_many([(family, key, entry)])
     }
 
-    pub fn log_one(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
-        self.log_one_no_fsync(key, value)?;
-        self.last_update.store(now(), Ordering::Relaxed);
-        Ok(())
+    /// Logs entries as a single record, so they are either all restored or none of them are.
+    pub fn log_many<'a, I>(&mut self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (u32, &'a str, &'a Entry)>,
+    {
+        self.write_record(self.last_sequence + 1, now() as u64, entries)
     }
 
-    pub fn log_many(&mut self, items: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
-        for (key, value) in items.iter() {
-            self.log_one_no_fsync(key, value)?;
+    /// Logs a record taken from another WAL, such as an archived one, keeping its sequence number
+    /// and timestamp. It must come after every record logged so far.
+    pub fn log_record(&mut self, record: &WalRecord) -> io::Result<()> {
+        if record.sequence <= self.last_sequence {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "WAL record {} doesn't follow the last logged record {}",
+                    record.sequence, self.last_sequence
+                ),
+            ));
         }
 
+        self.write_record(
+            record.sequence,
+            record.timestamp,
+            record.entries.iter().map(|(family, key, entry)| (*family, key.as_str(), entry)),
+        )
+    }
+
+    fn write_record<'a, I>(&mut self, sequence: u64, timestamp: u64, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (u32, &'a str, &'a Entry)>,
+    {
+        self.wal.write_all(&encode_record(sequence, timestamp, entries)?)?;
+
+        self.last_sequence = sequence;
         self.last_update.store(now(), Ordering::Relaxed);
 
         Ok(())
     }
 
-    pub fn restore<'a>(&'a mut self) -> io::Result<impl Iterator<Item = (String, Vec<u8>)> + 'a> {
+    /// Returns the logged entries along with the ID of the column family they were written to.
+    #[cfg(test)]
+    pub fn restore<'a>(
+        &'a mut self,
+    ) -> io::Result<impl Iterator<Item = (u32, String, Entry)> + 'a> {
+        Ok(self.records()?.flat_map(|it| it.entries))
+    }
+
+    /// Returns the logged records, oldest first.
+    pub fn records<'a>(&'a mut self) -> io::Result<impl Iterator<Item = WalRecord> + 'a> {
         self.wal.seek(SeekFrom::Start(0))?;
 
         if self.wal.metadata()?.len() > 0 {
-            self.parse_header()?;
+            (self.version, self.truncated_sequence) = parse_header(&mut self.wal)?;
+            self.last_sequence = self.truncated_sequence;
         }
 
         Ok(std::iter::from_fn(|| {
-            self.read_one().ok().flatten()
+            let record = read_record(&mut self.wal, self.version, self.last_sequence)
+                .ok()
+                .flatten()?;
+
+            self.last_sequence = record.sequence;
+            Some(record)
         }))
     }
 
+    /// Discards the logged records, copying them to the archive first if the WAL has one.
     pub fn truncate(&mut self) -> io::Result<()> {
+        if self.last_sequence > self.truncated_sequence
+            && let Some(archive) = &self.archive
+        {
+            let path = archive_file_path(archive, self.truncated_sequence + 1);
+            let tmp_path = path.with_extension("tmp");
+
+            let mut file = File::create(&tmp_path)?;
+            self.wal.seek(SeekFrom::Start(0))?;
+            io::copy(&mut self.wal, &mut file)?;
+            file.sync_all()?;
+            drop(file);
+
+            fs::rename(&tmp_path, &path)?;
+        }
+
         self.wal.seek(SeekFrom::Start(0))?;
         self.wal.set_len(0)?;
         self.write_header()?;
+        self.version = VERSION;
+        self.truncated_sequence = self.last_sequence;
 
         self.wal.sync_all()?;
 
         Ok(())
     }
 
-    fn read_one(&mut self) -> io::Result<Option<(String, Vec<u8>)>> {
-        let crc = match self.wal.read_u32() {
-            Ok(crc) => crc,
+    /// Flushes logged entries to disk without waiting for the background sync.
+    pub fn sync(&self) -> io::Result<()> {
+        self.wal.sync_all()
+    }
 
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
-                return Ok(None);
-            },
+    fn write_header(&mut self) -> io::Result<()> {
+        self.wal.write_u32(MAGIC)?;
+        self.wal.write_u8(VERSION)?;
+        self.wal.write_u64(self.last_sequence)?;
 
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        Ok(())
+    }
 
-        let len = if let Ok(len) = self.wal.read_u64() {
-            len as usize
-        } else {
-            return Ok(None);
-        };
+}
 
-        let mut buf = vec![0u8; len];
-        self.wal.read_exact(&mut buf)?;
+/// Returns the records of the archive files in `directory`, or of any other WAL file copied into
+/// it, ordered by sequence number. Records found in more than one file are returned once.
+pub fn read_archive(directory: &Path) -> io::Result<Vec<WalRecord>> {
+    let mut records = Vec::new();
 
-        let expected_crc = crc::crc32c_iter(
-            len.to_be_bytes()
-                .iter()
-                .chain(buf.iter())
-                .cloned()
-        );
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
 
-        if crc != expected_crc {
-            // Treat CRC failure as EOF
-            return Ok(None);
+        if path.extension().is_some_and(|it| it == ARCHIVE_EXTENSION) {
+            records.extend(read_wal_file(&path)?);
         }
+    }
 
-        let mut cursor = io::Cursor::new(&buf);
+    records.sort_by_key(|it| it.sequence);
+    records.dedup_by_key(|it| it.sequence);
 
-        let key = cursor.read_string()?;
-        let value = cursor.read_bytes()?;
+    Ok(records)
+}
+
+/// Removes the archive files in `directory`, or other WAL files copied into it, that only hold
+/// records up to and including `sequence`. Returns the number of files removed.
+pub(crate) fn purge_archive(directory: &Path, sequence: u64) -> io::Result<usize> {
+    let mut removed = 0;
 
-        Ok(Some((key, value)))
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if path.extension().is_none_or(|it| it != ARCHIVE_EXTENSION) {
+            continue;
+        }
+
+        if read_wal_file(&path)?.iter().all(|it| it.sequence <= sequence) {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
     }
 
-    fn write_header(&mut self) -> io::Result<()> {
-        self.wal.write_u32(MAGIC)?;
-        self.wal.write_u8(VERSION)?;
+    Ok(removed)
+}
 
-        Ok(())
+pub(crate) fn read_wal_file(path: &Path) -> io::Result<Vec<WalRecord>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+
+    if file.get_ref().metadata()?.len() == 0 {
+        return Ok(records);
+    }
+
+    let (version, mut last_sequence) = parse_header(&mut file)?;
+
+    while let Some(record) = read_record(&mut file, version, last_sequence)? {
+        last_sequence = record.sequence;
+        records.push(record);
     }
 
-    fn parse_header(&mut self) -> io::Result<()> {
-        let magic = self.wal.read_u32()?;
-        let version = self.wal.read_u8()?;
+    Ok(records)
+}
 
-        if magic != MAGIC {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid WAL header."));
+/// Reads the records of the WAL file at `path` up to the first torn or corrupted one, which a
+/// file another process is appending to may end with. Returns them along with the sequence
+/// number of the last record logged before the file's first. A file with an unreadable header
+/// has none.
+pub(crate) fn read_valid_records(path: &Path) -> io::Result<(u64, Vec<WalRecord>)> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    let mut truncated_sequence = 0;
+
+    if file.get_ref().metadata()?.len() > 0
+        && let Ok((version, last_sequence)) = parse_header(&mut file)
+    {
+        truncated_sequence = last_sequence;
+
+        let mut last_sequence = last_sequence;
+        while let Ok(Some(record)) = read_record(&mut file, version, last_sequence) {
+            last_sequence = record.sequence;
+            records.push(record);
         }
+    }
+
+    Ok((truncated_sequence, records))
+}
+
+/// Rewrites the WAL file at `path` with the records that can still be read from it, dropping the
+/// first torn or corrupted record and everything after it. A file with an unreadable header keeps
+/// none. Returns the number of records kept.
+pub(crate) fn salvage_wal_file(path: &Path) -> io::Result<usize> {
+    let (truncated_sequence, records) = read_valid_records(path)?;
+
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path)?;
+
+    tmp.write_u32(MAGIC)?;
+    tmp.write_u8(VERSION)?;
+    tmp.write_u64(truncated_sequence)?;
+
+    for record in records.iter() {
+        let entries = record.entries.iter().map(|(family, key, entry)| (*family, key.as_str(), entry));
+        tmp.write_all(&encode_record(record.sequence, record.timestamp, entries)?)?;
+    }
+
+    tmp.sync_all()?;
+    drop(tmp);
+
+    fs::rename(&tmp_path, path)?;
+
+    Ok(records.len())
+}
+
+/// Encodes a record along with the CRC and the length that precede it in the file.
+fn encode_record<'a, I>(sequence: u64, timestamp: u64, entries: I) -> io::Result<Vec<u8>>
+where
+    I: IntoIterator<Item = (u32, &'a str, &'a Entry)>,
+{
+    let mut count = 0u64;
+    let mut body = Vec::new();
+
+    for (family, key, entry) in entries {
+        body.write_u32(family)?;
+        body.write_string(key)?;
+        entry.encode(&mut body)?;
+        count += 1;
+    }
+
+    let mut buf = Vec::with_capacity(24 + body.len());
+    buf.write_u64(sequence)?;
+    buf.write_u64(timestamp)?;
+    buf.write_u64(count)?;
+    buf.extend_from_slice(&body);
+
+    let len = buf.len() as u64;
+
+    let crc = crc::crc32c_iter(
+        len.to_be_bytes()
+            .iter()
+            .chain(buf.iter())
+            .cloned()
+    );
+
+    let mut record = Vec::with_capacity(12 + buf.len());
+    record.write_u32(crc)?;
+    record.write_u64(len)?;
+    record.extend_from_slice(&buf);
+
+    Ok(record)
+}
+
+fn archive_file_path(directory: &Path, first_sequence: u64) -> PathBuf {
+    directory.join(format!("{first_sequence:016}.{ARCHIVE_EXTENSION}"))
+}
+
+/// Reads the header, returning the format version and the sequence number of the last record
+/// logged before the file's first one.
+pub(crate) fn parse_header<R: Read>(reader: &mut R) -> io::Result<(u8, u64)> {
+    let magic = reader.read_u32()?;
+    let version = reader.read_u8()?;
+
+    if magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid WAL header."));
+    }
+
+    if !(1..=VERSION).contains(&version) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported WAL version."));
+    }
+
+    let last_sequence = match version {
+        5.. => reader.read_u64()?,
+        _ => 0,
+    };
+
+    Ok((version, last_sequence))
+}
 
-        if version != VERSION {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported WAL version."));
+/// Reads the record following the one with sequence number `last_sequence`, which records of
+/// versions before 5 take theirs from.
+pub(crate) fn read_record<R: Read>(
+    reader: &mut R,
+    version: u8,
+    last_sequence: u64,
+) -> io::Result<Option<WalRecord>> {
+    let crc = match reader.read_u32() {
+        Ok(crc) => crc,
+
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None);
+        },
+
+        Err(e) => {
+            return Err(e);
         }
+    };
+
+    let len = if let Ok(len) = reader.read_u64() {
+        len as usize
+    } else {
+        return Ok(None);
+    };
+
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+
+    let expected_crc = crc::crc32c_iter(
+        len.to_be_bytes()
+            .iter()
+            .chain(buf.iter())
+            .cloned()
+    );
+
+    if crc != expected_crc {
+        // Treat CRC failure as EOF
+        return Ok(None);
+    }
 
-        Ok(())
+    let mut cursor = io::Cursor::new(&buf);
+
+    if version < 4 {
+        let key = cursor.read_string()?;
+        let entry = match version {
+            1 => Entry::new(cursor.read_bytes()?),
+            2 => Entry::decode_legacy_value(&mut cursor)?,
+            _ => Entry::decode(&mut cursor)?,
+        };
+
+        return Ok(Some(WalRecord {
+            sequence: last_sequence + 1,
+            timestamp: 0,
+            entries: vec![(DEFAULT_COLUMN_FAMILY, key, entry)],
+        }));
+    }
+
+    let (sequence, timestamp) = match version {
+        5.. => (cursor.read_u64()?, cursor.read_u64()?),
+        _ => (last_sequence + 1, 0),
+    };
+
+    let count = cursor.read_u64()?;
+    let mut entries = Vec::new();
+
+    for _ in 0..count {
+        let family = cursor.read_u32()?;
+        let key = cursor.read_string()?;
+        let entry = Entry::decode(&mut cursor)?;
+
+        entries.push((family, key, entry));
     }
 
+    Ok(Some(WalRecord {
+        sequence,
+        timestamp,
+        entries,
+    }))
 }
 
 fn run_fsync(file: File, stop: Arc<AtomicBool>, last_update: Arc<AtomicU128>) {
@@ -235,3 +529,45 @@ fn now() -> u128 {
         .as_millis()
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_restore_returns_logged_entries() {
+        let dir = PathBuf::from("test_restore_returns_logged_entries");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let expiring = Entry::Value {
+            value: b"value1".to_vec(),
+            expires_at: Some(1234),
+        };
+
+        let mut wal = Wal::new(&dir, None).unwrap();
+        wal.truncate().unwrap();
+        wal.log_one(0, "key1", &expiring).unwrap();
+        wal.log_many([
+            (0, "key2", &Entry::new(b"value2".to_vec())),
+            (1, "key3", &Entry::Merge(vec![b"a".to_vec(), b"b".to_vec()])),
+        ])
+        .unwrap();
+        drop(wal);
+
+        let mut wal = Wal::new(&dir, None).unwrap();
+        let restored: Vec<_> = wal.restore().unwrap().collect();
+
+        assert_eq!(
+            restored,
+            vec![
+                (0, "key1".to_owned(), expiring),
+                (0, "key2".to_owned(), Entry::new(b"value2".to_vec())),
+                (1, "key3".to_owned(), Entry::Merge(vec![b"a".to_vec(), b"b".to_vec()])),
+            ]
+        );
+    }
+}
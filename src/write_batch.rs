@@ -0,0 +1,62 @@
+//! Writes to one or more column families that are applied atomically.
+
+use crate::store_impl::DEFAULT_COLUMN_FAMILY_NAME;
+
+pub(crate) enum BatchOp {
+    Insert(Vec<u8>),
+    Merge(Vec<u8>),
+    Delete,
+}
+
+/// A set of writes applied together through [`crate::DefaultStore::write`].
+///
+/// The writes may span column families. They are logged to the WAL as a single record, so after
+/// a crash either all of them are restored or none are.
+#[derive(Default)]
+pub struct WriteBatch {
+    // Column family name, key and the write, in the order they were added.
+    pub(crate) ops: Vec<(String, String, BatchOp)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: &str, value: &[u8]) {
+        self.insert_cf(DEFAULT_COLUMN_FAMILY_NAME, key, value);
+    }
+
+    pub fn insert_cf(&mut self, family: &str, key: &str, value: &[u8]) {
+        self.push(family, key, BatchOp::Insert(value.to_owned()));
+    }
+
+    /// Records a merge operand, see [`crate::Store::merge`].
+    pub fn merge(&mut self, key: &str, operand: &[u8]) {
+        self.merge_cf(DEFAULT_COLUMN_FAMILY_NAME, key, operand);
+    }
+
+    pub fn merge_cf(&mut self, family: &str, key: &str, operand: &[u8]) {
+        self.push(family, key, BatchOp::Merge(operand.to_owned()));
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        self.delete_cf(DEFAULT_COLUMN_FAMILY_NAME, key);
+    }
+
+    pub fn delete_cf(&mut self, family: &str, key: &str) {
+        self.push(family, key, BatchOp::Delete);
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn push(&mut self, family: &str, key: &str, op: BatchOp) {
+        self.ops.push((family.to_owned(), key.to_owned(), op));
+    }
+}